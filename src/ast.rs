@@ -1,4 +1,5 @@
 use crate::token::Position;
+use jazzlight::bigint::BigInt;
 #[derive(Clone, Debug, PartialEq)]
 pub enum Constant {
     True,
@@ -6,12 +7,37 @@ pub enum Constant {
     Null,
     This,
     Int(i64),
+    /// Decimal form of an integer literal that doesn't fit in an `i64`.
+    BigInt(String),
     Float(f64),
     Str(String),
     Builtin(String),
     Ident(String),
 }
 
+impl Constant {
+    /// Integer constant for `x`, big when it doesn't fit in an `i64`.
+    pub fn from_bigint(x: BigInt) -> Constant {
+        match x.to_i64() {
+            Some(x) => Constant::Int(x),
+            None => Constant::BigInt(x.to_string()),
+        }
+    }
+
+    /// `-self` for numbers.
+    pub fn negate(&self) -> Option<Constant> {
+        Some(match self {
+            Constant::Int(x) => match x.checked_neg() {
+                Some(x) => Constant::Int(x),
+                None => Constant::from_bigint(BigInt::from_i64(*x).neg()),
+            },
+            Constant::BigInt(x) => Constant::from_bigint(BigInt::parse(x, 10)?.neg()),
+            Constant::Float(x) => Constant::Float(-x),
+            _ => return None,
+        })
+    }
+}
+
 use crate::P;
 
 #[derive(Clone, Debug, PartialEq)]
@@ -25,9 +51,115 @@ pub enum WhileFlag {
     NormalWhile,
     DoWhile,
 }
+/// Parameter list of a function.
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct Params {
+    /// Positional parameters and their default values, only the trailing
+    /// parameters may have one.
+    pub names: Vec<(String, Option<P<Expr>>)>,
+    /// `...rest`, an array of the arguments after the positional ones.
+    pub rest: Option<String>,
+    /// Whether the body calls `$args()`.
+    pub uses_args: bool,
+}
+
+impl Params {
+    /// Parameters without default value or rest parameter.
+    pub fn new(names: Vec<String>) -> Params {
+        Params {
+            names: names.into_iter().map(|name| (name, None)).collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Number of arguments a call must pass.
+    pub fn required(&self) -> usize {
+        self.names
+            .iter()
+            .take_while(|(_, default)| default.is_none())
+            .count()
+    }
+
+    /// Whether the function takes a varying number of arguments or needs the
+    /// argument array.
+    pub fn variadic(&self) -> bool {
+        self.rest.is_some() || self.uses_args || self.required() < self.names.len()
+    }
+}
+
+/// Left side of a destructuring declaration or assignment.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Destructure {
+    /// A variable in declarations, any assignable expression in assignments.
+    Target(P<Expr>),
+    /// `[a, b = default]`, elements past the end of the array are null.
+    Array(Vec<(Destructure, Option<P<Expr>>)>),
+    /// `{ key, key: target = default }`, a key without target assigns the
+    /// field to the variable of the same name. Missing fields are null.
+    Object(Vec<(String, Destructure, Option<P<Expr>>)>),
+}
+
+/// Pattern of a `match` arm.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Pattern {
+    /// `_`, matches anything.
+    Wildcard,
+    /// A name, matches anything and binds it.
+    Bind(String),
+    /// A literal, matches values equal to it.
+    Literal(Constant),
+    /// `[p, q, ...rest]`, matches arrays with one element per pattern, or at
+    /// least that many when there's a rest binding.
+    Array(Vec<Pattern>, Option<String>),
+    /// `{ key: pattern, key }`, matches objects that have every key. A key
+    /// without pattern binds the field to a variable of the same name.
+    Object(Vec<(String, Pattern)>),
+    /// `int(p)`, matches values whose `$typeof` is the name and `p`.
+    Type(String, Box<Pattern>),
+    /// `p | q`
+    Or(Vec<Pattern>),
+}
+
+impl Pattern {
+    /// Names bound by the pattern, in order of first appearance.
+    pub fn bindings(&self, names: &mut Vec<String>) {
+        match self {
+            Pattern::Bind(name) => {
+                if !names.contains(name) {
+                    names.push(name.clone());
+                }
+            }
+            Pattern::Array(elements, rest) => {
+                for element in elements.iter() {
+                    element.bindings(names);
+                }
+                if let Some(rest) = rest {
+                    Pattern::Bind(rest.clone()).bindings(names);
+                }
+            }
+            Pattern::Object(fields) => {
+                for (_, field) in fields.iter() {
+                    field.bindings(names);
+                }
+            }
+            Pattern::Type(_, pattern) => pattern.bindings(names),
+            Pattern::Or(alternatives) => {
+                for alternative in alternatives.iter() {
+                    alternative.bindings(names);
+                }
+            }
+            Pattern::Wildcard | Pattern::Literal(_) => (),
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum ExprDecl {
     Assign(P<Expr>, P<Expr>),
+    /// `e1 op= e2`, holds the binary operator.
+    AssignOp(String, P<Expr>, P<Expr>),
+    /// `++e`, `--e` when the flag is set, otherwise `e++`, `e--`. Holds "+" or "-".
+    Incr(String, bool, P<Expr>),
     Const(Constant),
     Block(Vec<P<Expr>>),
     Paren(P<Expr>),
@@ -35,28 +167,39 @@ pub enum ExprDecl {
     Call(P<Expr>, Vec<P<Expr>>),
     Array(P<Expr>, P<Expr>),
 
-    Vars(Vec<(String, Option<P<Expr>>)>),
+    /// `[e, ...]`
+    ArrayLiteral(Vec<P<Expr>>),
+    /// `var [a, b] = e` or `let { x, y } = e` when the flag is set, holding
+    /// whether the variables are reassignable, otherwise `[a, b] = e`.
+    Destructuring(Option<bool>, Destructure, P<Expr>),
     For(P<Expr>, P<Expr>, P<Expr>, P<Expr>),
     ForIn(String, P<Expr>, P<Expr>),
     While(P<Expr>, P<Expr>),
     If(P<Expr>, P<Expr>, Option<P<Expr>>),
     Try(P<Expr>, String, P<Expr>),
-    Function(Vec<String>, P<Expr>),
+    Function(Params, P<Expr>),
+    /// `...e` in a call's arguments.
+    Spread(P<Expr>),
     Binop(String, P<Expr>, P<Expr>),
     Return(Option<P<Expr>>),
     Break(Option<P<Expr>>),
     Var(bool, String, Option<P<Expr>>),
+    /// `const name = value`, removed by the resolver.
+    ConstDecl(String, P<Expr>),
     Continue,
     Next(P<Expr>, P<Expr>),
     Object(Vec<(String, P<Expr>)>),
     Label(String),
-    Switch(P<Expr>, Vec<(P<Expr>, P<Expr>)>, Option<P<Expr>>),
+    /// `match value { pattern if guard -> body ... }`, arms are tried in order.
+    Match(P<Expr>, Vec<(Pattern, Option<P<Expr>>, P<Expr>)>),
     Unop(String, P<Expr>),
     Throw(P<Expr>),
     Include(String),
     Yield(P<Expr>),
     Jazz(String),
     Goto(String),
+    /// `type Name : parent { method(params) body ... }`
+    Type(String, Option<P<Expr>>, Vec<(String, P<Expr>)>),
 }
 
 pub fn make_call(v: P<Expr>, args: Vec<P<Expr>>, pos: Position) -> Expr {
@@ -124,6 +267,12 @@ impl Expr {
                 f(e1);
                 f(e2);
             }
+            ExprDecl::For(e1, e2, e3, e4) => {
+                f(e1);
+                f(e2);
+                f(e3);
+                f(e4);
+            }
             ExprDecl::If(e1, e2, e3) => {
                 f(e1);
                 f(e2);
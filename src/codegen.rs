@@ -21,6 +21,8 @@ pub enum Global {
     Func(i32, i32),
     Str(String),
     Float(u64),
+    /// Decimal digits of a big integer.
+    BigInt(String),
 }
 #[derive(Clone, Debug, PartialEq)]
 pub enum Access {
@@ -28,7 +30,8 @@ pub enum Access {
     Stack(i32),
     Global(i32),
     Field(P<Expr>, String),
-    Index(i32),
+    /// Element of the object or array in the first local at the key in the second.
+    Index(i32, i32),
     Array(P<Expr>, P<Expr>),
     This,
 }
@@ -51,12 +54,19 @@ pub struct Context {
     pub ops: Vec<UOP>,
     pub pos: Vec<(i32, i32)>,
     pub locals: LinkedHashMap<String, i32>,
+    /// Number of local slots in use, slots of a block's locals are reused
+    /// after the block ends.
+    pub slots: i32,
     pub env: LinkedHashMap<String, i32>,
     pub stack: i32,
     pub limit: i32,
     pub nenv: i32,
     pub breaks: Vec<String>,
     pub continues: Vec<String>,
+    /// Number of `try` bodies being compiled in the current function.
+    pub tries: usize,
+    /// Value of `tries` at the start of each enclosing loop.
+    pub loop_tries: Vec<usize>,
     pub cur_pos: Option<Position>,
     pub labels: LinkedHashMap<String, Option<usize>>,
     pub used_upvars: LinkedHashMap<String, i32>,
@@ -148,6 +158,10 @@ impl Context {
                 let pos = self.global(&Global::Float(f.to_bits()));
                 self.write(Op::LoadGlobal(pos as _));
             }
+            Constant::BigInt(x) => {
+                let pos = self.global(&Global::BigInt(x.to_owned()));
+                self.write(Op::LoadGlobal(pos as _));
+            }
             Constant::Str(s) => {
                 let pos = self.global(&Global::Str(s.to_owned()));
                 self.write(Op::LoadGlobal(pos as _));
@@ -158,16 +172,8 @@ impl Context {
                     let i = *self.locals.get(s).unwrap();
                     self.write(Op::LoadLocal(i as u16));
                 } else if self.env.contains_key(s) {
-                    self.nenv += 1;
-                    let pos = if !self.used_upvars.contains_key(s) {
-                        let pos = self.used_upvars.len();
-
-                        self.used_upvars.insert(s.to_owned(), pos as _);
-                        pos as u16
-                    } else {
-                        *self.used_upvars.get(s).unwrap() as u16
-                    };
-                    self.write(Op::LoadEnv(pos));
+                    let pos = self.upvar(s);
+                    self.write(Op::LoadEnv(pos as u16));
                 } else {
                     let g = self.global(&Global::Var(s.to_owned()));
                     self.write(Op::LoadGlobal(g as u32));
@@ -182,8 +188,6 @@ impl Context {
     pub fn write_op(&mut self, op: &str) {
         use Op::*;
         match op {
-            "&&" => self.write(And),
-            "||" => self.write(Or),
             "+" => self.write(Add),
             "-" => self.write(Sub),
             "/" => self.write(Div),
@@ -191,11 +195,14 @@ impl Context {
             "%" => self.write(Mod),
             "<<" => self.write(Shl),
             ">>" => self.write(Shr),
+            ">>>" => self.write(UShr),
             "|" => self.write(Or),
             "&" => self.write(And),
             "^" => self.write(Xor),
             "==" => self.write(Eq),
             "!=" => self.write(Neq),
+            "===" => self.write(Same),
+            "!==" => self.write(NotSame),
             ">" => self.write(Gt),
             ">=" => self.write(Gte),
             "<" => self.write(Lt),
@@ -205,6 +212,93 @@ impl Context {
         }
     }
 
+    /// Slot of the captured variable `name` in the environment of the function
+    /// being compiled.
+    fn upvar(&mut self, name: &str) -> i32 {
+        self.nenv += 1;
+        match self.used_upvars.get(name) {
+            Some(pos) => *pos,
+            None => {
+                let pos = self.used_upvars.len() as i32;
+                self.used_upvars.insert(name.to_owned(), pos);
+                pos
+            }
+        }
+    }
+
+    /// Allocate a slot for a new local `name`, shadowing any local or upvalue
+    /// with the same name until the end of the enclosing block.
+    fn new_local(&mut self, name: &str) -> i32 {
+        let id = self.slots;
+        self.slots += 1;
+        self.locals.insert(name.to_owned(), id);
+        id
+    }
+
+    /// Allocate a local that source code can't refer to.
+    fn temp_local(&mut self) -> i32 {
+        let name = format!("#tmp{}", self.slots);
+        self.new_local(&name)
+    }
+
+    /// Like `compile_access`, but evaluates the object and key of a field or
+    /// array access into temporary locals, so that the target can be read and
+    /// then written without evaluating its subexpressions twice.
+    pub fn compile_access_once(&mut self, e: &P<Expr>) -> Access {
+        match self.compile_access(e) {
+            Access::Field(e, f) => {
+                self.compile(&e, false);
+                let object = self.temp_local();
+                self.write(Op::StoreLocal(object as _));
+                let gid = self.global(&Global::Str(f));
+                self.write(Op::LoadGlobal(gid as _));
+                let key = self.temp_local();
+                self.write(Op::StoreLocal(key as _));
+                Access::Index(object, key)
+            }
+            Access::Array(ea, ei) => {
+                self.compile(&ea, false);
+                let object = self.temp_local();
+                self.write(Op::StoreLocal(object as _));
+                self.compile(&ei, false);
+                let key = self.temp_local();
+                self.write(Op::StoreLocal(key as _));
+                Access::Index(object, key)
+            }
+            acc => acc,
+        }
+    }
+
+    /// Compile `e1 op= e2`, leaving the new value on the stack if `value` is set.
+    fn compile_assign_op(&mut self, op: &str, e1: &P<Expr>, e2: &P<Expr>, value: bool) {
+        let acc = self.compile_access_once(e1);
+        self.compile(e2, false);
+        self.access_get(acc.clone());
+        self.write_op(op);
+        if value {
+            self.write(Op::Dup);
+        }
+        self.access_set(acc);
+    }
+
+    /// Compile `++e`, `--e`, `e++` or `e--`. Postfix forms leave the old value
+    /// on the stack when `value` is set.
+    fn compile_incr(&mut self, op: &str, prefix: bool, e: &P<Expr>, value: bool) {
+        let one = P(make_int(1, e.pos.clone()));
+        if prefix || !value {
+            return self.compile_assign_op(op, e, &one, value);
+        }
+        let acc = self.compile_access_once(e);
+        self.access_get(acc.clone());
+        let old = self.temp_local();
+        self.write(Op::StoreLocal(old as _));
+        self.write(Op::LoadInt(1));
+        self.write(Op::LoadLocal(old as _));
+        self.write_op(op);
+        self.access_set(acc);
+        self.write(Op::LoadLocal(old as _));
+    }
+
     pub fn compile_access(&mut self, e: &P<Expr>) -> Access {
         match &e.decl {
             ExprDecl::Const(Constant::Ident(name)) => {
@@ -214,10 +308,7 @@ impl Context {
                     let l = *l.unwrap();
                     return Access::Stack(l);
                 } else if self.env.contains_key(s) {
-                    let l = self.env.get(s);
-                    self.used_upvars.insert(s.to_owned(), *l.unwrap());
-                    self.nenv += 1;
-                    return Access::Env(*l.unwrap());
+                    return Access::Env(self.upvar(s));
                 } else {
                     let g = self.global(&Global::Var(name.to_owned()));
                     return Access::Global(g);
@@ -248,7 +339,11 @@ impl Context {
                 self.compile(&e, false);
                 self.write(Op::Load)
             }
-            Access::Index(_) => unimplemented!(),
+            Access::Index(object, key) => {
+                self.write(Op::LoadLocal(key as _));
+                self.write(Op::LoadLocal(object as _));
+                self.write(Op::Load);
+            }
             Access::This => self.write(Op::LoadThis),
             Access::Array(ea, ei) => {
                 self.compile(&ei, false);
@@ -262,18 +357,18 @@ impl Context {
         match acc {
             Access::Env(n) => self.write(Op::StoreEnv(n as _)),
             Access::Stack(l) => self.write(Op::StoreLocal(l as _)),
-            Access::Global(_) =>
-            /*self.write(Op::StoreGlobal(g as u32)),*/
-            {
-                unimplemented!()
-            }
+            Access::Global(g) => self.write(Op::StoreGlobal(g as u32)),
             Access::Field(e, f) => {
                 let gid = self.global(&Global::Str(f.to_owned()));
                 self.write(Op::LoadGlobal(gid as _));
                 self.compile(&e, false);
                 self.write(Op::Store);
             }
-            Access::Index(_) => unimplemented!(),
+            Access::Index(object, key) => {
+                self.write(Op::LoadLocal(key as _));
+                self.write(Op::LoadLocal(object as _));
+                self.write(Op::Store);
+            }
             Access::This => self.write(Op::StoreThis),
             Access::Array(ea, ei) => {
                 self.compile(&ei, false);
@@ -292,6 +387,7 @@ impl Context {
                     self.write(Op::LoadNull);
                 }
                 let br = self.breaks.last().expect("break in wrong context").clone();
+                self.leave_tries();
                 self.emit_goto(&br);
             }
             ExprDecl::Continue => {
@@ -300,25 +396,11 @@ impl Context {
                     .last()
                     .expect("continue in wrong context")
                     .clone();
+                self.leave_tries();
                 self.emit_goto(&c);
             }
             ExprDecl::Const(c) => self.compile_const(c),
-            ExprDecl::Block(v) => {
-                if v.len() == 0 {
-                    self.write(Op::LoadNull);
-                } else {
-                    let locals = self.locals.clone();
-                    //let stack = self.stack;
-                    for el in v.iter() {
-                        self.compile(el, tail);
-                    }
-
-                    /*if stack < self.stack {
-                        self.write(Op::Pop((self.stack - stack) as u32)); // clear stack from values and locals
-                    }*/
-                    self.locals = locals;
-                }
-            }
+            ExprDecl::Block(v) => self.compile_block(v, tail, true),
             ExprDecl::Paren(e) => self.compile(e, tail),
             ExprDecl::Field(e, f) => {
                 /*let mut h = 0xcbf29ce484222325;
@@ -334,26 +416,19 @@ impl Context {
                 self.compile(ea, false);
                 self.write(Op::Load);
             }
-            ExprDecl::Var(_, name, init) => {
-                match init {
-                    Some(e) => match &e.decl {
-                        ExprDecl::Function(args, body) => {
-                            self.compile_function(args, body, Some(name))
-                        }
-                        _ => self.compile(e, false),
-                    },
-                    None => self.write(Op::LoadNull),
+            ExprDecl::ArrayLiteral(elements) => {
+                for e in elements.iter().rev() {
+                    self.compile(e, false);
                 }
-                let id = self.locals.len() as u16;
-                self.locals.insert(name.to_owned(), id as i32);
-
-                self.write(Op::StoreLocal(id));
+                self.write(Op::MakeArray(elements.len() as _));
             }
-
-            ExprDecl::Assign(e1, e2) => {
-                let a = self.compile_access(e1);
-                self.compile(e2, false);
-                self.access_set(a);
+            ExprDecl::Var(..)
+            | ExprDecl::Destructuring(..)
+            | ExprDecl::Assign(..)
+            | ExprDecl::Type(..)
+            | ExprDecl::Label(_) => {
+                self.compile_stmt(e);
+                self.write(Op::LoadNull);
             }
             ExprDecl::Binop(op, e1, e2) => {
                 self.compile_binop(op, e1, e2, tail);
@@ -376,54 +451,70 @@ impl Context {
                 let end = self.new_empty_label();
                 self.breaks.push(end.clone());
                 self.continues.push(start.clone());
+                self.loop_tries.push(self.tries);
+                let exit = self.new_empty_label();
                 self.label_here(&start);
                 self.compile(cond, false);
-                self.emit_gotof(&end);
-                self.compile(body, false);
+                self.emit_gotof(&exit);
+                self.compile_stmt(body);
                 self.emit_goto(&start);
+                // `break` jumps to `end` with its value pushed.
+                self.label_here(&exit);
+                self.write(Op::LoadNull);
                 self.label_here(&end);
                 self.breaks.pop();
                 self.continues.pop();
+                self.loop_tries.pop();
             }
-            ExprDecl::Switch(value, with, default_) => {
-                let orl = self.new_empty_label();
+            // Variables declared by `init` are scoped to the loop. Closures
+            // copy captured values when they are made, so each iteration's
+            // closures see that iteration's loop variables.
+            ExprDecl::For(init, cond, step, body) => {
+                let (locals, slots) = (self.locals.clone(), self.slots);
+                self.compile_stmt(init);
+                let start = self.new_empty_label();
+                let next = self.new_empty_label();
                 let end = self.new_empty_label();
-
-                for (cond, expr) in with.iter() {
-                    let l1 = self.new_empty_label();
-                    self.compile(value, false);
-                    self.compile(cond, false);
-                    self.write(Op::Eq);
-                    self.emit_gotof(&l1);
-                    self.compile(&expr, tail);
-                    self.emit_goto(&end);
-                    self.label_here(&l1);
-                }
-                if default_.is_some() {
-                    self.emit_goto(&orl);
-                }
-                self.label_here(&orl);
-                if default_.is_some() {
-                    self.compile(&default_.clone().unwrap(), false);
-                    self.emit_goto(&end);
-                }
+                let exit = self.new_empty_label();
+                self.breaks.push(end.clone());
+                self.continues.push(next.clone());
+                self.loop_tries.push(self.tries);
+                self.label_here(&start);
+                self.compile(cond, false);
+                self.emit_gotof(&exit);
+                self.compile_stmt(body);
+                self.label_here(&next);
+                self.compile_stmt(step);
+                self.emit_goto(&start);
+                self.label_here(&exit);
+                self.write(Op::LoadNull);
                 self.label_here(&end);
+                self.breaks.pop();
+                self.continues.pop();
+                self.loop_tries.pop();
+                self.locals = locals;
+                self.slots = slots;
             }
+            ExprDecl::Match(value, arms) => self.compile_match(value, arms, tail),
 
             ExprDecl::If(e, e1, e2) => {
                 //let stack = self.stack;
 
                 let lbl_false = self.new_empty_label();
+                let end = self.new_empty_label();
                 self.compile(&e, false);
                 self.emit_gotof(&lbl_false);
                 self.compile(e1, tail);
+                self.emit_goto(&end);
                 self.label_here(&lbl_false);
-                if e2.is_some() {
-                    let e2 = e2.clone().unwrap();
-                    self.compile(&e2, tail);
+                match e2 {
+                    Some(e2) => self.compile(e2, tail),
+                    None => self.write(Op::LoadNull),
                 }
+                self.label_here(&end);
             }
             ExprDecl::Call(e, el) => {
+                let spread = el.iter().any(|arg| matches!(arg.decl, ExprDecl::Spread(_)));
                 match &e.decl {
                     ExprDecl::Const(Constant::Builtin(name)) => {
                         let builtin: &str = name;
@@ -433,9 +524,11 @@ impl Context {
                                 self.write(Op::New);
                                 return;
                             }
-                            "hash" => {
-                                self.compile(&el[0], false);
-                                self.write(Op::Hash);
+                            "args" => {
+                                match self.locals.get("#args") {
+                                    Some(id) => self.write(Op::LoadLocal(*id as _)),
+                                    None => self.write(Op::MakeArray(0)),
+                                }
                                 return;
                             }
                             /*"typeof" => {
@@ -446,11 +539,23 @@ impl Context {
                             _ => (),
                         }
                     }
+                    // `o.f(...a)` is `$apply(o.f, o, args)`.
+                    ExprDecl::Field(e, f) if spread => {
+                        self.compile_spread_args(el);
+                        self.compile_this_for(e);
+                        let gid = self.global(&Global::Str(f.to_owned()));
+                        self.write(Op::LoadGlobal(gid as _));
+                        self.compile(e, false);
+                        self.write(Op::Load);
+                        self.compile_const(&Constant::Builtin("apply".to_owned()));
+                        self.write(Op::Call(3));
+                        return;
+                    }
                     ExprDecl::Field(e, f) => {
                         for e in el.iter().rev() {
                             self.compile(e, false);
                         }
-                        self.compile(e, false);
+                        self.compile_this_for(e);
                         let gid = self.global(&Global::Str(f.to_owned()));
                         self.write(Op::LoadGlobal(gid as _));
                         self.compile(e, false);
@@ -460,6 +565,13 @@ impl Context {
                     }
                     _ => (),
                 }
+                if spread {
+                    self.compile_spread_args(el);
+                    self.compile(e, false);
+                    self.compile_const(&Constant::Builtin("call".to_owned()));
+                    self.write(Op::Call(2));
+                    return;
+                }
                 for x in el.iter().rev() {
                     self.compile(x, false);
                 }
@@ -470,9 +582,6 @@ impl Context {
                     self.write(Op::TailCall(el.len() as _));
                 }
             }
-            ExprDecl::Label(label) => {
-                self.labels.insert(label.to_owned(), Some(self.pos()));
-            }
             ExprDecl::Goto(label) => {
                 self.emit_goto(label);
             }
@@ -482,9 +591,12 @@ impl Context {
                 match op {
                     "-" => self.write(Op::Neg),
                     "!" => self.write(Op::Not),
+                    "~" => self.write(Op::BitNot),
                     _ => (),
                 }
             }
+            ExprDecl::AssignOp(op, e1, e2) => self.compile_assign_op(op, e1, e2, true),
+            ExprDecl::Incr(op, prefix, e) => self.compile_incr(op, *prefix, e, true),
             ExprDecl::Throw(expr) => {
                 self.compile(expr, false);
                 self.write(Op::Throw);
@@ -493,21 +605,374 @@ impl Context {
                 let catch_lbl = self.new_empty_label();
                 let end_lbl = self.new_empty_label();
                 self.emit_paddr(&catch_lbl);
+                self.tries += 1;
                 self.compile(expr, false);
+                self.tries -= 1;
+                self.write(Op::CatchPop);
                 self.emit_goto(&end_lbl);
                 self.label_here(&catch_lbl);
-                let locals = self.locals.clone();
-                let id = self.locals.len() as _;
-                self.locals.insert(name.to_owned(), id);
+                let (locals, slots) = (self.locals.clone(), self.slots);
+                let id = self.new_local(name);
                 self.write(Op::StoreLocal(id as _));
                 self.compile(catch, tail);
                 self.locals = locals;
+                self.slots = slots;
                 self.label_here(&end_lbl);
             }
             v => panic!("{:?}", v),
         }
     }
 
+    /// A type is a prototype object holding its methods, its parent type is
+    /// the prototype's prototype. Instances are created by `Type.new(args)`,
+    /// which calls `$construct` to make an object with the type as prototype
+    /// and call its `init` method.
+    fn compile_type(
+        &mut self,
+        name: &str,
+        parent: &Option<P<Expr>>,
+        methods: &[(String, P<Expr>)],
+    ) {
+        // The type's slot comes before the parent's temporary one, which is
+        // freed again together with `super` once the methods are compiled.
+        let id = self.slots;
+        self.slots += 1;
+        let locals = self.locals.clone();
+        match parent {
+            Some(parent) => {
+                self.compile(parent, false);
+                let id = self.temp_local();
+                self.write(Op::Dup);
+                self.write(Op::StoreLocal(id as _));
+                // Methods capture the parent as `super`, the parser only
+                // accepts it in `super.method()` calls inside methods.
+                self.locals.insert("super".to_owned(), id);
+            }
+            None => self.write(Op::LoadNull),
+        }
+        self.write(Op::New);
+        self.locals.insert(name.to_owned(), id);
+        self.write(Op::StoreLocal(id as _));
+
+        let set_method = |ctx: &mut Self, method: &str| {
+            let gid = ctx.global(&Global::Str(method.to_owned()));
+            ctx.write(Op::LoadGlobal(gid as _));
+            ctx.write(Op::LoadLocal(id as _));
+            ctx.write(Op::Store);
+        };
+        if !methods.iter().any(|(method, _)| method == "new") {
+            self.write(Op::LoadBuiltin("construct".to_owned()));
+            set_method(self, "new");
+        }
+        for (method, function) in methods.iter() {
+            match &function.decl {
+                ExprDecl::Function(params, body) => self.compile_function(params, body, None),
+                _ => unreachable!(),
+            }
+            set_method(self, method);
+        }
+        self.locals = locals;
+        self.locals.insert(name.to_owned(), id);
+        self.slots = id + 1;
+    }
+
+    /// Assign the parts of the value in local `value` to `target`, declaring
+    /// the target variables if `declare` is set.
+    fn compile_destructure(&mut self, target: &Destructure, value: i32, declare: bool) {
+        match target {
+            Destructure::Target(e) => match &e.decl {
+                ExprDecl::Const(Constant::Ident(name)) if declare => {
+                    self.write(Op::LoadLocal(value as _));
+                    let id = self.new_local(name);
+                    self.write(Op::StoreLocal(id as _));
+                }
+                _ => {
+                    let access = self.compile_access(e);
+                    self.write(Op::LoadLocal(value as _));
+                    self.access_set(access);
+                }
+            },
+            Destructure::Array(elements) => {
+                for (i, (target, default)) in elements.iter().enumerate() {
+                    self.write(Op::LoadInt(i as i64));
+                    self.write(Op::LoadLocal(value as _));
+                    self.write(Op::Load);
+                    self.compile_destructure_part(target, default, declare);
+                }
+            }
+            Destructure::Object(fields) => {
+                for (key, target, default) in fields.iter() {
+                    let gid = self.global(&Global::Str(key.to_owned()));
+                    // A missing field with a default is null rather than a
+                    // `FieldError` in strict mode.
+                    let labels = match default {
+                        Some(_) => {
+                            let (missing, done) = (self.new_empty_label(), self.new_empty_label());
+                            self.write(Op::LoadGlobal(gid as _));
+                            self.compile_builtin_call("objfield", value, 2);
+                            self.emit_gotof(&missing);
+                            Some((missing, done))
+                        }
+                        None => None,
+                    };
+                    self.write(Op::LoadGlobal(gid as _));
+                    self.write(Op::LoadLocal(value as _));
+                    self.write(Op::Load);
+                    if let Some((missing, done)) = labels {
+                        self.emit_goto(&done);
+                        self.label_here(&missing);
+                        self.write(Op::LoadNull);
+                        self.label_here(&done);
+                    }
+                    self.compile_destructure_part(target, default, declare);
+                }
+            }
+        }
+    }
+
+    /// Destructure the element or field on top of the stack, replacing null
+    /// with `default`.
+    fn compile_destructure_part(
+        &mut self,
+        target: &Destructure,
+        default: &Option<P<Expr>>,
+        declare: bool,
+    ) {
+        if let Some(default) = default {
+            let present = self.new_empty_label();
+            self.write(Op::Dup);
+            self.write(Op::IsNotNull);
+            self.emit_gotot(&present);
+            self.write(Op::Pop(1));
+            self.compile(default, false);
+            self.label_here(&present);
+        }
+        let id = self.temp_local();
+        self.write(Op::StoreLocal(id as _));
+        self.compile_destructure(target, id, declare);
+    }
+
+    /// Arms are tried in order, an arm matches when its pattern matches and its
+    /// guard is true. Pattern bindings are locals scoped to the arm. Throws a
+    /// `MatchError` when no arm matches.
+    fn compile_match(
+        &mut self,
+        value: &P<Expr>,
+        arms: &[(Pattern, Option<P<Expr>>, P<Expr>)],
+        tail: bool,
+    ) {
+        let (locals, slots) = (self.locals.clone(), self.slots);
+        self.compile(value, false);
+        let subject = self.temp_local();
+        self.write(Op::StoreLocal(subject as _));
+        let end = self.new_empty_label();
+        for (pattern, guard, body) in arms.iter() {
+            let (arm_locals, arm_slots) = (self.locals.clone(), self.slots);
+            let next = self.new_empty_label();
+            let mut names = vec![];
+            pattern.bindings(&mut names);
+            for name in names.iter() {
+                self.new_local(name);
+            }
+            self.compile_pattern(pattern, subject, &next);
+            if let Some(guard) = guard {
+                self.compile(guard, false);
+                self.emit_gotof(&next);
+            }
+            self.compile(body, tail);
+            self.emit_goto(&end);
+            self.label_here(&next);
+            self.locals = arm_locals;
+            self.slots = arm_slots;
+        }
+        self.write(Op::LoadLocal(subject as _));
+        let gid = self.global(&Global::Str("MatchError: no arm matches ".to_owned()));
+        self.write(Op::LoadGlobal(gid as _));
+        self.write(Op::Add);
+        self.write(Op::Throw);
+        self.label_here(&end);
+        self.locals = locals;
+        self.slots = slots;
+    }
+
+    /// Test the value in local `subject` against `pattern`, jumping to `fail`
+    /// if it doesn't match. Bindings are stored in locals allocated by the caller.
+    fn compile_pattern(&mut self, pattern: &Pattern, subject: i32, fail: &str) {
+        match pattern {
+            Pattern::Wildcard => (),
+            Pattern::Bind(name) => {
+                let id = self.locals[name.as_str()];
+                self.write(Op::LoadLocal(subject as _));
+                self.write(Op::StoreLocal(id as _));
+            }
+            Pattern::Literal(c) => {
+                self.compile_const(c);
+                self.write(Op::LoadLocal(subject as _));
+                self.write(Op::Eq);
+                self.emit_gotof(fail);
+            }
+            Pattern::Type(name, pattern) => {
+                self.compile_type_test(subject, name, fail);
+                self.compile_pattern(pattern, subject, fail);
+            }
+            Pattern::Array(elements, rest) => {
+                self.compile_type_test(subject, "array", fail);
+                let n = elements.len() as i64;
+                self.write(Op::LoadInt(n));
+                self.compile_builtin_call("asize", subject, 1);
+                self.write(if rest.is_some() { Op::Gte } else { Op::Eq });
+                self.emit_gotof(fail);
+                for (i, element) in elements.iter().enumerate() {
+                    if let Pattern::Wildcard = element {
+                        continue;
+                    }
+                    self.write(Op::LoadInt(i as i64));
+                    self.write(Op::LoadLocal(subject as _));
+                    self.write(Op::Load);
+                    let id = self.temp_local();
+                    self.write(Op::StoreLocal(id as _));
+                    self.compile_pattern(element, id, fail);
+                }
+                if let Some(rest) = rest {
+                    // $asub(subject, n, $asize(subject) - n)
+                    self.write(Op::LoadInt(n));
+                    self.compile_builtin_call("asize", subject, 1);
+                    self.write(Op::Sub);
+                    self.write(Op::LoadInt(n));
+                    self.compile_builtin_call("asub", subject, 3);
+                    let id = self.locals[rest.as_str()];
+                    self.write(Op::StoreLocal(id as _));
+                }
+            }
+            Pattern::Object(fields) => {
+                self.compile_type_test(subject, "object", fail);
+                for (key, field) in fields.iter() {
+                    let gid = self.global(&Global::Str(key.to_owned()));
+                    self.write(Op::LoadGlobal(gid as _));
+                    self.compile_builtin_call("objfield", subject, 2);
+                    self.emit_gotof(fail);
+                    if let Pattern::Wildcard = field {
+                        continue;
+                    }
+                    self.write(Op::LoadGlobal(gid as _));
+                    self.write(Op::LoadLocal(subject as _));
+                    self.write(Op::Load);
+                    let id = self.temp_local();
+                    self.write(Op::StoreLocal(id as _));
+                    self.compile_pattern(field, id, fail);
+                }
+            }
+            Pattern::Or(alternatives) => {
+                let matched = self.new_empty_label();
+                let (last, rest) = alternatives.split_last().unwrap();
+                for alternative in rest.iter() {
+                    let next = self.new_empty_label();
+                    self.compile_pattern(alternative, subject, &next);
+                    self.emit_goto(&matched);
+                    self.label_here(&next);
+                }
+                self.compile_pattern(last, subject, fail);
+                self.label_here(&matched);
+            }
+        }
+    }
+
+    /// Jump to `fail` unless `$typeof` of local `subject` is `name`.
+    fn compile_type_test(&mut self, subject: i32, name: &str, fail: &str) {
+        self.compile_builtin_call("typeof", subject, 1);
+        let gid = self.global(&Global::Str(name.to_owned()));
+        self.write(Op::LoadGlobal(gid as _));
+        self.write(Op::Eq);
+        self.emit_gotof(fail);
+    }
+
+    /// Call builtin `name` with local `subject` as first argument. The other
+    /// `argc - 1` arguments must already be on the stack, last one first.
+    fn compile_builtin_call(&mut self, name: &str, subject: i32, argc: u16) {
+        self.write(Op::LoadLocal(subject as _));
+        self.compile_const(&Constant::Builtin(name.to_owned()));
+        self.write(Op::Call(argc));
+    }
+
+    /// Push `this` for a method call on `e`. `super.f(...)` looks `f` up from
+    /// the parent type but keeps `this`.
+    fn compile_this_for(&mut self, e: &P<Expr>) {
+        if e.decl == ExprDecl::Const(Constant::Ident("super".to_owned())) {
+            self.write(Op::LoadThis);
+        } else {
+            self.compile(e, false);
+        }
+    }
+
+    /// Remove the handlers of `try` bodies that a `break` or `continue` jumps out of.
+    fn leave_tries(&mut self) {
+        let outer = self.loop_tries.last().cloned().unwrap_or(0);
+        for _ in outer..self.tries {
+            self.write(Op::CatchPop);
+        }
+    }
+
+    /// Compile `e` for its side effects only. Unlike `compile`, which always
+    /// pushes exactly one value, this leaves the stack as it was.
+    pub fn compile_stmt(&mut self, e: &P<Expr>) {
+        match &e.decl {
+            ExprDecl::Var(_, name, init) => {
+                match init {
+                    Some(e) => match &e.decl {
+                        ExprDecl::Function(args, body) => {
+                            self.compile_function(args, body, Some(name))
+                        }
+                        _ => self.compile(e, false),
+                    },
+                    None => self.write(Op::LoadNull),
+                }
+                let id = self.new_local(name);
+                self.write(Op::StoreLocal(id as _));
+            }
+            ExprDecl::Assign(e1, e2) => {
+                let a = self.compile_access(e1);
+                self.compile(e2, false);
+                self.access_set(a);
+            }
+            ExprDecl::Destructuring(declaration, target, value) => {
+                self.compile(value, false);
+                let id = self.temp_local();
+                self.write(Op::StoreLocal(id as _));
+                self.compile_destructure(target, id, declaration.is_some());
+            }
+            ExprDecl::AssignOp(op, e1, e2) => self.compile_assign_op(op, e1, e2, false),
+            ExprDecl::Incr(op, prefix, e) => self.compile_incr(op, *prefix, e, false),
+            ExprDecl::Type(name, parent, methods) => self.compile_type(name, parent, methods),
+            ExprDecl::Label(label) => {
+                self.labels.insert(label.to_owned(), None);
+                self.label_here(label);
+            }
+            ExprDecl::Block(v) => self.compile_block(v, false, false),
+            _ => {
+                self.compile(e, false);
+                self.write(Op::Pop(1));
+            }
+        }
+    }
+
+    /// Compile block elements as statements. When `value` is set the last
+    /// element is left on the stack, or null for an empty block.
+    pub fn compile_block(&mut self, v: &[P<Expr>], tail: bool, value: bool) {
+        let (locals, slots) = (self.locals.clone(), self.slots);
+        for (i, el) in v.iter().enumerate() {
+            if value && i == v.len() - 1 {
+                self.compile(el, tail);
+            } else {
+                self.compile_stmt(el);
+            }
+        }
+        if value && v.is_empty() {
+            self.write(Op::LoadNull);
+        }
+        self.locals = locals;
+        self.slots = slots;
+    }
+
     pub fn compile_binop(&mut self, op: &str, e1: &P<Expr>, e2: &P<Expr>, tail: bool) {
         match op {
             "==" => match &e2.decl {
@@ -532,20 +997,19 @@ impl Context {
                     self.write(Op::Neq);
                 }
             },
-            "&&" => {
-                let if_false = self.new_empty_label();
-                self.compile(e1, false);
-                self.emit_gotof(&if_false);
-                self.compile(e2, tail);
-                self.label_here(&if_false);
-            }
-            "||" => {
-                let if_true = self.new_empty_label();
+            // `a && b` and `a || b` evaluate to the operand that decided the result.
+            "&&" | "||" => {
+                let end = self.new_empty_label();
                 self.compile(e1, false);
-                self.emit_gotot(&if_true);
+                self.write(Op::Dup);
+                if op == "&&" {
+                    self.emit_gotof(&end);
+                } else {
+                    self.emit_gotot(&end);
+                }
+                self.write(Op::Pop(1));
                 self.compile(e2, tail);
-
-                self.label_here(&if_true);
+                self.label_here(&end);
             }
             _ => {
                 self.compile(e2, false);
@@ -556,7 +1020,14 @@ impl Context {
         }
     }
 
-    pub fn compile_function(&mut self, params: &[String], e: &P<Expr>, vname: Option<&str>) {
+    /// A function with a fixed number of parameters gets them as its first
+    /// locals and `argc` is their count. A variadic function has `argc` set to
+    /// `-(n + 1)` for `n` positional parameters, the VM passes it the missing
+    /// positional arguments as null and the array of all arguments in local
+    /// `n`. Its prologue checks the number of arguments and sets default
+    /// values and the rest parameter.
+    pub fn compile_function(&mut self, params: &Params, e: &P<Expr>, vname: Option<&str>) {
+        let n = params.names.len();
         let mut ctx = Context {
             g: self.g.clone(),
             ops: Vec::new(),
@@ -564,20 +1035,29 @@ impl Context {
             limit: self.stack,
             stack: self.stack,
             locals: LinkedHashMap::new(),
+            slots: 0,
             nenv: 0,
             env: self.locals.clone(),
             cur_pos: None,
             continues: vec![],
             breaks: vec![],
+            tries: 0,
+            loop_tries: vec![],
             labels: self.labels.clone(),
             used_upvars: LinkedHashMap::new(),
             trace_info: HashMap::new(),
             ret_lbl: String::new(),
         };
-        for (idx, p) in params.iter().enumerate() {
+        for (p, _) in params.names.iter() {
             ctx.stack += 1;
-            ctx.locals.insert(p.to_owned(), idx as i32);
+            ctx.new_local(p);
         }
+        let argc = if params.variadic() {
+            ctx.new_local("#args");
+            -(n as i32) - 1
+        } else {
+            n as i32
+        };
 
         let gid = ctx.g.borrow().table.len();
         if vname.is_some() {
@@ -588,18 +1068,19 @@ impl Context {
         }
         ctx.g.borrow_mut().table.push(Global::Func(gid as i32, -1));
         ctx.ret_lbl = ctx.new_empty_label();
+        if params.variadic() {
+            ctx.compile_prologue(params);
+        }
         ctx.compile(e, true);
         let ret_lbl = ctx.ret_lbl.clone();
         ctx.label_here(&ret_lbl);
         ctx.write(Op::Ret);
         //ctx.check_stack(s, "");
 
-        ctx.g.borrow_mut().functions.push((
-            ctx.ops.clone(),
-            ctx.pos.clone(),
-            gid as i32,
-            params.len() as i32,
-        ));
+        ctx.g
+            .borrow_mut()
+            .functions
+            .push((ctx.ops.clone(), ctx.pos.clone(), gid as i32, argc));
 
         for (k, v) in ctx.labels.iter() {
             self.labels.insert(k.clone(), v.clone());
@@ -616,6 +1097,116 @@ impl Context {
         }
     }
 
+    /// Check the argument count of a variadic function and set its default
+    /// values and rest parameter.
+    fn compile_prologue(&mut self, params: &Params) {
+        let n = params.names.len() as i64;
+        let required = params.required() as i64;
+        let args = self.locals["#args"];
+        let count = self.temp_local();
+        self.compile_builtin_call("asize", args, 1);
+        self.write(Op::StoreLocal(count as _));
+
+        let ok = self.new_empty_label();
+        let error = self.new_empty_label();
+        // Functions reading `$args()` accept extra arguments like rest parameters.
+        let unbounded = params.rest.is_some() || params.uses_args;
+        let expected = match unbounded {
+            true if required == 0 => None,
+            true => Some(format!("at least {}", required)),
+            false if required == n => Some(n.to_string()),
+            false => Some(format!("{} to {}", required, n)),
+        };
+        if let Some(expected) = expected {
+            if !unbounded {
+                self.write(Op::LoadInt(n));
+                self.write(Op::LoadLocal(count as _));
+                self.write(Op::Gt);
+                self.emit_gotot(&error);
+            }
+            self.write(Op::LoadInt(required));
+            self.write(Op::LoadLocal(count as _));
+            self.write(Op::Gte);
+            self.emit_gotot(&ok);
+            self.label_here(&error);
+            self.write(Op::LoadLocal(count as _));
+            let message = format!("Expected {} arguments,found ", expected);
+            let gid = self.global(&Global::Str(message));
+            self.write(Op::LoadGlobal(gid as _));
+            self.write(Op::Add);
+            self.write(Op::Throw);
+            self.label_here(&ok);
+        }
+
+        for (i, (name, default)) in params.names.iter().enumerate() {
+            if let Some(default) = default {
+                let passed = self.new_empty_label();
+                self.write(Op::LoadInt(i as i64));
+                self.write(Op::LoadLocal(count as _));
+                self.write(Op::Gt);
+                self.emit_gotot(&passed);
+                self.compile(default, false);
+                let id = self.locals[name.as_str()];
+                self.write(Op::StoreLocal(id as _));
+                self.label_here(&passed);
+            }
+        }
+
+        if let Some(rest) = &params.rest {
+            // $asub(args, n, count - n), or [] when there are no extra arguments.
+            let empty = self.new_empty_label();
+            let done = self.new_empty_label();
+            self.write(Op::LoadInt(n));
+            self.write(Op::LoadLocal(count as _));
+            self.write(Op::Gt);
+            self.emit_gotof(&empty);
+            self.write(Op::LoadInt(n));
+            self.write(Op::LoadLocal(count as _));
+            self.write(Op::Sub);
+            self.write(Op::LoadInt(n));
+            self.compile_builtin_call("asub", args, 3);
+            self.emit_goto(&done);
+            self.label_here(&empty);
+            self.write(Op::MakeArray(0));
+            self.label_here(&done);
+            let id = self.new_local(rest);
+            self.write(Op::StoreLocal(id as _));
+        }
+    }
+
+    /// Push the arguments of a call as one array, spreading `...e` arguments.
+    fn compile_spread_args(&mut self, args: &[P<Expr>]) {
+        // Runs of plain arguments form one array, each spread argument another.
+        let mut parts = vec![];
+        let mut start = 0;
+        for (i, arg) in args.iter().enumerate() {
+            if let ExprDecl::Spread(_) = arg.decl {
+                if start < i {
+                    parts.push(&args[start..i]);
+                }
+                parts.push(&args[i..i + 1]);
+                start = i + 1;
+            }
+        }
+        if start < args.len() {
+            parts.push(&args[start..]);
+        }
+        // Like call arguments, the last part is evaluated first.
+        for part in parts.iter().rev() {
+            match &part[0].decl {
+                ExprDecl::Spread(e) => self.compile(e, false),
+                _ => {
+                    for e in part.iter().rev() {
+                        self.compile(e, false);
+                    }
+                    self.write(Op::MakeArray(part.len() as _));
+                }
+            }
+        }
+        self.compile_const(&Constant::Builtin("aconcat".to_owned()));
+        self.write(Op::Call(parts.len() as _));
+    }
+
     pub fn new() -> Context {
         let g = Globals {
             globals: LinkedHashMap::new(),
@@ -628,12 +1219,15 @@ impl Context {
             ops: vec![],
             pos: vec![],
             locals: Default::default(),
+            slots: 0,
             env: Default::default(),
             stack: 0,
             limit: 0,
             nenv: 0,
             breaks: vec![],
             continues: vec![],
+            tries: 0,
+            loop_tries: vec![],
             cur_pos: None,
             labels: Default::default(),
             used_upvars: Default::default(),
@@ -714,6 +1308,8 @@ pub fn module_from_context(ctx: &mut Context) -> Ref<Module> {
                     argc: *nargs,
                     env: Value::Array(Ref(vec![])),
                     module: Some(m.clone()),
+                    calls: Default::default(),
+                    bound: None,
                 });
 
                 m.borrow_mut().globals[i] = Value::Function(func);
@@ -724,6 +1320,10 @@ pub fn module_from_context(ctx: &mut Context) -> Ref<Module> {
             Global::Float(x) => {
                 m.borrow_mut().globals[i] = Value::Float(f64::from_bits(*x));
             }
+            Global::BigInt(x) => {
+                let x = jazzlight::bigint::BigInt::parse(x, 10).unwrap();
+                m.borrow_mut().globals[i] = Value::from_bigint(x);
+            }
             _ => (),
         };
     }
@@ -731,3 +1331,27 @@ pub fn module_from_context(ctx: &mut Context) -> Ref<Module> {
 
     m
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::reader::Reader;
+
+    #[test]
+    fn super_is_scoped_to_the_methods() {
+        let mut ast = vec![];
+        let src = "type Point { fn len() { 0 } } type Point3 : Point { fn len() { super.len() } }";
+        Parser::new(Reader::from_string(src), &mut ast)
+            .parse()
+            .unwrap();
+        crate::resolver::resolve(&mut ast).unwrap();
+        let mut ctx = Context::new();
+        for e in ast.iter() {
+            ctx.compile_stmt(e);
+        }
+        let names: Vec<&str> = ctx.locals.keys().map(|name| name.as_str()).collect();
+        assert_eq!(names, ["Point", "Point3"]);
+        assert_eq!(ctx.slots, 2);
+    }
+}
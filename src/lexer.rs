@@ -36,6 +36,7 @@ impl Lexer {
             "loop" => TokenKind::Loop,
             "break" => TokenKind::Break,
             "switch" => TokenKind::Match,
+            "match" => TokenKind::Match,
             "continue" => TokenKind::Continue,
             "const" => TokenKind::Const,
             "return" => TokenKind::Return,
@@ -103,10 +104,13 @@ impl Lexer {
                 self.read_comment()?;
             } else if self.is_multi_comment_start() {
                 self.read_multi_comment()?;
+            } else if ch == Some('r') && is_quote(self.next()) {
+                self.read_char();
+                return self.read_string(pos, true);
             } else if is_identifier_start(ch) {
                 return self.read_identifier();
             } else if is_quote(ch) {
-                return self.read_string();
+                return self.read_string(pos, false);
             } else if is_char_quote(ch) {
                 return self.read_char_literal();
             } else if is_operator(ch) {
@@ -198,6 +202,7 @@ impl Lexer {
                     '\"' => Ok('\"'),
                     '\'' => Ok('\''),
                     '0' => Ok('\0'),
+                    '$' => Ok('$'),
                     _ => {
                         let msg = Msg::InvalidEscapeSequence(ch);
                         Err(MsgWithPos::new(self.filename(), pos, msg))
@@ -211,24 +216,114 @@ impl Lexer {
         }
     }
 
-    fn read_string(&mut self) -> Result<Token, MsgWithPos> {
-        let pos = self.reader.pos();
-        let mut value = String::new();
-
+    /// Reads `"..."` or `"""..."""`, whose first newline is dropped. Raw
+    /// strings keep backslashes and `${` as written.
+    fn read_string(&mut self, pos: Position, raw: bool) -> Result<Token, MsgWithPos> {
         self.read_char();
+        let triple = is_quote(self.cur()) && is_quote(self.next());
+        if triple {
+            self.read_char();
+            self.read_char();
+            if is_newline(self.cur()) {
+                self.read_char();
+            }
+        }
 
-        while !self.cur().is_none() && !is_quote(self.cur()) {
-            let ch = self.read_escaped_char(pos.clone(), Msg::UnclosedString)?;
-            value.push(ch);
+        let mut parts = vec![];
+        let mut value = String::new();
+        loop {
+            match self.cur() {
+                None => return Err(MsgWithPos::new(self.filename(), pos, Msg::UnclosedString)),
+                Some('"') => {
+                    self.read_char();
+                    if !triple {
+                        break;
+                    }
+                    if is_quote(self.cur()) && is_quote(self.next()) {
+                        self.read_char();
+                        self.read_char();
+                        break;
+                    }
+                    value.push('"');
+                }
+                Some('$') if !raw && self.next() == Some('{') => {
+                    self.read_char();
+                    self.read_char();
+                    if !value.is_empty() {
+                        parts.push(StringPart::Str(std::mem::replace(
+                            &mut value,
+                            String::new(),
+                        )));
+                    }
+                    let expr_pos = self.reader.pos();
+                    let mut src = String::new();
+                    self.read_interpolation(&mut src, &expr_pos)?;
+                    self.read_char();
+                    parts.push(StringPart::Expr(src, expr_pos));
+                }
+                Some(ch) if raw => {
+                    self.read_char();
+                    value.push(ch);
+                }
+                Some(_) => {
+                    let ch = self.read_escaped_char(pos.clone(), Msg::UnclosedString)?;
+                    value.push(ch);
+                }
+            }
         }
 
-        if is_quote(self.cur()) {
-            self.read_char();
+        if parts.is_empty() {
+            return Ok(Token::new(TokenKind::String(value), pos));
+        }
+        if !value.is_empty() {
+            parts.push(StringPart::Str(value));
+        }
+        Ok(Token::new(TokenKind::Template(parts), pos))
+    }
 
-            let ttype = TokenKind::String(value);
-            Ok(Token::new(ttype, pos))
-        } else {
-            Err(MsgWithPos::new(self.filename(), pos, Msg::UnclosedString))
+    /// Copies the source of a `${...}` up to its closing `}` into `src`,
+    /// skipping over braces inside nested literals.
+    fn read_interpolation(&mut self, src: &mut String, pos: &Position) -> Result<(), MsgWithPos> {
+        let mut depth = 0;
+        loop {
+            let ch = match self.cur() {
+                None => {
+                    let msg = Msg::UnclosedInterpolation;
+                    return Err(MsgWithPos::new(self.filename(), pos.clone(), msg));
+                }
+                Some('}') if depth == 0 => return Ok(()),
+                Some(ch) => ch,
+            };
+            self.read_char();
+            src.push(ch);
+            match ch {
+                '{' => depth += 1,
+                '}' => depth -= 1,
+                '"' | '\'' => {
+                    while self.cur().is_some() && self.cur() != Some(ch) {
+                        let c = self.cur().unwrap();
+                        self.read_char();
+                        src.push(c);
+                        if c == '\\' {
+                            if let Some(c) = self.cur() {
+                                self.read_char();
+                                src.push(c);
+                            }
+                        } else if c == '$' && ch == '"' && self.cur() == Some('{') {
+                            self.read_char();
+                            src.push('{');
+                            self.read_interpolation(src, pos)?;
+                            self.read_char();
+                            src.push('}');
+                        }
+                    }
+                    if let Some(c) = self.cur() {
+                        self.read_char();
+                        src.push(c);
+                    }
+                }
+                _ => {}
+            }
         }
     }
 
@@ -240,16 +335,42 @@ impl Lexer {
         let nch = self.cur().unwrap_or('x');
 
         tok.kind = match ch {
-            '+' => TokenKind::Add,
-            '-' => {
-                if nch == '>' {
+            '+' => match nch {
+                '+' => {
+                    self.read_char();
+                    TokenKind::AddAdd
+                }
+                '=' => {
+                    self.read_char();
+                    TokenKind::AddEq
+                }
+                _ => TokenKind::Add,
+            },
+            '-' => match nch {
+                '>' => {
                     self.read_char();
                     TokenKind::Arrow
-                } else {
-                    TokenKind::Sub
                 }
-            }
+                '-' => {
+                    self.read_char();
+                    TokenKind::SubSub
+                }
+                '=' => {
+                    self.read_char();
+                    TokenKind::SubEq
+                }
+                _ => TokenKind::Sub,
+            },
 
+            '*' | '/' | '%' | '^' if nch == '=' => {
+                self.read_char();
+                match ch {
+                    '*' => TokenKind::MulEq,
+                    '/' => TokenKind::DivEq,
+                    '%' => TokenKind::ModEq,
+                    _ => TokenKind::CaretEq,
+                }
+            }
             '*' => TokenKind::Mul,
             '/' => TokenKind::Div,
             '%' => TokenKind::Mod,
@@ -265,6 +386,9 @@ impl Lexer {
                 if nch == '|' {
                     self.read_char();
                     TokenKind::Or
+                } else if nch == '=' {
+                    self.read_char();
+                    TokenKind::BitOrEq
                 } else {
                     TokenKind::BitOr
                 }
@@ -274,6 +398,9 @@ impl Lexer {
                 if nch == '&' {
                     self.read_char();
                     TokenKind::And
+                } else if nch == '=' {
+                    self.read_char();
+                    TokenKind::BitAndEq
                 } else {
                     TokenKind::BitAnd
                 }
@@ -291,9 +418,18 @@ impl Lexer {
                     TokenKind::Colon
                 }
             }
+            '.' if nch == '.' && self.next() == Some('.') => {
+                self.read_char();
+                self.read_char();
+                TokenKind::DotDotDot
+            }
             '.' => TokenKind::Dot,
             '=' => {
-                if nch == '=' {
+                if nch == '=' && self.next() == Some('=') {
+                    self.read_char();
+                    self.read_char();
+                    TokenKind::EqEqEq
+                } else if nch == '=' {
                     self.read_char();
                     TokenKind::EqEq
                 } else {
@@ -309,7 +445,13 @@ impl Lexer {
 
                 '<' => {
                     self.read_char();
-                    TokenKind::LtLt
+
+                    if self.cur() == Some('=') {
+                        self.read_char();
+                        TokenKind::LtLtEq
+                    } else {
+                        TokenKind::LtLt
+                    }
                 }
 
                 _ => TokenKind::Lt,
@@ -324,14 +466,26 @@ impl Lexer {
                 '>' => {
                     self.read_char();
 
-                    TokenKind::GtGt
+                    if self.cur() == Some('>') {
+                        self.read_char();
+                        TokenKind::GtGtGt
+                    } else if self.cur() == Some('=') {
+                        self.read_char();
+                        TokenKind::GtGtEq
+                    } else {
+                        TokenKind::GtGt
+                    }
                 }
 
                 _ => TokenKind::Gt,
             },
 
             '!' => {
-                if nch == '=' {
+                if nch == '=' && self.next() == Some('=') {
+                    self.read_char();
+                    self.read_char();
+                    TokenKind::NeEq
+                } else if nch == '=' {
                     self.read_char();
                     TokenKind::Ne
                 } else {
@@ -395,6 +549,9 @@ impl Lexer {
         self.cur() == Some('*') && self.next() == Some('/')
     }
 
+    /// Reads an int with an optional `b` (byte) or `L` (long) suffix, or a
+    /// float. Digits may be separated with `_`. Hex floats are kept with
+    /// their `0x` prefix, as in `0x1.8p3`.
     fn read_number(&mut self) -> Result<Token, MsgWithPos> {
         let pos = self.reader.pos();
         let mut value = String::new();
@@ -414,6 +571,12 @@ impl Lexer {
                     self.read_char();
                     self.read_char();
 
+                    // `0b` without binary digits is the byte zero.
+                    if !is_digit_or_underscore(self.cur(), IntBase::Bin) {
+                        let ttype = TokenKind::LitInt("0".into(), IntBase::Dec, IntSuffix::Byte);
+                        return Ok(Token::new(ttype, pos));
+                    }
+
                     IntBase::Bin
                 }
 
@@ -424,32 +587,72 @@ impl Lexer {
         };
 
         self.read_digits(&mut value, base);
+        if value.is_empty() {
+            return Err(self.invalid_number(pos));
+        }
 
-        if base == IntBase::Dec && self.cur() == Some('.') && is_digit(self.next()) {
+        let exponent = match base {
+            IntBase::Dec => Some('e'),
+            IntBase::Hex => Some('p'),
+            IntBase::Bin => None,
+        };
+        let fraction = base != IntBase::Bin
+            && self.cur() == Some('.')
+            && self
+                .next()
+                .map(|ch| ch.is_digit(base.num()))
+                .unwrap_or(false);
+        if fraction {
             self.read_char();
             value.push('.');
 
-            self.read_digits(&mut value, IntBase::Dec);
+            self.read_digits(&mut value, base);
+        }
 
-            if self.cur() == Some('e') || self.cur() == Some('E') {
+        let has_exponent =
+            exponent.is_some() && self.cur().map(|ch| ch.to_ascii_lowercase()) == exponent;
+        if has_exponent {
+            value.push(exponent.unwrap());
+            self.read_char();
+
+            if self.cur() == Some('+') || self.cur() == Some('-') {
                 value.push(self.cur().unwrap());
                 self.read_char();
+            }
 
-                if self.cur() == Some('+') || self.cur() == Some('-') {
-                    value.push(self.cur().unwrap());
-                    self.read_char();
-                }
-
-                self.read_digits(&mut value, IntBase::Dec);
+            if !is_digit(self.cur()) {
+                return Err(self.invalid_number(pos));
             }
+            self.read_digits(&mut value, IntBase::Dec);
+        }
 
+        if fraction || has_exponent {
+            if base == IntBase::Hex {
+                value.insert_str(0, "0x");
+            }
             let ttype = TokenKind::LitFloat(value);
             return Ok(Token::new(ttype, pos));
         }
 
-        let ttype = TokenKind::LitInt(value, base, IntSuffix::Int);
+        let suffix = match self.cur() {
+            Some('b') | Some('B') => IntSuffix::Byte,
+            Some('L') => IntSuffix::Long,
+            _ => IntSuffix::Int,
+        };
+        if suffix != IntSuffix::Int {
+            self.read_char();
+        }
+        if is_identifier(self.cur()) {
+            return Err(self.invalid_number(pos));
+        }
+
+        let ttype = TokenKind::LitInt(value, base, suffix);
         Ok(Token::new(ttype, pos))
     }
+
+    fn invalid_number(&self, pos: Position) -> MsgWithPos {
+        MsgWithPos::new(self.filename(), pos, Msg::InvalidNumber)
+    }
 }
 
 fn is_digit(ch: Option<char>) -> bool {
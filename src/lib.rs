@@ -5,6 +5,7 @@ pub mod msg;
 pub mod optimizer;
 pub mod parser;
 pub mod reader;
+pub mod resolver;
 pub mod token;
 use std::sync::Arc;
 
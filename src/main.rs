@@ -3,6 +3,7 @@ use jazzlightc::reader::Reader;
 use jazzlight::writer::BytecodeWriter;
 use jazzlightc::codegen::{compile, module_from_context};
 use jazzlightc::parser::Parser;
+use jazzlightc::resolver::resolve;
 use std::path::PathBuf;
 use structopt::StructOpt;
 
@@ -19,6 +20,12 @@ pub struct Options {
     verbose: bool,
     #[structopt(long = "run")]
     run: bool,
+    #[structopt(short = "W", long = "warnings")]
+    /// Report shadowed and unused variables
+    warnings: bool,
+    #[structopt(long = "deny-warnings")]
+    /// Report warnings and fail compilation if there are any
+    deny_warnings: bool,
 }
 
 fn main() {
@@ -40,14 +47,26 @@ fn main() {
             std::process::exit(1);
         }
     }
+    let warnings = match resolve(&mut ast) {
+        Ok(warnings) => warnings,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    if ops.warnings || ops.deny_warnings {
+        for warning in warnings.iter() {
+            eprintln!("{}", warning.warning());
+        }
+        if ops.deny_warnings && !warnings.is_empty() {
+            std::process::exit(1);
+        }
+    }
     let mut ctx = compile(ast);
     let m = module_from_context(&mut ctx);
 
     if ops.dump_op || ops.verbose {
-        println!("Byteocde:");
-        for (i, op) in m.borrow().code.iter().enumerate() {
-            println!("{:04}: {:?}", i, op)
-        }
+        print!("{}", jazzlight::asm::disassemble(&m.borrow()));
         println!();
     }
     let mut w = BytecodeWriter { bytecode: vec![] };
@@ -64,6 +64,8 @@ pub enum Msg {
     UnknownChar(char),
     UnclosedChar,
     UnclosedString,
+    UnclosedInterpolation,
+    InvalidNumber,
     NumberOverflow(String),
     ExpectedClass(String),
     ExpectedFactor(String),
@@ -113,6 +115,13 @@ pub enum Msg {
     MakeIteratorReturnType(String),
     UnknownStructField(String, String),
     StructFieldNotInitialized(String, String),
+    ShadowVar(String),
+    UnusedVariable(String),
+    ExpectedPattern(String),
+    PatternBindingsDiffer,
+    RestNotLast,
+    InvalidDestructuringTarget,
+    MissingDefaultValue(String),
 }
 
 impl Msg {
@@ -149,13 +158,13 @@ impl Msg {
             }
             IdentifierExists(ref name) => format!("can not redefine identifier `{}`.", name),
             ShadowFunction(ref name) => format!("can not shadow function `{}`.", name),
-            ShadowParam(ref name) => format!("can not shadow param `{}`.", name),
+            ShadowParam(ref name) => format!("variable `{}` shadows a parameter.", name),
             ShadowClass(ref name) => format!("can not shadow class `{}`.", name),
             ShadowStruct(ref name) => format!("can not shadow struct `{}`.", name),
             ShadowTrait(ref name) => format!("can not shadow trait `{}`.", name),
             ShadowField(ref name) => format!("field with name `{}` already exists.", name),
-            ShadowGlobal(ref name) => format!("can not shadow global variable `{}`.", name),
-            ShadowConst(ref name) => format!("can not shadow const `{}`", name),
+            ShadowGlobal(ref name) => format!("variable `{}` shadows a global variable.", name),
+            ShadowConst(ref name) => format!("variable `{}` shadows a constant.", name),
             VarNeedsTypeInfo(ref name) => format!(
                 "variable `{}` needs either type declaration or expression.",
                 name
@@ -254,6 +263,8 @@ impl Msg {
             UnclosedComment => "unclosed comment.".into(),
             InvalidEscapeSequence(ch) => format!("unknown escape sequence `\\{}`.", ch),
             UnclosedString => "unclosed string.".into(),
+            UnclosedInterpolation => "unclosed `${` in string.".into(),
+            InvalidNumber => "invalid number literal.".into(),
             UnclosedChar => "unclosed char.".into(),
             IoError => "error reading from file.".into(),
             MissingFctBody => "missing function body.".into(),
@@ -347,6 +358,18 @@ impl Msg {
             StructFieldNotInitialized(ref struc, ref field) => {
                 format!("field `{}` in struct `{}` not initialized.", field, struc)
             }
+            ShadowVar(ref name) => format!("variable `{}` shadows an outer variable.", name),
+            UnusedVariable(ref name) => format!("unused variable `{}`.", name),
+            ExpectedPattern(ref got) => format!("pattern expected but got {}.", got),
+            PatternBindingsDiffer => {
+                "alternatives of a pattern must bind the same variables.".into()
+            }
+            RestNotLast => "`...` must come last.".into(),
+            InvalidDestructuringTarget => "invalid target in destructuring assignment.".into(),
+            MissingDefaultValue(ref name) => format!(
+                "parameter `{}` follows a parameter with default value and needs one too.",
+                name
+            ),
         }
     }
 }
@@ -371,6 +394,15 @@ impl MsgWithPos {
         }
     }
 
+    /// Like `message`, for diagnostics that don't stop compilation.
+    pub fn warning(&self) -> String {
+        if self.path.is_empty() {
+            format!("warning at {}: {}", self.pos, self.msg.message())
+        } else {
+            format!("warning in {}: {}", self.pos, self.msg.message())
+        }
+    }
+
     pub fn without_path(pos: Position, msg: Msg) -> MsgWithPos {
         MsgWithPos {
             path: "".to_string(),
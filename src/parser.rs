@@ -1,11 +1,21 @@
 use std::mem;
 
 use crate::{ast::*, lexer::*, msg::*, reader::Reader, token::*};
+use jazzlight::bigint::BigInt;
 
 pub struct Parser<'a> {
     lexer: Lexer,
     token: Token,
     ast: &'a mut Vec<P<Expr>>,
+    /// Set while parsing methods of a type with a parent type.
+    super_available: bool,
+    /// Line of the last consumed token.
+    prev_line: u32,
+    /// Set when `$args` is used in the function being parsed.
+    uses_args: bool,
+    /// Set when the next token is an integer literal directly after unary
+    /// minus, so `-9223372036854775808` is in range.
+    negated: bool,
 }
 use crate::P;
 
@@ -29,6 +39,10 @@ impl<'a> Parser<'a> {
                 Position::new(crate::P("<>".to_owned()), 1, 1),
             ),
             ast,
+            super_available: false,
+            prev_line: 0,
+            uses_args: false,
+            negated: false,
         }
     }
 
@@ -80,21 +94,64 @@ impl<'a> Parser<'a> {
         let pos = self.expect_token(TokenKind::Fun)?.position;
 
         //self.expect_identifier()?;
+        // `this` of a nested function isn't the method's receiver.
+        let super_available = mem::replace(&mut self.super_available, false);
+        let function = self.parse_function_rest(pos);
+        self.super_available = super_available;
+        function
+    }
+
+    /// Parameter list and body of a function or method.
+    fn parse_function_rest(&mut self, pos: Position) -> EResult {
         self.expect_token(TokenKind::LParen)?;
-        let params = if self.token.kind == TokenKind::RParen {
-            vec![]
-        } else {
-            let mut tmp = vec![];
-            while !self.token.is(TokenKind::RParen) {
-                tmp.push(self.expect_identifier()?);
-                if !self.token.is(TokenKind::RParen) {
-                    self.expect_token(TokenKind::Comma)?;
-                }
+        let params = self.parse_params(TokenKind::RParen, 0)?;
+        self.parse_function_body(params, pos)
+    }
+
+    /// Parameters up to and including `stop`. Default values are parsed as
+    /// binary expressions above `precedence`.
+    fn parse_params(&mut self, stop: TokenKind, precedence: u32) -> Result<Params, MsgWithPos> {
+        let mut params = Params::default();
+        while !self.token.is(stop.clone()) && !self.token.is_eof() {
+            let param_pos = self.token.position.clone();
+            if params.rest.is_some() {
+                return Err(MsgWithPos::new(
+                    self.lexer.path(),
+                    param_pos,
+                    Msg::RestNotLast,
+                ));
             }
-            tmp
-        };
-        self.expect_token(TokenKind::RParen)?;
+            if self.token.is(TokenKind::DotDotDot) {
+                self.advance_token()?;
+                params.rest = Some(self.expect_identifier()?);
+            } else {
+                let name = self.expect_identifier()?;
+                let default = if self.token.is(TokenKind::Eq) {
+                    self.advance_token()?;
+                    Some(self.parse_binary(precedence)?)
+                } else if params.required() < params.names.len() {
+                    return Err(MsgWithPos::new(
+                        self.lexer.path(),
+                        param_pos,
+                        Msg::MissingDefaultValue(name),
+                    ));
+                } else {
+                    None
+                };
+                params.names.push((name, default));
+            }
+            if !self.token.is(stop.clone()) {
+                self.expect_token(TokenKind::Comma)?;
+            }
+        }
+        self.expect_token(stop)?;
+        Ok(params)
+    }
+
+    fn parse_function_body(&mut self, mut params: Params, pos: Position) -> EResult {
+        let uses_args = mem::replace(&mut self.uses_args, false);
         let body = self.parse_expression()?;
+        params.uses_args = mem::replace(&mut self.uses_args, uses_args);
         Ok(expr!(ExprDecl::Function(params, body), pos))
     }
 
@@ -108,6 +165,15 @@ impl<'a> Parser<'a> {
         let reassignable = self.token.is(TokenKind::Var);
 
         let pos = self.advance_token()?.position;
+        if self.token.is(TokenKind::LBracket) || self.token.is(TokenKind::LBrace) {
+            let target = self.parse_destructure()?;
+            self.expect_token(TokenKind::Eq)?;
+            let value = self.parse_expression()?;
+            return Ok(expr!(
+                ExprDecl::Destructuring(Some(reassignable), target, value),
+                pos
+            ));
+        }
         let ident = self.expect_identifier()?;
         let expr = if self.token.is(TokenKind::Eq) {
             self.expect_token(TokenKind::Eq)?;
@@ -119,6 +185,91 @@ impl<'a> Parser<'a> {
         Ok(expr!(ExprDecl::Var(reassignable, ident, expr), pos))
     }
 
+    /// Variables of a destructuring declaration.
+    fn parse_destructure(&mut self) -> Result<Destructure, MsgWithPos> {
+        let default = |p: &mut Parser| {
+            if p.token.is(TokenKind::Eq) {
+                p.advance_token()?;
+                Ok(Some(p.parse_binary(0)?))
+            } else {
+                Ok(None)
+            }
+        };
+        match self.token.kind {
+            TokenKind::LBracket => {
+                self.advance_token()?;
+                let elements = self.parse_comma_list(TokenKind::RBracket, |p| {
+                    let target = p.parse_destructure()?;
+                    Ok((target, default(p)?))
+                })?;
+                Ok(Destructure::Array(elements))
+            }
+            TokenKind::LBrace => {
+                self.advance_token()?;
+                let fields = self.parse_comma_list(TokenKind::RBrace, |p| {
+                    let pos = p.token.position.clone();
+                    let key = p.expect_identifier()?;
+                    let target = if p.token.is(TokenKind::Colon) {
+                        p.advance_token()?;
+                        p.parse_destructure()?
+                    } else {
+                        Destructure::Target(expr!(
+                            ExprDecl::Const(Constant::Ident(key.clone())),
+                            pos
+                        ))
+                    };
+                    Ok((key, target, default(p)?))
+                })?;
+                Ok(Destructure::Object(fields))
+            }
+            _ => {
+                let pos = self.token.position.clone();
+                let name = self.expect_identifier()?;
+                Ok(Destructure::Target(expr!(
+                    ExprDecl::Const(Constant::Ident(name)),
+                    pos
+                )))
+            }
+        }
+    }
+
+    /// Turn the array literal on the left of `=` into assignment targets,
+    /// elements written as `target = default` have a default.
+    fn assignment_targets(&self, e: P<Expr>) -> Result<Destructure, MsgWithPos> {
+        match &e.decl {
+            ExprDecl::ArrayLiteral(elements) => {
+                let mut targets = vec![];
+                for element in elements.iter() {
+                    targets.push(match &element.decl {
+                        ExprDecl::Assign(target, default) => (
+                            self.assignment_targets(target.clone())?,
+                            Some(default.clone()),
+                        ),
+                        _ => (self.assignment_targets(element.clone())?, None),
+                    });
+                }
+                Ok(Destructure::Array(targets))
+            }
+            ExprDecl::Const(Constant::Ident(_))
+            | ExprDecl::Const(Constant::This)
+            | ExprDecl::Field(..)
+            | ExprDecl::Array(..) => Ok(Destructure::Target(e)),
+            _ => Err(MsgWithPos::new(
+                self.lexer.path(),
+                e.pos.clone(),
+                Msg::InvalidDestructuringTarget,
+            )),
+        }
+    }
+
+    fn parse_const(&mut self) -> EResult {
+        let pos = self.expect_token(TokenKind::Const)?.position;
+        let ident = self.expect_identifier()?;
+        self.expect_token(TokenKind::Eq)?;
+        let expr = self.parse_expression()?;
+        Ok(expr!(ExprDecl::ConstDecl(ident, expr), pos))
+    }
+
     fn parse_return(&mut self) -> EResult {
         let pos = self.expect_token(TokenKind::Return)?.position;
         let expr = self.parse_expression()?;
@@ -131,6 +282,7 @@ impl<'a> Parser<'a> {
 
             TokenKind::Match => self.parse_match(),
             TokenKind::Let | TokenKind::Var => self.parse_let(),
+            TokenKind::Const => self.parse_const(),
             TokenKind::Yield => self.parse_yield(),
             TokenKind::LBrace => self.parse_block(),
             TokenKind::If => self.parse_if(),
@@ -143,6 +295,7 @@ impl<'a> Parser<'a> {
             TokenKind::Throw => self.parse_throw(),
             TokenKind::Import => self.parse_import(),
             TokenKind::Try => self.parse_try(),
+            TokenKind::Type => self.parse_type(),
             _ => self.parse_binary(0),
         };
 
@@ -160,6 +313,84 @@ impl<'a> Parser<'a> {
         let catch = self.parse_expression()?;
         Ok(expr!(ExprDecl::Try(expr, name, catch), pos))
     }
+    fn parse_type(&mut self) -> EResult {
+        let pos = self.expect_token(TokenKind::Type)?.position;
+        let name = self.expect_identifier()?;
+        let parent = if self.token.is(TokenKind::Colon) {
+            self.advance_token()?;
+            Some(self.parse_primary()?)
+        } else {
+            None
+        };
+        self.expect_token(TokenKind::LBrace)?;
+        let super_available = mem::replace(&mut self.super_available, parent.is_some());
+        let mut methods = vec![];
+        while !self.token.is(TokenKind::RBrace) && !self.token.is_eof() {
+            let pos = self.token.position.clone();
+            // Methods may be written as `name(...)`, `fn name(...)` or `function name(...)`.
+            if self.token.is(TokenKind::Fun) {
+                self.advance_token()?;
+            } else if self.token.kind == TokenKind::Identifier("fn".to_owned()) {
+                self.advance_token()?;
+                if self.token.is(TokenKind::LParen) {
+                    let method = self.parse_function_rest(pos)?;
+                    methods.push(("fn".to_owned(), method));
+                    continue;
+                }
+            }
+            let mut name = self.expect_identifier()?;
+            // `get name() {...}` and `set name(value) {...}` define accessors,
+            // stored as "get name" and "set name".
+            if (name == "get" || name == "set") && !self.token.is(TokenKind::LParen) {
+                name = format!("{} {}", name, self.expect_identifier()?);
+            }
+            let method = self.parse_function_rest(pos)?;
+            methods.push((name, method));
+            if self.token.is(TokenKind::Semicolon) || self.token.is(TokenKind::Comma) {
+                self.advance_token()?;
+            }
+        }
+        self.super_available = super_available;
+        self.expect_token(TokenKind::RBrace)?;
+        Ok(expr!(ExprDecl::Type(name, parent, methods), pos))
+    }
+
+    /// `super.method(args)`, calls the parent type's method with the current `this`.
+    fn parse_super(&mut self) -> EResult {
+        let pos = self.token.position.clone();
+        if !self.super_available {
+            return Err(MsgWithPos::new(
+                self.lexer.path(),
+                pos,
+                Msg::SuperUnavailable,
+            ));
+        }
+        self.advance_token()?;
+        let needs_call = |p: &Self| {
+            Err(MsgWithPos::new(
+                p.lexer.path(),
+                pos.clone(),
+                Msg::SuperNeedsMethodCall,
+            ))
+        };
+        if !self.token.is(TokenKind::Dot) {
+            return needs_call(self);
+        }
+        self.advance_token()?;
+        let method = self.expect_identifier()?;
+        if !self.token.is(TokenKind::LParen) {
+            return needs_call(self);
+        }
+        self.advance_token()?;
+        let args = self.parse_arguments()?;
+        let this = expr!(
+            ExprDecl::Const(Constant::Ident("super".to_owned())),
+            pos.clone()
+        );
+        let field = expr!(ExprDecl::Field(this, method), pos.clone());
+        Ok(expr!(ExprDecl::Call(field, args), pos))
+    }
+
     fn parse_self(&mut self) -> EResult {
         let pos = self.expect_token(TokenKind::This)?.position;
         Ok(expr!(ExprDecl::Const(Constant::This), pos))
@@ -199,13 +430,12 @@ impl<'a> Parser<'a> {
             let name = self.expect_identifier()?;
             Ok(expr!(ExprDecl::ForIn(name, in_, block), pos))
         } else {
-            self.expect_token(TokenKind::Semicolon)?;
-
+            // `parse_expression` already consumed the `;` after the
+            // initializer and the condition.
             let cond = self.parse_expression()?;
-            self.expect_token(TokenKind::Semicolon)?;
             let then = self.parse_expression()?;
 
-            let block = self.parse_expression()?;
+            let block = self.parse_block()?;
             Ok(expr!(ExprDecl::For(decl, cond, then, block), pos))
         }
     }
@@ -221,25 +451,162 @@ impl<'a> Parser<'a> {
         let pos = self.expect_token(TokenKind::Match)?.position;
         let value = self.parse_expression()?;
         self.expect_token(TokenKind::LBrace)?;
-        let mut data = vec![];
-        let mut or = None;
+        let mut arms = vec![];
         while !self.token.is(TokenKind::RBrace) && !self.token.is_eof() {
-            if self.token.is(TokenKind::Underscore) {
-                self.expect_token(TokenKind::Underscore)?;
-                self.expect_token(TokenKind::Arrow)?;
-                let expr = self.parse_expression()?;
-                or = Some(expr);
-                continue;
-            }
-            let cond = self.parse_expression()?;
+            let pattern = self.parse_pattern()?;
+            let guard = if self.token.is(TokenKind::If) {
+                self.advance_token()?;
+                Some(self.parse_binary(0)?)
+            } else {
+                None
+            };
             self.expect_token(TokenKind::Arrow)?;
             let expr = self.parse_expression()?;
-            data.push((cond, expr));
+            arms.push((pattern, guard, expr));
+            if self.token.is(TokenKind::Comma) {
+                self.advance_token()?;
+            }
         }
 
         self.expect_token(TokenKind::RBrace)?;
 
-        Ok(expr!(ExprDecl::Switch(value, data, or), pos))
+        Ok(expr!(ExprDecl::Match(value, arms), pos))
+    }
+
+    fn parse_pattern(&mut self) -> Result<Pattern, MsgWithPos> {
+        let pos = self.token.position.clone();
+        let mut alternatives = vec![self.parse_single_pattern()?];
+        while self.token.is(TokenKind::BitOr) {
+            self.advance_token()?;
+            alternatives.push(self.parse_single_pattern()?);
+        }
+        if alternatives.len() == 1 {
+            return Ok(alternatives.pop().unwrap());
+        }
+        let bindings = |pattern: &Pattern| {
+            let mut names = vec![];
+            pattern.bindings(&mut names);
+            names.sort();
+            names
+        };
+        let first = bindings(&alternatives[0]);
+        if alternatives
+            .iter()
+            .any(|pattern| bindings(pattern) != first)
+        {
+            return Err(MsgWithPos::new(
+                self.lexer.path(),
+                pos,
+                Msg::PatternBindingsDiffer,
+            ));
+        }
+        Ok(Pattern::Or(alternatives))
+    }
+
+    fn parse_single_pattern(&mut self) -> Result<Pattern, MsgWithPos> {
+        match self.token.kind {
+            TokenKind::Underscore => {
+                self.advance_token()?;
+                Ok(Pattern::Wildcard)
+            }
+            TokenKind::Identifier(_) => {
+                let pos = self.token.position.clone();
+                let name = self.expect_identifier()?;
+                if !self.token.is(TokenKind::LParen) {
+                    return Ok(Pattern::Bind(name));
+                }
+                const TYPES: &[&str] = &[
+                    "bool", "int", "float", "char", "string", "array", "object", "function",
+                ];
+                if !TYPES.contains(&name.as_str()) {
+                    return Err(MsgWithPos::new(
+                        self.lexer.path(),
+                        pos,
+                        Msg::UnknownType(name),
+                    ));
+                }
+                self.advance_token()?;
+                let pattern = self.parse_pattern()?;
+                self.expect_token(TokenKind::RParen)?;
+                Ok(Pattern::Type(name, Box::new(pattern)))
+            }
+            TokenKind::LBracket => {
+                self.advance_token()?;
+                let elements = self.parse_comma_list(TokenKind::RBracket, |p| {
+                    if p.token.is(TokenKind::DotDotDot) {
+                        p.advance_token()?;
+                        let pos = p.token.position.clone();
+                        Ok(Err((p.expect_identifier()?, pos)))
+                    } else {
+                        Ok(Ok(p.parse_pattern()?))
+                    }
+                })?;
+                let mut patterns = vec![];
+                let mut rest: Option<(String, Position)> = None;
+                for element in elements {
+                    match (element, &rest) {
+                        (_, Some((_, pos))) => {
+                            return Err(MsgWithPos::new(
+                                self.lexer.path(),
+                                pos.clone(),
+                                Msg::RestNotLast,
+                            ))
+                        }
+                        (Ok(pattern), None) => patterns.push(pattern),
+                        (Err(name), None) => rest = Some(name),
+                    }
+                }
+                Ok(Pattern::Array(patterns, rest.map(|(name, _)| name)))
+            }
+            TokenKind::LBrace => {
+                self.advance_token()?;
+                let fields = self.parse_comma_list(TokenKind::RBrace, |p| {
+                    let key = p.expect_identifier()?;
+                    if p.token.is(TokenKind::Colon) {
+                        p.advance_token()?;
+                        Ok((key, p.parse_pattern()?))
+                    } else {
+                        Ok((key.clone(), Pattern::Bind(key)))
+                    }
+                })?;
+                Ok(Pattern::Object(fields))
+            }
+            TokenKind::Sub
+            | TokenKind::LitInt(..)
+            | TokenKind::LitFloat(_)
+            | TokenKind::LitChar(_)
+            | TokenKind::String(_)
+            | TokenKind::True
+            | TokenKind::False
+            | TokenKind::Nil => {
+                let negative = self.token.is(TokenKind::Sub);
+                if negative {
+                    self.advance_token()?;
+                    match self.token.kind {
+                        TokenKind::LitInt(..) => self.negated = true,
+                        TokenKind::LitFloat(_) => (),
+                        _ => {
+                            return Err(MsgWithPos::new(
+                                self.lexer.path(),
+                                self.token.position.clone(),
+                                Msg::ExpectedPattern(self.token.name()),
+                            ))
+                        }
+                    }
+                }
+                let literal = self.parse_factor()?;
+                match &literal.decl {
+                    ExprDecl::Const(c) if negative => Ok(Pattern::Literal(c.negate().unwrap())),
+                    ExprDecl::Const(c) => Ok(Pattern::Literal(c.clone())),
+                    _ => unreachable!(),
+                }
+            }
+            _ => Err(MsgWithPos::new(
+                self.lexer.path(),
+                self.token.position.clone(),
+                Msg::ExpectedPattern(self.token.name()),
+            )),
+        }
     }
 
     fn parse_if(&mut self) -> EResult {
@@ -278,12 +645,28 @@ impl<'a> Parser<'a> {
     fn create_binary(&mut self, tok: Token, left: P<Expr>, right: P<Expr>) -> P<Expr> {
         let op = match tok.kind {
             TokenKind::Eq => return expr!(ExprDecl::Assign(left, right), tok.position),
+            TokenKind::AddEq
+            | TokenKind::SubEq
+            | TokenKind::MulEq
+            | TokenKind::DivEq
+            | TokenKind::ModEq
+            | TokenKind::LtLtEq
+            | TokenKind::GtGtEq
+            | TokenKind::BitOrEq
+            | TokenKind::BitAndEq
+            | TokenKind::CaretEq => {
+                let name = tok.kind.name();
+                let op = name[..name.len() - 1].to_owned();
+                return expr!(ExprDecl::AssignOp(op, left, right), tok.position);
+            }
             TokenKind::Or => "||",
             TokenKind::And => "&&",
             TokenKind::BitOr => "|",
             TokenKind::BitAnd => "&",
             TokenKind::EqEq => "==",
             TokenKind::Ne => "!=",
+            TokenKind::EqEqEq => "===",
+            TokenKind::NeEq => "!==",
             TokenKind::Lt => "<",
             TokenKind::Gt => ">",
             TokenKind::Le => "<=",
@@ -309,9 +692,21 @@ impl<'a> Parser<'a> {
             let right_precedence = match self.token.kind {
                 TokenKind::Or => 1,
                 TokenKind::And => 2,
-                TokenKind::Eq => 3,
+                TokenKind::Eq
+                | TokenKind::AddEq
+                | TokenKind::SubEq
+                | TokenKind::MulEq
+                | TokenKind::DivEq
+                | TokenKind::ModEq
+                | TokenKind::LtLtEq
+                | TokenKind::GtGtEq
+                | TokenKind::BitOrEq
+                | TokenKind::BitAndEq
+                | TokenKind::CaretEq => 3,
                 TokenKind::EqEq
                 | TokenKind::Ne
+                | TokenKind::EqEqEq
+                | TokenKind::NeEq
                 | TokenKind::Lt
                 | TokenKind::Le
                 | TokenKind::Gt
@@ -334,7 +729,13 @@ impl<'a> Parser<'a> {
             let tok = self.advance_token()?;
             left = {
                 let right = self.parse_binary(right_precedence)?;
-                self.create_binary(tok, left, right)
+                match left.decl {
+                    ExprDecl::ArrayLiteral(_) if tok.is(TokenKind::Eq) => {
+                        let targets = self.assignment_targets(left)?;
+                        expr!(ExprDecl::Destructuring(None, targets, right), tok.position)
+                    }
+                    _ => self.create_binary(tok, left, right),
+                }
             };
         }
     }
@@ -346,17 +747,28 @@ impl<'a> Parser<'a> {
     }
     pub fn parse_unary(&mut self) -> EResult {
         match self.token.kind {
-            TokenKind::Add | TokenKind::Sub | TokenKind::Not => {
+            TokenKind::Add | TokenKind::Sub | TokenKind::Not | TokenKind::Tilde => {
                 let tok = self.advance_token()?;
                 let op = match tok.kind {
                     TokenKind::Add => String::from("+"),
                     TokenKind::Sub => String::from("-"),
                     TokenKind::Not => String::from("!"),
+                    TokenKind::Tilde => String::from("~"),
                     _ => unreachable!(),
                 };
+                self.negated = op == "-" && matches!(self.token.kind, TokenKind::LitInt(..));
                 let expr = self.parse_primary()?;
                 Ok(expr!(ExprDecl::Unop(op, expr), tok.position))
             }
+            TokenKind::AddAdd | TokenKind::SubSub => {
+                let tok = self.advance_token()?;
+                let op = if tok.is(TokenKind::AddAdd) { "+" } else { "-" };
+                let expr = self.parse_primary()?;
+                Ok(expr!(
+                    ExprDecl::Incr(op.to_owned(), true, expr),
+                    tok.position
+                ))
+            }
             _ => self.parse_primary(),
         }
     }
@@ -382,8 +794,22 @@ impl<'a> Parser<'a> {
                     let ident = self.expect_identifier()?;
                     expr!(ExprDecl::Field(left, ident), tok.position)
                 }
+                // `o::f` is `$method(o, "f")`, `o.f` bound to `o`.
+                TokenKind::Sep => {
+                    let tok = self.advance_token()?;
+                    let ident = self.expect_identifier()?;
+                    let pos = tok.position;
+                    let method = expr!(
+                        ExprDecl::Const(Constant::Builtin("method".to_owned())),
+                        pos.clone()
+                    );
+                    let name = expr!(ExprDecl::Const(Constant::Str(ident)), pos.clone());
+                    expr!(ExprDecl::Call(method, vec![left, name]), pos)
+                }
 
-                TokenKind::LBracket => {
+                // Like `++`, a `[` on the next line starts a new expression,
+                // e.g. an array pattern in the next `match` arm.
+                TokenKind::LBracket if self.token.position.line == self.prev_line => {
                     let tok = self.advance_token()?;
                     let val_or_index = self.parse_expression()?;
                     if self.token.is(TokenKind::Comma) {
@@ -400,14 +826,22 @@ impl<'a> Parser<'a> {
                         expr!(ExprDecl::Array(left, val_or_index), tok.position)
                     }
                 }
+
+                // A `++` or `--` on the next line starts a new expression.
+                TokenKind::AddAdd | TokenKind::SubSub
+                    if self.token.position.line == self.prev_line =>
+                {
+                    let tok = self.advance_token()?;
+                    let op = if tok.is(TokenKind::AddAdd) { "+" } else { "-" };
+                    expr!(ExprDecl::Incr(op.to_owned(), false, left), tok.position)
+                }
                 _ => {
                     if self.token.is(TokenKind::LParen) {
                         let expr = left;
 
                         self.expect_token(TokenKind::LParen)?;
 
-                        let args =
-                            self.parse_comma_list(TokenKind::RParen, |p| p.parse_expression())?;
+                        let args = self.parse_arguments()?;
 
                         expr!(ExprDecl::Call(expr, args), expr.pos.clone())
                     } else {
@@ -418,6 +852,18 @@ impl<'a> Parser<'a> {
         }
     }
 
+    /// Arguments of a call after the `(`, `...e` spreads array `e`.
+    fn parse_arguments(&mut self) -> Result<Vec<P<Expr>>, MsgWithPos> {
+        self.parse_comma_list(TokenKind::RParen, |p| {
+            if p.token.is(TokenKind::DotDotDot) {
+                let pos = p.advance_token()?.position;
+                Ok(expr!(ExprDecl::Spread(p.parse_expression()?), pos))
+            } else {
+                p.parse_expression()
+            }
+        })
+    }
+
     fn expect_identifier(&mut self) -> Result<String, MsgWithPos> {
         let tok = self.advance_token()?;
 
@@ -468,20 +914,24 @@ impl<'a> Parser<'a> {
 
     fn advance_token(&mut self) -> Result<Token, MsgWithPos> {
         let tok = self.lexer.read_token()?;
+        self.prev_line = self.token.position.line;
 
         Ok(mem::replace(&mut self.token, tok))
     }
 
+    /// `|a, b| expr` is `function(a, b) expr`; `||` starts a lambda without
+    /// parameters. Default values stop at `|`, parenthesize them otherwise.
     fn parse_lambda(&mut self) -> EResult {
-        unimplemented!()
-        /*let tok = self.advance_token()?;
-        let params = if tok.kind == TokenKind::Or {
-            vec![]
+        let tok = self.advance_token()?;
+        let params = if tok.is(TokenKind::Or) {
+            Params::default()
         } else {
-            self.parse_comma_list(TokenKind::BitOr, |f| f.parse_function_param())?
+            self.parse_params(TokenKind::BitOr, 6)?
         };
-        let block = self.parse_expression()?;
-        Ok(expr!(ExprDecl::Lambda(params, block), tok.position))*/
+        let super_available = mem::replace(&mut self.super_available, false);
+        let function = self.parse_function_body(params, tok.position);
+        self.super_available = super_available;
+        function
     }
 
     pub fn parse_factor(&mut self) -> EResult {
@@ -489,11 +939,13 @@ impl<'a> Parser<'a> {
             TokenKind::Fun => self.parse_function(),
 
             TokenKind::LParen => self.parse_parentheses(),
+            TokenKind::LBracket => self.parse_array_literal(),
             TokenKind::LitChar(_) => self.lit_char(),
             TokenKind::LitInt(_, _, _) => self.lit_int(),
             TokenKind::LitFloat(_) => self.lit_float(),
-            TokenKind::String(_) => self.lit_str(),
+            TokenKind::String(_) | TokenKind::Template(_) => self.lit_str(),
             TokenKind::Builtin(_) => self.parse_builtin(),
+            TokenKind::Identifier(ref name) if name == "super" => self.parse_super(),
             TokenKind::Identifier(_) => self.ident(),
             TokenKind::This => self.parse_self(),
             TokenKind::BitOr | TokenKind::Or => self.parse_lambda(),
@@ -518,10 +970,19 @@ impl<'a> Parser<'a> {
             unreachable!()
         };
         let pos = self.advance_token()?.position;
+        if b == "args" {
+            self.uses_args = true;
+        }
 
         Ok(expr!(ExprDecl::Const(Constant::Builtin(b.clone())), pos))
     }
 
+    fn parse_array_literal(&mut self) -> EResult {
+        let pos = self.expect_token(TokenKind::LBracket)?.position;
+        let elements = self.parse_comma_list(TokenKind::RBracket, |p| p.parse_expression())?;
+        Ok(expr!(ExprDecl::ArrayLiteral(elements), pos))
+    }
+
     fn parse_parentheses(&mut self) -> EResult {
         let pos = self.advance_token()?.position;
         let expr = self.parse_expression()?;
@@ -552,13 +1013,34 @@ impl<'a> Parser<'a> {
     fn lit_int(&mut self) -> EResult {
         let tok = self.advance_token()?;
         let pos = tok.position.clone();
-        if let TokenKind::LitInt(i, _, _) = tok.kind {
-            Ok(expr!(
-                ExprDecl::Const(Constant::Int(i.parse().unwrap())),
-                pos
-            ))
-        } else {
-            unreachable!()
+        let (digits, base, suffix) = match &tok.kind {
+            TokenKind::LitInt(digits, base, suffix) => (digits, *base, *suffix),
+            _ => unreachable!(),
+        };
+        let digits: String = digits.chars().filter(|&ch| ch != '_').collect();
+        let negated = std::mem::replace(&mut self.negated, false);
+        let (max, ty) = match suffix {
+            IntSuffix::Byte => (u8::max_value() as u64, "byte"),
+            IntSuffix::Int => (i64::max_value() as u64, "int"),
+            IntSuffix::Long => {
+                let value = BigInt::parse(&digits, base.num()).unwrap();
+                return Ok(expr!(ExprDecl::Const(Constant::from_bigint(value)), pos));
+            }
+        };
+        match u64::from_str_radix(&digits, base.num()) {
+            Ok(value) if value <= max => {
+                Ok(expr!(ExprDecl::Const(Constant::Int(value as i64)), pos))
+            }
+            // Negating this gives `i64::MIN`.
+            Ok(value) if negated && ty == "int" && value == max + 1 => {
+                let value = BigInt::parse(&digits, base.num()).unwrap();
+                Ok(expr!(ExprDecl::Const(Constant::from_bigint(value)), pos))
+            }
+            _ => Err(MsgWithPos::new(
+                self.lexer.path(),
+                pos,
+                Msg::NumberOverflow(ty.into()),
+            )),
         }
     }
 
@@ -575,26 +1057,80 @@ impl<'a> Parser<'a> {
     fn lit_float(&mut self) -> EResult {
         let tok = self.advance_token()?;
         let pos = tok.position.clone();
-        if let TokenKind::LitFloat(c) = tok.kind {
-            Ok(expr!(
-                ExprDecl::Const(Constant::Float(c.parse().unwrap())),
-                pos
-            ))
+        let number = match &tok.kind {
+            TokenKind::LitFloat(number) => number,
+            _ => unreachable!(),
+        };
+        let digits: String = number.chars().filter(|&ch| ch != '_').collect();
+        let value = if digits.starts_with("0x") {
+            parse_hex_float(&digits[2..])
         } else {
-            unreachable!()
+            digits.parse().unwrap()
+        };
+        if value.is_infinite() {
+            return Err(MsgWithPos::new(
+                self.lexer.path(),
+                pos,
+                Msg::NumberOverflow("float".into()),
+            ));
         }
+        Ok(expr!(ExprDecl::Const(Constant::Float(value)), pos))
     }
 
     fn lit_str(&mut self) -> EResult {
         let tok = self.advance_token()?;
         let pos = tok.position.clone();
-        if let TokenKind::String(s) = tok.kind {
-            Ok(expr!(ExprDecl::Const(Constant::Str(s)), pos))
-        } else {
-            unreachable!()
+        match tok.kind {
+            TokenKind::String(s) => Ok(expr!(ExprDecl::Const(Constant::Str(s)), pos)),
+            TokenKind::Template(parts) => {
+                // "a${x}b" is "a" + $string(x) + "b".
+                let mut result: Option<P<Expr>> = None;
+                for part in parts {
+                    let part = match part {
+                        StringPart::Str(s) => expr!(ExprDecl::Const(Constant::Str(s)), pos.clone()),
+                        StringPart::Expr(src, expr_pos) => {
+                            let value = self.parse_interpolation(&src, &expr_pos)?;
+                            let string = Constant::Builtin("string".to_owned());
+                            let callee = expr!(ExprDecl::Const(string), expr_pos.clone());
+                            expr!(ExprDecl::Call(callee, vec![value]), expr_pos)
+                        }
+                    };
+                    result = Some(match result {
+                        Some(left) => {
+                            expr!(ExprDecl::Binop("+".to_owned(), left, part), pos.clone())
+                        }
+                        None => part,
+                    });
+                }
+                Ok(result.unwrap())
+            }
+            _ => unreachable!(),
         }
     }
 
+    /// Parses the source of a `${...}` with a lexer positioned where it
+    /// starts in the file.
+    fn parse_interpolation(&mut self, src: &str, pos: &Position) -> EResult {
+        let lexer = mem::replace(
+            &mut self.lexer,
+            Lexer::new(Reader::from_string_at(src, pos)),
+        );
+        let token = self.token.clone();
+        let prev_line = self.prev_line;
+        let result = self.parse_interpolated_expression();
+        self.lexer = lexer;
+        self.token = token;
+        self.prev_line = prev_line;
+        result
+    }
+
+    fn parse_interpolated_expression(&mut self) -> EResult {
+        self.advance_token()?;
+        let expr = self.parse_expression()?;
+        self.expect_token(TokenKind::End)?;
+        Ok(expr)
+    }
+
     fn ident(&mut self) -> EResult {
         let pos = self.token.position.clone();
         let ident = self.expect_identifier()?;
@@ -606,3 +1142,60 @@ impl<'a> Parser<'a> {
         Ok(expr!(ExprDecl::Const(Constant::Ident(ident)), pos))
     }
 }
+
+/// Value of hex digits with an optional fraction and binary exponent, like
+/// `1.8p3`.
+fn parse_hex_float(number: &str) -> f64 {
+    let (mantissa, exponent) = match number.find('p') {
+        Some(p) => (
+            &number[..p],
+            number[p + 1..].parse().unwrap_or(i32::max_value()),
+        ),
+        None => (number, 0),
+    };
+    let mut value = 0.0;
+    let mut exponent = exponent;
+    let mut fraction = false;
+    for ch in mantissa.chars() {
+        if ch == '.' {
+            fraction = true;
+        } else {
+            value = value * 16.0 + ch.to_digit(16).unwrap() as f64;
+            if fraction {
+                exponent = exponent.saturating_sub(4);
+            }
+        }
+    }
+    value * 2f64.powi(exponent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_err(src: &str) -> (Msg, u32, u32) {
+        let mut ast = vec![];
+        let err = Parser::new(Reader::from_string(src), &mut ast)
+            .parse()
+            .unwrap_err();
+        (err.msg, err.pos.line, err.pos.column)
+    }
+
+    #[test]
+    fn number_literals_are_checked() {
+        let overflow = |ty: &str| Msg::NumberOverflow(ty.into());
+        assert_eq!(parse_err("var x = 256b"), (overflow("byte"), 1, 9));
+        assert_eq!(
+            parse_err("var x = 1\n  + 9_223_372_036_854_775_808"),
+            (overflow("int"), 2, 5)
+        );
+        assert_eq!(parse_err("var x = 1e999"), (overflow("float"), 1, 9));
+        assert_eq!(parse_err("var x = 0x"), (Msg::InvalidNumber, 1, 9));
+        assert_eq!(parse_err("var x = 1e+"), (Msg::InvalidNumber, 1, 9));
+        assert_eq!(parse_err("var x = 12abc"), (Msg::InvalidNumber, 1, 9));
+        assert_eq!(
+            parse_err("var x = -(9223372036854775808)"),
+            (overflow("int"), 1, 11)
+        );
+    }
+}
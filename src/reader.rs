@@ -39,6 +39,15 @@ impl Reader {
         common_init("<<code>>".into(), src.into())
     }
 
+    /// Reader for a piece of source found at `pos` of another file, so
+    /// positions point into that file.
+    pub fn from_string_at(src: &str, pos: &Position) -> Reader {
+        let mut reader = common_init(pos.file.to_string(), src.into());
+        reader.line = pos.line as usize;
+        reader.col = pos.column as usize;
+        reader
+    }
+
     pub fn set_tabwidth(&mut self, width: usize) {
         self.tabwidth = width;
     }
@@ -0,0 +1,567 @@
+//! Resolves names before code generation: rejects reassignment of `let`
+//! bindings and `const` declarations, requires `let` bindings to be
+//! initialized and folds `const` values into the places they are used.
+//!
+//! Scopes follow code generation: blocks, function bodies and catch blocks
+//! open a new scope and an inner declaration shadows an outer one. A name
+//! that isn't bound in any scope is a module global, reading a global that is
+//! never assigned anywhere in the program is an error. Shadowing and unused
+//! variables are reported as warnings.
+
+use crate::ast::*;
+use crate::msg::{Msg, MsgWithPos};
+use crate::token::Position;
+use crate::P;
+use std::collections::{HashMap, HashSet};
+
+#[derive(Clone)]
+enum Binding {
+    Var,
+    Let,
+    Param,
+    Const(ExprDecl),
+    /// Catch variables and types, never reported as unused.
+    Hidden,
+}
+
+struct Local {
+    binding: Binding,
+    pos: Position,
+    used: bool,
+}
+
+struct Resolver {
+    scopes: Vec<HashMap<String, Local>>,
+    /// Globals assigned somewhere in the program.
+    assigned: HashSet<String>,
+    /// Reads of names that aren't bound in any scope.
+    unresolved: Vec<(String, Position)>,
+    warnings: Vec<MsgWithPos>,
+}
+
+type RResult = Result<P<Expr>, MsgWithPos>;
+
+/// Resolve the top-level expressions of a program in place and return the
+/// warnings found, ordered by position.
+pub fn resolve(ast: &mut Vec<P<Expr>>) -> Result<Vec<MsgWithPos>, MsgWithPos> {
+    let mut resolver = Resolver {
+        scopes: vec![],
+        assigned: HashSet::new(),
+        unresolved: vec![],
+        warnings: vec![],
+    };
+    *ast = resolver.scoped(|r| r.exprs(ast))?;
+    for (name, pos) in resolver.unresolved.iter() {
+        if !resolver.assigned.contains(name) {
+            return Err(error(pos, Msg::UnknownIdentifier(name.clone())));
+        }
+    }
+    let mut warnings = resolver.warnings;
+    warnings.sort_by_key(|w| (w.pos.line, w.pos.column));
+    Ok(warnings)
+}
+
+fn error(pos: &Position, msg: Msg) -> MsgWithPos {
+    MsgWithPos::new(pos.file.to_string(), pos.clone(), msg)
+}
+
+fn expr(pos: &Position, decl: ExprDecl) -> P<Expr> {
+    P(Expr {
+        pos: pos.clone(),
+        decl,
+    })
+}
+
+/// The literal `e` evaluates to, `e` must only contain literals and already
+/// folded constants.
+fn literal(e: &Expr) -> Option<ExprDecl> {
+    match &e.decl {
+        ExprDecl::Const(Constant::Ident(_))
+        | ExprDecl::Const(Constant::This)
+        | ExprDecl::Const(Constant::Builtin(_)) => None,
+        ExprDecl::Const(_) => Some(e.decl.clone()),
+        ExprDecl::Paren(e) => literal(e),
+        ExprDecl::Unop(op, e) => {
+            let value = literal(e)?;
+            let op: &str = op;
+            Some(ExprDecl::Const(match (op, value) {
+                ("+", ExprDecl::Const(c @ Constant::Int(_)))
+                | ("+", ExprDecl::Const(c @ Constant::BigInt(_)))
+                | ("+", ExprDecl::Const(c @ Constant::Float(_))) => c,
+                ("-", ExprDecl::Const(c)) => c.negate()?,
+                ("~", ExprDecl::Const(Constant::Int(x))) => Constant::Int(!x),
+                ("!", ExprDecl::Const(Constant::True)) => Constant::False,
+                ("!", ExprDecl::Const(Constant::False)) => Constant::True,
+                _ => return None,
+            }))
+        }
+        _ => None,
+    }
+}
+
+impl Resolver {
+    fn lookup(&self, name: &str) -> Option<&Binding> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name))
+            .map(|local| &local.binding)
+    }
+
+    /// Binding of `name` at a use site.
+    fn read(&mut self, name: &str, pos: &Position) -> Option<Binding> {
+        let local = self
+            .scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(name));
+        match local {
+            Some(local) => {
+                local.used = true;
+                Some(local.binding.clone())
+            }
+            None => {
+                // `super` is bound by code generation inside methods.
+                if name != "super" {
+                    self.unresolved.push((name.to_owned(), pos.clone()));
+                }
+                None
+            }
+        }
+    }
+
+    fn warn(&mut self, pos: &Position, msg: Msg) {
+        self.warnings.push(error(pos, msg));
+    }
+
+    fn declare(&mut self, name: &str, binding: Binding, pos: &Position) {
+        let in_scope = self.scopes.last().unwrap().contains_key(name);
+        if !in_scope {
+            let shadowed = match self.lookup(name) {
+                Some(Binding::Param) => Some(Msg::ShadowParam(name.to_owned())),
+                Some(Binding::Const(_)) => Some(Msg::ShadowConst(name.to_owned())),
+                Some(_) => Some(Msg::ShadowVar(name.to_owned())),
+                None if self.assigned.contains(name) => Some(Msg::ShadowGlobal(name.to_owned())),
+                None => None,
+            };
+            if let Some(msg) = shadowed {
+                self.warn(pos, msg);
+            }
+        }
+        let local = Local {
+            binding,
+            pos: pos.clone(),
+            used: false,
+        };
+        self.scopes
+            .last_mut()
+            .unwrap()
+            .insert(name.to_owned(), local);
+    }
+
+    fn scoped<T>(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<T, MsgWithPos>,
+    ) -> Result<T, MsgWithPos> {
+        self.scopes.push(HashMap::new());
+        let result = f(self);
+        let scope = self.scopes.pop().unwrap();
+        for (name, local) in scope.iter() {
+            if let Binding::Var | Binding::Let = local.binding {
+                if !local.used && !name.starts_with('_') {
+                    self.warn(&local.pos, Msg::UnusedVariable(name.clone()));
+                }
+            }
+        }
+        result
+    }
+
+    /// Resolve a list of expressions in the current scope, `const`
+    /// declarations are dropped once their value is known.
+    fn exprs(&mut self, v: &[P<Expr>]) -> Result<Vec<P<Expr>>, MsgWithPos> {
+        let mut result = Vec::with_capacity(v.len());
+        for e in v.iter() {
+            let e = self.expr(e)?;
+            if let ExprDecl::ConstDecl(..) = e.decl {
+                continue;
+            }
+            result.push(e);
+        }
+        Ok(result)
+    }
+
+    fn opt(&mut self, e: &Option<P<Expr>>) -> Result<Option<P<Expr>>, MsgWithPos> {
+        e.as_ref().map(|e| self.expr(e)).transpose()
+    }
+
+    /// Resolve a function, a default value can use the parameters before it.
+    fn function(
+        &mut self,
+        pos: &Position,
+        params: &Params,
+        body: &P<Expr>,
+    ) -> Result<(Params, P<Expr>), MsgWithPos> {
+        self.scoped(|r| {
+            let mut resolved = Params {
+                rest: params.rest.clone(),
+                uses_args: params.uses_args,
+                ..Default::default()
+            };
+            let names = params.names.iter().map(|(name, _)| name);
+            for (i, param) in names.chain(params.rest.iter()).enumerate() {
+                if r.scopes.last().unwrap().contains_key(param) {
+                    return Err(error(pos, Msg::IdentifierExists(param.clone())));
+                }
+                let default = match params.names.get(i) {
+                    Some((_, default)) => r.opt(default)?,
+                    None => None,
+                };
+                r.declare(param, Binding::Param, pos);
+                if i < params.names.len() {
+                    resolved.names.push((param.clone(), default));
+                }
+            }
+            Ok((resolved, r.expr(body)?))
+        })
+    }
+
+    /// Resolve the target of an assignment, the target itself must be
+    /// reassignable and isn't folded. `read` is set when the old value is used.
+    fn target(&mut self, e: &P<Expr>, read: bool) -> RResult {
+        match &e.decl {
+            ExprDecl::Const(Constant::Ident(name)) => {
+                let binding = if read {
+                    self.read(name, &e.pos)
+                } else {
+                    self.lookup(name).cloned()
+                };
+                match binding {
+                    Some(Binding::Let) => Err(error(&e.pos, Msg::LetReassigned)),
+                    Some(Binding::Const(_)) => Err(error(&e.pos, Msg::AssignmentToConst)),
+                    Some(_) => Ok(e.clone()),
+                    None => {
+                        if !read {
+                            self.assigned.insert(name.clone());
+                        }
+                        Ok(e.clone())
+                    }
+                }
+            }
+            _ => self.expr(e),
+        }
+    }
+
+    /// Resolve destructuring targets in order, declaring them with `binding`
+    /// or assigning them when it's `None`. A default can use earlier targets.
+    fn destructure(
+        &mut self,
+        target: &Destructure,
+        binding: &Option<Binding>,
+    ) -> Result<Destructure, MsgWithPos> {
+        Ok(match target {
+            Destructure::Target(e) => match (binding, &e.decl) {
+                (Some(binding), ExprDecl::Const(Constant::Ident(name))) => {
+                    self.declare(name, binding.clone(), &e.pos);
+                    Destructure::Target(e.clone())
+                }
+                _ => Destructure::Target(self.target(e, false)?),
+            },
+            Destructure::Array(elements) => {
+                let mut resolved = vec![];
+                for (target, default) in elements.iter() {
+                    let default = self.opt(default)?;
+                    resolved.push((self.destructure(target, binding)?, default));
+                }
+                Destructure::Array(resolved)
+            }
+            Destructure::Object(fields) => {
+                let mut resolved = vec![];
+                for (key, target, default) in fields.iter() {
+                    let default = self.opt(default)?;
+                    resolved.push((key.clone(), self.destructure(target, binding)?, default));
+                }
+                Destructure::Object(resolved)
+            }
+        })
+    }
+
+    fn expr(&mut self, e: &P<Expr>) -> RResult {
+        let pos = &e.pos;
+        let decl = match &e.decl {
+            ExprDecl::Const(Constant::Ident(name)) => match self.read(name, pos) {
+                Some(Binding::Const(value)) => value.clone(),
+                _ => return Ok(e.clone()),
+            },
+            ExprDecl::Var(reassignable, name, init) => {
+                if !reassignable && init.is_none() {
+                    return Err(error(pos, Msg::LetMissingInitialization));
+                }
+                let binding = if *reassignable {
+                    Binding::Var
+                } else {
+                    Binding::Let
+                };
+                // A function can call itself through the variable it's stored in.
+                let recursive = match init {
+                    Some(init) => matches!(init.decl, ExprDecl::Function(..)),
+                    None => false,
+                };
+                if recursive {
+                    self.declare(name, binding.clone(), pos);
+                }
+                let init = self.opt(init)?;
+                if !recursive {
+                    self.declare(name, binding, pos);
+                }
+                ExprDecl::Var(*reassignable, name.clone(), init)
+            }
+            ExprDecl::Destructuring(declaration, target, value) => {
+                let value = self.expr(value)?;
+                let binding = match declaration {
+                    Some(true) => Some(Binding::Var),
+                    Some(false) => Some(Binding::Let),
+                    None => None,
+                };
+                let target = self.destructure(target, &binding)?;
+                ExprDecl::Destructuring(*declaration, target, value)
+            }
+            ExprDecl::ArrayLiteral(elements) => ExprDecl::ArrayLiteral(self.exprs(elements)?),
+            ExprDecl::ConstDecl(name, value) => {
+                let value = self.expr(value)?;
+                match literal(&value) {
+                    Some(literal) => self.declare(name, Binding::Const(literal), pos),
+                    None => return Err(error(&value.pos, Msg::ConstValueExpected)),
+                }
+                ExprDecl::ConstDecl(name.clone(), value)
+            }
+            ExprDecl::Assign(e1, e2) => {
+                let e2 = self.expr(e2)?;
+                ExprDecl::Assign(self.target(e1, false)?, e2)
+            }
+            ExprDecl::AssignOp(op, e1, e2) => {
+                let e1 = self.target(e1, true)?;
+                ExprDecl::AssignOp(op.clone(), e1, self.expr(e2)?)
+            }
+            ExprDecl::Incr(op, prefix, e) => {
+                ExprDecl::Incr(op.clone(), *prefix, self.target(e, true)?)
+            }
+            ExprDecl::Block(v) => ExprDecl::Block(self.scoped(|r| r.exprs(v))?),
+            ExprDecl::Function(params, body) => {
+                let (params, body) = self.function(pos, params, body)?;
+                ExprDecl::Function(params, body)
+            }
+            ExprDecl::Spread(e) => ExprDecl::Spread(self.expr(e)?),
+            ExprDecl::Type(name, parent, methods) => {
+                let parent = self.opt(parent)?;
+                self.declare(name, Binding::Hidden, pos);
+                let mut resolved = vec![];
+                for (method, function) in methods.iter() {
+                    resolved.push((method.clone(), self.expr(function)?));
+                }
+                ExprDecl::Type(name.clone(), parent, resolved)
+            }
+            ExprDecl::Try(body, name, catch) => {
+                let body = self.expr(body)?;
+                let catch = self.scoped(|r| {
+                    r.declare(name, Binding::Hidden, pos);
+                    r.expr(catch)
+                })?;
+                ExprDecl::Try(body, name.clone(), catch)
+            }
+            ExprDecl::Paren(e) => ExprDecl::Paren(self.expr(e)?),
+            ExprDecl::Field(e, f) => ExprDecl::Field(self.expr(e)?, f.clone()),
+            ExprDecl::Call(e, args) => {
+                let e = self.expr(e)?;
+                ExprDecl::Call(e, self.exprs(args)?)
+            }
+            ExprDecl::Array(e, index) => {
+                let e = self.expr(e)?;
+                ExprDecl::Array(e, self.expr(index)?)
+            }
+            ExprDecl::While(cond, body) => {
+                let cond = self.expr(cond)?;
+                ExprDecl::While(cond, self.expr(body)?)
+            }
+            ExprDecl::For(init, cond, step, body) => self.scoped(|r| {
+                let init = r.expr(init)?;
+                let cond = r.expr(cond)?;
+                let step = r.expr(step)?;
+                Ok(ExprDecl::For(init, cond, step, r.expr(body)?))
+            })?,
+            ExprDecl::If(cond, then, otherwise) => {
+                let cond = self.expr(cond)?;
+                let then = self.expr(then)?;
+                ExprDecl::If(cond, then, self.opt(otherwise)?)
+            }
+            ExprDecl::Binop(op, e1, e2) => {
+                let e1 = self.expr(e1)?;
+                ExprDecl::Binop(op.clone(), e1, self.expr(e2)?)
+            }
+            ExprDecl::Unop(op, e) => ExprDecl::Unop(op.clone(), self.expr(e)?),
+            ExprDecl::Return(e) => ExprDecl::Return(self.opt(e)?),
+            ExprDecl::Break(e) => ExprDecl::Break(self.opt(e)?),
+            ExprDecl::Throw(e) => ExprDecl::Throw(self.expr(e)?),
+            ExprDecl::Yield(e) => ExprDecl::Yield(self.expr(e)?),
+            ExprDecl::Object(fields) => {
+                let mut resolved = vec![];
+                for (name, e) in fields.iter() {
+                    resolved.push((name.clone(), self.expr(e)?));
+                }
+                ExprDecl::Object(resolved)
+            }
+            ExprDecl::Match(value, arms) => {
+                let value = self.expr(value)?;
+                let mut resolved = vec![];
+                for (pattern, guard, body) in arms.iter() {
+                    // Pattern bindings are scoped to their arm.
+                    resolved.push(self.scoped(|r| {
+                        let mut names = vec![];
+                        pattern.bindings(&mut names);
+                        for name in names.iter() {
+                            r.declare(name, Binding::Var, pos);
+                        }
+                        let guard = r.opt(guard)?;
+                        Ok((pattern.clone(), guard, r.expr(body)?))
+                    })?);
+                }
+                ExprDecl::Match(value, resolved)
+            }
+            _ => return Ok(e.clone()),
+        };
+        Ok(expr(pos, decl))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::reader::Reader;
+
+    fn resolve_str(src: &str) -> Result<Vec<P<Expr>>, Msg> {
+        let mut ast = vec![];
+        Parser::new(Reader::from_string(src), &mut ast)
+            .parse()
+            .unwrap();
+        resolve(&mut ast).map_err(|e| e.msg)?;
+        Ok(ast)
+    }
+
+    fn warnings(src: &str) -> Vec<Msg> {
+        let mut ast = vec![];
+        Parser::new(Reader::from_string(src), &mut ast)
+            .parse()
+            .unwrap();
+        resolve(&mut ast)
+            .unwrap()
+            .into_iter()
+            .map(|w| w.msg)
+            .collect()
+    }
+
+    #[test]
+    fn interpolated_expressions_keep_their_position() {
+        let mut ast = vec![];
+        Parser::new(
+            Reader::from_string("var a = 1\n$print(\"${a} ${ zz }\")"),
+            &mut ast,
+        )
+        .parse()
+        .unwrap();
+        let err = resolve(&mut ast).unwrap_err();
+        assert_eq!(err.msg, Msg::UnknownIdentifier("zz".into()));
+        assert_eq!((err.pos.line, err.pos.column), (2, 17));
+    }
+
+    #[test]
+    fn let_bindings_are_immutable() {
+        assert_eq!(
+            resolve_str("let x = 1; x = 2;").err(),
+            Some(Msg::LetReassigned)
+        );
+        assert_eq!(
+            resolve_str("let x = 1; x += 2;").err(),
+            Some(Msg::LetReassigned)
+        );
+        assert_eq!(
+            resolve_str("let x = 1; var f = function() { x++ };").err(),
+            Some(Msg::LetReassigned)
+        );
+        assert_eq!(
+            resolve_str("let x;").err(),
+            Some(Msg::LetMissingInitialization)
+        );
+        assert!(resolve_str("let x = 1; { var x = 2; x = 3; }").is_ok());
+        assert!(resolve_str("let x = 1; var f = function(x) { x = 2 };").is_ok());
+    }
+
+    #[test]
+    fn constants_are_folded() {
+        let ast = resolve_str("const N = -3; var x = N;").unwrap();
+        assert_eq!(ast.len(), 1);
+        match &ast[0].decl {
+            ExprDecl::Var(_, _, Some(init)) => {
+                assert_eq!(init.decl, ExprDecl::Const(Constant::Int(-3)))
+            }
+            decl => panic!("{:?}", decl),
+        }
+        assert_eq!(
+            resolve_str("const N = 1; N = 2;").err(),
+            Some(Msg::AssignmentToConst)
+        );
+        assert_eq!(
+            resolve_str("var y = 1; const N = y;").err(),
+            Some(Msg::ConstValueExpected)
+        );
+    }
+
+    #[test]
+    fn destructured_let_bindings_are_immutable() {
+        assert!(resolve_str("var [a, b] = [1, 2]; a = b;").is_ok());
+        assert_eq!(
+            resolve_str("let { x, y = 0 } = $new(null); y = x;").err(),
+            Some(Msg::LetReassigned)
+        );
+    }
+
+    #[test]
+    fn unknown_globals_are_errors() {
+        assert_eq!(
+            resolve_str("var count = 1; $print(cuont);").err(),
+            Some(Msg::UnknownIdentifier("cuont".to_owned()))
+        );
+        assert_eq!(
+            resolve_str("g += 1;").err(),
+            Some(Msg::UnknownIdentifier("g".to_owned()))
+        );
+        assert!(resolve_str("var f = function() { g }; g = 1; f();").is_ok());
+        assert!(resolve_str("var f = function(n) { f(n) }; f(1);").is_ok());
+        assert_eq!(
+            resolve_str("var f = function(a, a) { a };").err(),
+            Some(Msg::IdentifierExists("a".to_owned()))
+        );
+    }
+
+    #[test]
+    fn shadowing_and_unused_variables_warn() {
+        assert_eq!(
+            warnings("var x = 1; var unused = 2; var _ok = 3; var f = function(y) { var x = y; x }; f(x);"),
+            vec![
+                Msg::UnusedVariable("unused".to_owned()),
+                Msg::ShadowVar("x".to_owned()),
+            ]
+        );
+        assert_eq!(
+            warnings("var f = function(y) { { var y = 2; y } }; f(1);"),
+            vec![Msg::ShadowParam("y".to_owned())]
+        );
+        assert_eq!(
+            warnings("const N = 1; var f = function() { var N = 2; N }; f();"),
+            vec![Msg::ShadowConst("N".to_owned())]
+        );
+        assert_eq!(
+            warnings("g = 1; var f = function() { var g = 2; g }; f();"),
+            vec![Msg::ShadowGlobal("g".to_owned())]
+        );
+    }
+}
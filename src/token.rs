@@ -23,9 +23,19 @@ impl fmt::Display for Position {
     }
 }
 
+/// Piece of a string literal with interpolations.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum StringPart {
+    Str(String),
+    /// Source of a `${...}` expression and the position where it starts.
+    Expr(String, Position),
+}
+
 #[derive(PartialEq, Eq, Debug, Clone)]
 pub enum TokenKind {
     String(String),
+    /// String literal containing `${...}`.
+    Template(Vec<StringPart>),
     LitChar(char),
     LitInt(String, IntBase, IntSuffix),
     LitFloat(String),
@@ -83,6 +93,7 @@ pub enum TokenKind {
     Comma,
     Semicolon,
     Dot,
+    DotDotDot,
     Colon,
     Sep, // ::
     Arrow,
@@ -97,6 +108,8 @@ pub enum TokenKind {
     Eq,
     EqEq,
     Ne,
+    EqEqEq,
+    NeEq,
     Lt,
     Le,
     Gt,
@@ -105,6 +118,19 @@ pub enum TokenKind {
     GtGt,
     GtGtGt,
     LtLt,
+
+    AddEq,
+    SubEq,
+    MulEq,
+    DivEq,
+    ModEq,
+    LtLtEq,
+    GtGtEq,
+    BitOrEq,
+    BitAndEq,
+    CaretEq,
+    AddAdd,
+    SubSub,
 }
 
 impl TokenKind {
@@ -112,7 +138,7 @@ impl TokenKind {
         match *self {
             TokenKind::Yield => "yield",
             TokenKind::ForEach => "foreach",
-            TokenKind::String(_) => "string",
+            TokenKind::String(_) | TokenKind::Template(_) => "string",
             TokenKind::LitInt(_, _, suffix) => match suffix {
                 IntSuffix::Byte => "byte number",
                 IntSuffix::Int => "int number",
@@ -175,6 +201,7 @@ impl TokenKind {
             TokenKind::Comma => ",",
             TokenKind::Semicolon => ";",
             TokenKind::Dot => ".",
+            TokenKind::DotDotDot => "...",
             TokenKind::Colon => ":",
             TokenKind::Sep => "::",
             TokenKind::Arrow => "->",
@@ -189,6 +216,8 @@ impl TokenKind {
             TokenKind::Eq => "=",
             TokenKind::EqEq => "==",
             TokenKind::Ne => "!=",
+            TokenKind::EqEqEq => "===",
+            TokenKind::NeEq => "!==",
             TokenKind::Lt => "<",
             TokenKind::Le => "<=",
             TokenKind::Gt => ">",
@@ -196,6 +225,19 @@ impl TokenKind {
             TokenKind::GtGtGt => ">>>",
             TokenKind::GtGt => ">>",
             TokenKind::LtLt => "<<",
+
+            TokenKind::AddEq => "+=",
+            TokenKind::SubEq => "-=",
+            TokenKind::MulEq => "*=",
+            TokenKind::DivEq => "/=",
+            TokenKind::ModEq => "%=",
+            TokenKind::LtLtEq => "<<=",
+            TokenKind::GtGtEq => ">>=",
+            TokenKind::BitOrEq => "|=",
+            TokenKind::BitAndEq => "&=",
+            TokenKind::CaretEq => "^=",
+            TokenKind::AddAdd => "++",
+            TokenKind::SubSub => "--",
         }
     }
 }
@@ -0,0 +1,43 @@
+//! Compiles and runs programs for the integration tests.
+
+use jazzlight::builtins::capture_output;
+use jazzlight::get_vm;
+use jazzlight::interp::*;
+use jazzlight::jit::{self, JitMode};
+use jazzlight::value::Value;
+use jazzlightc::codegen::{compile, module_from_context};
+use jazzlightc::parser::Parser;
+use jazzlightc::reader::Reader;
+use jazzlightc::resolver::resolve;
+
+/// Run a program with the JIT in `mode`. Returns what it printed and the
+/// value it returned.
+pub fn run(reader: Reader, mode: JitMode) -> (String, String) {
+    let mut ast = vec![];
+    Parser::new(reader, &mut ast).parse().unwrap();
+    resolve(&mut ast).unwrap();
+    let mut ctx = compile(ast);
+    let m = module_from_context(&mut ctx);
+
+    jit::set_mode(mode);
+    let vm = get_vm!();
+    vm.pc = 0;
+    vm.stack().clear();
+    vm.save_state_exit();
+    let mut result = Value::Null;
+    let output = capture_output(|| result = vm.interp(m));
+    (output, result.to_string())
+}
+
+/// Run a program with and without the JIT, check that both give the same
+/// result and return what it printed.
+#[allow(dead_code)]
+pub fn eval(src: &str) -> String {
+    let interpreted = run(Reader::from_string(src), JitMode::Off);
+    let compiled = run(Reader::from_string(src), JitMode::Force);
+    assert_eq!(
+        interpreted, compiled,
+        "compiled code differs from the interpreter"
+    );
+    interpreted.0
+}
@@ -0,0 +1,98 @@
+//! Runs every example and programs exercising the natively compiled opcodes
+//! with and without the JIT and checks that both produce the same output. The
+//! programs of `semantics.rs` are checked the same way by its `eval`.
+
+mod common;
+
+use common::{eval, run};
+use jazzlight::jit::{self, JitMode};
+use jazzlightc::reader::Reader;
+use std::path::Path;
+
+fn run_file(path: &Path, mode: JitMode) -> (String, String) {
+    run(Reader::from_file(path.to_str().unwrap()).unwrap(), mode)
+}
+
+fn examples() -> Vec<std::path::PathBuf> {
+    let mut files = std::fs::read_dir("examples")
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().map(|ext| ext == "jzl").unwrap_or(false))
+        .collect::<Vec<_>>();
+    files.sort();
+    files
+}
+
+#[test]
+fn jit_matches_interpreter() {
+    for path in examples() {
+        let interpreted = run_file(&path, JitMode::Off);
+        let compiled = run_file(&path, JitMode::Force);
+        assert!(
+            !interpreted.0.is_empty(),
+            "{} printed nothing",
+            path.display()
+        );
+        assert_eq!(interpreted, compiled, "{}", path.display());
+    }
+}
+
+#[test]
+fn jit_matches_interpreter_when_compiling_late() {
+    jit::set_threshold(3);
+    for path in examples() {
+        let interpreted = run_file(&path, JitMode::Off);
+        let compiled = run_file(&path, JitMode::On);
+        assert_eq!(interpreted, compiled, "{}", path.display());
+    }
+}
+
+#[test]
+fn native_integer_code_matches_interpreter() {
+    assert_eq!(
+        eval(
+            r#"
+            var grow = function(n) {
+                var x = 1
+                var i = 0
+                while i < n { x = x * 3 + i; i = i + 1 }
+                x
+            }
+            var neg = function(x) { -x }
+            $print(grow(10), " ", grow(50), " ", neg(-9223372036854775807 - 1))
+            "#
+        ),
+        "73806 897372484614815735962786 9223372036854775808"
+    );
+    assert_eq!(
+        eval(
+            r#"
+            var f = function(a) { var x = a; var y = x + 1; y }
+            var g = function(a, b) { var t = a < b; var u = !t; $print(t, u, t == u, a != b, a >= b) }
+            var k = function() { var i = 0; var a = i++; var b = ++i; $print(a, b, i) }
+            var bits = function(x) { $print(x & 12, " ", x | 3, " ", x ^ 5, " ", ~x) }
+            $print(f(1), f(1.5), f("s"), " ")
+            g(1, 2)
+            k()
+            bits(10)
+            "#
+        ),
+        "22.5s1 truefalsefalsetruefalse0228 11 15 -11"
+    );
+    assert_eq!(
+        eval(
+            r#"
+            var deep = function(x) {
+                ((((((((((((((((((((x + 1) + 2) + 3) + 4) + 5) + 6) + 7) + 8) + 9) + 10)
+                    + 11) + 12) + 13) + 14) + 15) + 16) + 17) + 18) + 19) + 20)
+            }
+            var h = function(x) {
+                try { 1 + 2 * (x / 0) } catch e { $print("caught ") }
+                x - 1
+            }
+            $print(deep(1), " ", h(3))
+            "#
+        ),
+        "caught 211 2"
+    );
+}
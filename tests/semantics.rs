@@ -0,0 +1,752 @@
+//! Runs small programs and checks what they print. Every program also runs
+//! with the JIT compiling each function, which has to print the same.
+
+mod common;
+
+use common::eval;
+
+#[test]
+fn logical_operators_short_circuit() {
+    assert_eq!(eval(r#"var x = null; $print(x != null && x.f);"#), "false");
+    assert_eq!(
+        eval(
+            r#"
+            var n = $array(0)
+            var f = function() { n[0] = n[0] + 1; true }
+            false && f()
+            true || f()
+            $print(n[0])
+            "#
+        ),
+        "0"
+    );
+    assert_eq!(
+        eval(r#"$print(0 || "default", 5 && 7, null && 1);"#),
+        "default7null"
+    );
+}
+
+#[test]
+fn bitwise_operators_work_on_integers() {
+    assert_eq!(
+        eval(r#"$print(6 & 3, " ", 6 | 3, " ", 6 ^ 3, " ", ~0, " ", 1 << 4);"#),
+        "2 7 5 -1 16"
+    );
+    assert_eq!(eval(r#"$print(-16 >> 2, " ", -1 >>> 60);"#), "-4 15");
+}
+
+#[test]
+fn logical_not_is_boolean() {
+    assert_eq!(
+        eval(r#"var count = 0; if !count { $print("empty ") }; $print(!0, " ", !5, " ", ~5);"#),
+        "empty true false -6"
+    );
+}
+
+#[test]
+fn division_by_zero_is_catchable() {
+    assert_eq!(
+        eval(r#"try { $print(1 / 0); } catch e { $print(e); }"#),
+        "ZeroDivisionError: division by zero"
+    );
+    assert_eq!(
+        eval(r#"try { 7 % 0 } catch e { $print("caught"); }; $print(1.0 / 0);"#),
+        "caughtinf"
+    );
+}
+
+#[test]
+fn invalid_operands_throw_type_error() {
+    assert_eq!(
+        eval(r#"try { 1 + null } catch e { $print(e); }"#),
+        "TypeError: unsupported operand types for '+': int and null"
+    );
+    assert_eq!(
+        eval(r#"try { true & false } catch e { $print(e); }"#),
+        "TypeError: unsupported operand types for '&': bool and bool"
+    );
+    assert_eq!(
+        eval(r#"try { -"a" } catch e { $print(e); }"#),
+        "TypeError: unsupported operand type for '-': string"
+    );
+}
+
+#[test]
+fn integer_overflow_promotes_to_big_integers() {
+    assert_eq!(
+        eval(r#"var max = 9223372036854775807; $print(max + 1, " ", -max - 2, " ", max * 2);"#),
+        "9223372036854775808 -9223372036854775809 18446744073709551614"
+    );
+}
+
+#[test]
+fn exceptions_unwind_calls() {
+    assert_eq!(
+        eval(
+            r#"
+            var f = function(x) { 1 + f(x / 0) }
+            var r = null
+            try { f(1) } catch e { r = "caught" }
+            $print(r, " ", $array(1, 2)[1])
+            "#
+        ),
+        "caught 2"
+    );
+    assert_eq!(
+        eval(
+            r#"
+            var i = 0
+            try {
+                while i < 3 { i = i + 1; try { if i == 2 { break; } } catch e { $print("inner") } }
+                i / 0
+            } catch e { $print("outer ", i) }
+            "#
+        ),
+        "outer 2"
+    );
+}
+
+#[test]
+fn compound_assignment() {
+    assert_eq!(
+        eval(
+            r#"
+            var i = 1
+            i += 2; i *= 5; i -= 1; i /= 2; i %= 5
+            i <<= 3; i >>= 1; i |= 1; i &= 13; i ^= 3
+            $print(i)
+            "#
+        ),
+        "10"
+    );
+    assert_eq!(
+        eval(r#"var s = "a"; s += 1; var x = 2; var y = x += 3; $print(s, " ", y, " ", x);"#),
+        "a1 5 5"
+    );
+    assert_eq!(eval(r#"g = 10; g += 5; g++; $print(g);"#), "16");
+    assert_eq!(
+        eval(r#"var o = $new(null); o.x = 1; o.x += 4; ++o.x; $print(o.x);"#),
+        "6"
+    );
+    assert_eq!(
+        eval(
+            r#"
+            var n = 0
+            var f = function() { n += 1; n++; n }
+            f()
+            $print(f())
+            "#
+        ),
+        "4"
+    );
+}
+
+#[test]
+fn increment_and_decrement() {
+    assert_eq!(
+        eval(
+            r#"var i = 5; var a = i++; var b = ++i; var c = i--; var d = --i; $print(a, b, c, d, i);"#
+        ),
+        "57755"
+    );
+    assert_eq!(
+        eval(
+            r#"
+            var i = 0
+            while i < 3 { i++ }
+            var j = i
+            --j
+            $print(i, j)
+            "#
+        ),
+        "32"
+    );
+}
+
+#[test]
+fn compound_targets_are_evaluated_once() {
+    assert_eq!(
+        eval(
+            r#"
+            var a = $array(1, 2, 3)
+            var calls = $array(0)
+            var idx = function(x) { calls[0]++; x }
+            a[idx(1)] += 10
+            var old = a[idx(2)]++
+            $print(a[1], " ", a[2], " ", old, " ", calls[0])
+            "#
+        ),
+        "12 4 3 2"
+    );
+}
+
+#[test]
+fn types_and_inheritance() {
+    let types = r#"
+        type Point {
+            init(x, y) { this.x = x; this.y = y }
+            fn len2() { this.x * this.x + this.y * this.y }
+            describe() { "point" }
+        }
+        type Point3 : Point {
+            init(x, y, z) { super.init(x, y); this.z = z }
+            fn len2() { super.len2() + this.z * this.z }
+        }
+        var p = Point.new(3, 4)
+        var q = Point3.new(1, 2, 3)
+    "#;
+    assert_eq!(
+        eval(&format!(
+            r#"{} $print(p.len2(), " ", q.len2(), " ", q.describe(), " ", q.z);"#,
+            types
+        )),
+        "25 14 point 3"
+    );
+    assert_eq!(
+        eval(&format!(
+            r#"{}
+            $print($instanceof(q, Point3), " ", $instanceof(q, Point), " ", $instanceof(p, Point3));"#,
+            types
+        )),
+        "true true false"
+    );
+    assert_eq!(
+        eval(r#"type Empty {} var e = Empty.new(); $print($instanceof(e, Empty));"#),
+        "true"
+    );
+}
+
+#[test]
+fn constants_are_inlined() {
+    assert_eq!(
+        eval(
+            r#"
+            const LIMIT = 3
+            const NAME = "n"
+            let start = 0
+            var i = start
+            while i < LIMIT { i++ }
+            $print(NAME, i, " ", -LIMIT)
+            "#
+        ),
+        "n3 -3"
+    );
+}
+
+#[test]
+fn blocks_scope_variables() {
+    assert_eq!(
+        eval(
+            r#"
+            var a = 1
+            { var a = 5; var b = 7; $print(a, b, " ") }
+            $print(a, " ")
+            { var c = 3; { var c = c + 1; $print(c) } $print(c) }
+            "#
+        ),
+        "57 1 43"
+    );
+    assert_eq!(
+        eval(
+            r#"
+            var e = "outer"
+            try { var e = 1; throw "inner" } catch e { $print(e, " ") }
+            $print(e)
+            "#
+        ),
+        "inner outer"
+    );
+}
+
+#[test]
+fn closures_capture_each_iteration() {
+    assert_eq!(
+        eval(
+            r#"
+            var fs = $array(null, null, null)
+            var i = 0
+            while i < 3 {
+                var j = i * 10
+                fs[i] = function() { j }
+                i += 1
+            }
+            $print(fs[0](), " ", fs[1](), " ", fs[2]())
+            "#
+        ),
+        "0 10 20"
+    );
+    assert_eq!(
+        eval(
+            r#"
+            var fs = $array(null, null, null)
+            for var k = 0; k < 3; k += 1 {
+                fs[k] = function() { k }
+            }
+            for var k = 0; k < 5; k += 1 {
+                if k == 1 { continue }
+                if k == 3 { break }
+                $print(k)
+            }
+            $print(" ", fs[0](), fs[1](), fs[2]())
+            "#
+        ),
+        "02 012"
+    );
+}
+
+#[test]
+fn match_patterns() {
+    let describe = r#"
+        var describe = function(v) {
+            match v {
+                0 -> "zero"
+                1 | 2 -> "small",
+                -1 -> "minus one"
+                int(n) if n > 100 -> "big"
+                int(n) -> "int " + n
+                string(s) -> "string " + s
+                [] -> "empty"
+                [a, b, ...rest] -> "array " + a + b + " " + $asize(rest)
+                { kind: "point", x, y } -> "point " + x + "," + y
+                { kind } -> "kind " + kind
+                null -> "null"
+            }
+        }
+        var point = $new(null)
+        point.kind = "point"; point.x = 1; point.y = 2
+        var other = $new(null)
+        other.kind = "other"
+    "#;
+    let run = |arg: &str| eval(&format!("{} $print(describe({}))", describe, arg));
+    assert_eq!(run("0"), "zero");
+    assert_eq!(run("2"), "small");
+    assert_eq!(run("-1"), "minus one");
+    assert_eq!(run("500"), "big");
+    assert_eq!(run("7"), "int 7");
+    assert_eq!(run(r#""hi""#), "string hi");
+    assert_eq!(run("$array()"), "empty");
+    assert_eq!(run("$array(1, 2, 3, 4)"), "array 12 2");
+    assert_eq!(run("point"), "point 1,2");
+    assert_eq!(run("other"), "kind other");
+    assert_eq!(run("null"), "null");
+    assert_eq!(
+        eval(&format!(
+            "{} try describe($array(1)) catch e $print(e)",
+            describe
+        )),
+        "MatchError: no arm matches [1]"
+    );
+}
+
+#[test]
+fn match_bindings_are_scoped_to_arms() {
+    assert_eq!(
+        eval(
+            r#"
+            var x = "outer"
+            var r = match $array(1, 2) { [x, _] -> x + 1, _ -> 0 }
+            $print(r, " ", x, " ")
+            try match 1.5 { int(n) -> n } catch e $print(e)
+            "#
+        ),
+        "2 outer MatchError: no arm matches 1.5"
+    );
+}
+
+#[test]
+fn destructuring() {
+    assert_eq!(
+        eval(
+            r#"
+            var pair = function() { [1, 2] }
+            var [a, b] = pair()
+            [a, b] = [b, a]
+            let [x, [y, z], w = 9] = [1, [2, 3]]
+            $print(a, b, " ", x, y, z, w, " ")
+            var o = $new(null)
+            o.name = "ann"; o.age = 30
+            var { name, age: years, city = "nowhere" } = o
+            $print(name, " ", years, " ", city, " ")
+            var arr = [0, 0]
+            [arr[0], o.age, arr[1] = 7] = [5, 31]
+            $print(arr, o.age)
+            "#
+        ),
+        "21 1239 ann 30 nowhere [5,7]31"
+    );
+}
+
+#[test]
+fn default_and_rest_parameters() {
+    assert_eq!(
+        eval(
+            r#"
+            var f = function(a, b = a * 2, ...rest) { $print(a, b, rest, " ") }
+            f(1); f(1, 5); f(1, 5, 6, 7)
+            var count = function() { $asize($args()) }
+            $print(count(), count(1, "x"), " ")
+            try f() catch e $print(e, " ")
+            var g = function(a, b = 0) { a + b }
+            try g(1, 2, 3) catch e $print(e)
+            "#
+        ),
+        "12[] 15[] 15[6,7] 02 Expected at least 1 arguments,found 0 Expected 1 to 2 arguments,found 3"
+    );
+}
+
+#[test]
+fn spread_arguments() {
+    assert_eq!(
+        eval(
+            r#"
+            var sum3 = function(x, y, z) { x * 100 + y * 10 + z }
+            var xs = [2, 3]
+            $print(sum3(...[1, 2, 3]), " ", sum3(1, ...xs), " ", sum3(...xs, 4), " ")
+            var o = $new(null)
+            o.k = 100
+            o.m = function(a, b) { this.k + a + b }
+            $print(o.m(...xs))
+            "#
+        ),
+        "123 123 234 105"
+    );
+}
+
+#[test]
+fn lambdas() {
+    assert_eq!(
+        eval(
+            r#"
+            var k = 10
+            var fs = [0, 0]
+            for var i = 0; i < 2; i += 1 { fs[i] = || i * k }
+            var twice = |f, x = 1| f(f(x))
+            var sum = |...xs| { var s = 0; for var i = 0; i < $asize(xs); i += 1 { s += xs[i] }; s }
+            $print(fs[0](), fs[1](), " ", twice(|y| y * 3), " ", twice(|y| y + k, 5), " ", sum(1, 2, 3))
+            "#
+        ),
+        "010 9 25 6"
+    );
+}
+
+#[test]
+fn string_literals() {
+    assert_eq!(
+        eval(
+            r#"
+            var x = 41
+            var o = $new(null)
+            o.name = "ann"
+            $print("x = ${x + 1}, ${o.name}! \${x} ${"in ${x}"} ${[1, null]}|")
+            $print("""
+            "two"
+            lines""", "|")
+            $print(r"raw \n ${x}", r"""a\b"c""")
+            "#
+        ),
+        "x = 42, ann! ${x} in 41 [1,null]|            \"two\"\n            lines|raw \\n ${x}a\\b\"c"
+    );
+}
+
+#[test]
+fn number_literals() {
+    assert_eq!(
+        eval(
+            r#"
+            $print(1e-9, " ", 2.5E3, " ", 1_000_000, " ", 0xff, " ", 0b1010, " ")
+            $print(0x1.8p3, " ", 0x10p-2, " ", 255b, " ", 12L, " ", 0x7fff_ffff_ffff_ffff, " ")
+            const MIN = -9223372036854775808
+            var min = -0x8000_0000_0000_0000
+            $print(MIN, " ", min == MIN, " ", $typeof(min), " ", min - 1, " ")
+            $print(match min { -9223372036854775808 -> "min", _ -> "other" })
+            "#
+        ),
+        "0.000000001 2500 1000000 255 10 12 4 255 12 9223372036854775807 \
+         -9223372036854775808 true int -9223372036854775809 min"
+    );
+}
+
+#[test]
+fn big_integers() {
+    assert_eq!(
+        eval(
+            r#"
+            var max = 9223372036854775807
+            var f = 1
+            for var i = 1; i < 31; i += 1 { f *= i }
+            $print(f, " ", f / 1000000000000000000000L, " ", f % 1000007, " ", $typeof(f), " ")
+            $print(f > max, max + 1 - 1 == max, " ", -0xffff_ffff_ffff_ffff_ffL, " ")
+            $print($int("-123456789012345678901234567890") * 2, " ", $int("x"), " ")
+            $print($int(1e30), " ", $int(-2.5), " ", $int(-9223372036854775808.0), " ")
+            try $int(0.0 / 0.0) catch e $print(e, " ")
+            var o = $new(null)
+            o[max + 1] = "key"
+            $print(o[9223372036854775808L], " ")
+            $print(match max + 1 { 9223372036854775808L -> "pattern", _ -> "none" }, " ")
+            try f / (f - f) catch e $print(e)
+            "#
+        ),
+        "265252859812191058636308480000000 265252859812 790627 int \
+         truetrue -4722366482869645213695 \
+         -246913578024691357802469135780 null \
+         1000000000000000019884624838656 -2 -9223372036854775808 \
+         ValueError: cannot convert NaN to an integer key pattern ZeroDivisionError: division by zero"
+    );
+    assert_eq!(
+        eval(
+            r#"
+            var big = 1 << 70
+            $print(big, " ", (big + 300) & 255, " ", big | 1, " ", ~big, " ", -big ^ -1, " ")
+            $print(big >> 68, " ", -big >> 200, " ", 5 >> 64, " ", -1 >>> 60, " ")
+            try 1 << -1 catch e $print(e, " ")
+            try big >>> 1 catch e $print(e, " ")
+            try 1 << 9223372036854775807 catch e $print(e, " ")
+            try big << big catch e $print(e, " ")
+            $print(1 >> 9223372036854775807, " ", -big >> big, " ", (1 << 67108864) >> 67108863)
+            "#
+        ),
+        "1180591620717411303424 44 1180591620717411303425 -1180591620717411303425 \
+         1180591620717411303423 4 -1 0 15 ValueError: negative shift count \
+         ValueError: '>>>' needs an integer that fits in 64 bits \
+         ValueError: shift count too large ValueError: shift count too large 0 -1 2"
+    );
+}
+
+#[test]
+fn printing_values() {
+    assert_eq!(
+        eval(
+            r#"
+            var a = [1, "two", 1.0, null]
+            a[3] = a
+            $print(a, " ", $repr(a), " ")
+            var o = $new(null)
+            o.name = "a\"b\n\${x}"
+            o.self = o
+            o["with space"] = 9223372036854775808L
+            $print($repr(o), " ")
+            var P = $new(null)
+            P.__string = function() { "Point(" + this.x + ", " + this.y + ")" }
+            var p = $new(P)
+            p.x = 1; p.y = 2
+            $print(p, " ", $repr([p]), " ", "<${p}>", " ")
+            var Q = $new(null)
+            Q.__string = function() { "Q" + $repr(this) }
+            var q = $new(Q)
+            q.v = 1
+            $print(q, " ", $repr($amake(101)) == $repr($amake(100)), " ")
+            var E = $new(null)
+            E.__string = function() { throw "bad" }
+            try $print($new(E)) catch e $print("caught ", e)
+            "#
+        ),
+        "[1,two,1,<cycle>] [1, \"two\", 1.0, <cycle>] \
+         {name: \"a\\\"b\\n\\${x}\", self: <cycle>, \"with space\": 9223372036854775808L} \
+         Point(1, 2) [Point(1, 2)] <Point(1, 2)> Q{v: 1} false caught bad"
+    );
+}
+
+#[test]
+fn equality_and_identity() {
+    assert_eq!(
+        eval(
+            r#"
+            var a = $new(null)
+            a.a = 1
+            var b = $new(null)
+            b.b = 2
+            b.a = 1
+            $print(a == b, " ")
+            a.b = 2.0
+            $print(a == b, " ", a === b, " ", a === a, " ")
+            $print(1 == 1.9, " ", 1 == 1.0, " ", 1 === 1.0, " ", 'a' == 97, " ")
+            $print([1, [2]] == [1, [2]], " ", [1] == [1, 2], " ", [1] !== [1], " ")
+            var s = "x"
+            $print(s === s, " ", "x" == "x", " ")
+            var c = [1, null]
+            c[1] = c
+            var d = [1, null]
+            d[1] = d
+            $print(c == d, " ")
+            var f = function() { 1 }
+            $print(f == f, " ", f == function() { 1 }, " ")
+            var o = $new(null)
+            o[2] = "two"
+            $print(o[2.0], " ")
+            var n = 0.0 / 0.0
+            o[n] = 1
+            o[n] = 2
+            $print(n == n, " ", o[n], " ", $repr(o), " ")
+            $print($hash(2) == $hash(2.0), " ", $hash(n) == $hash(0.0 / 0.0), " ", $typeof($hash([1, "a"])))
+            "#
+        ),
+        "false true false true false true false false true false true true true true true false two false 2 {2: \"two\", NaN: 2} true true int"
+    );
+}
+
+#[test]
+fn ordering_comparisons() {
+    assert_eq!(
+        eval(
+            r#"
+            $print(1 <= 2, " ", 2 <= 2, " ", 3 <= 2, " ", 1 < 1.5, " ", 2 >= 1.5, " ")
+            $print(9007199254740993 > 9007199254740992.0, " ", 10000000000000000001L > 1e19, " ")
+            var chars = $schars("ab")
+            $print("apple" < "banana", " ", "b" > "abc", " ", "ab" < "abc", " ", chars[0] < chars[1], " ")
+            $print([1, 2] < [1, 3], " ", [1, 2] < [1, 2, 0], " ", [2] > [1, 9], " ", [1] <= [1], " ")
+            $print(0.0 / 0.0 < 1, " ", 0.0 / 0.0 >= 1, " ")
+            try 1 < "2" catch e $print(e, " ")
+            try [1] < ["a"] catch e $print(e, " ")
+            try chars[0] < 98 catch e $print(e, " ")
+            var words = ["pear", "fig", "apple", "fig"]
+            for var i = 1; i < $asize(words); i += 1 {
+                var j = i
+                while j > 0 && $compare(words[j - 1], words[j]) > 0 {
+                    var t = words[j]
+                    words[j] = words[j - 1]
+                    words[j - 1] = t
+                    j -= 1
+                }
+            }
+            $print(words, " ", $compare(1, 1.0), " ", $compare(0.0 / 0.0, 1), " ", $compare([1], [0, 5]))
+            "#
+        ),
+        "true true false true true true true true true true true true true true true false false \
+         TypeError: unsupported operand types for '<': int and string \
+         TypeError: unsupported operand types for '<': int and string \
+         TypeError: unsupported operand types for '<': char and int \
+         [apple,fig,fig,pear] 0 1 1"
+    );
+}
+
+#[test]
+fn operator_overloading() {
+    assert_eq!(
+        eval(
+            r#"
+            type Vec {
+                init(x, y) { this.x = x; this.y = y }
+                fn __add(a, b) { Vec.new(a.x + b.x, a.y + b.y) }
+                fn __mul(a, b) {
+                    if $typeof(a) == "int" { Vec.new(a * b.x, a * b.y) } else { Vec.new(a.x * b, a.y * b) }
+                }
+                fn __neg(a) { Vec.new(-a.x, -a.y) }
+                fn __eq(a, b) { $typeof(b) == "object" && a.x == b.x && a.y == b.y }
+                fn __lt(a, b) { a.x * a.x + a.y * a.y < b.x * b.x + b.y * b.y }
+                fn __string() { "(" + this.x + ", " + this.y + ")" }
+            }
+            type Matrix {
+                init(n) { this.rows = $amake(n * n); this.n = n }
+                fn __get(key) { this.rows[key[0] * this.n + key[1]] }
+                fn __set(key, value) { this.rows[key[0] * this.n + key[1]] = value }
+                fn __call(i) { this[[i, i]] }
+            }
+            var a = Vec.new(1, 2)
+            var b = Vec.new(3, 4)
+            $print(a + b, " ", a * 2, " ", 3 * a, " ", -a, " ")
+            $print(a == Vec.new(1, 2), " ", a != b, " ", a == 1, " ", a === Vec.new(1, 2), " ")
+            $print(a < b, " ", a > b, " ", a <= a, " ", b >= a, " ")
+            var m = Matrix.new(2)
+            m[[1, 1]] = 5
+            m[[0, 1]] = 7
+            $print(m[[1, 1]], " ", m[[0, 1]], " ", m.n, " ", m(1), " ")
+            try a - b catch e $print(e)
+            "#
+        ),
+        "(4, 6) (2, 4) (3, 6) (-1, -2) true true false false true false true true 5 7 2 5 \
+         TypeError: unsupported operand types for '-': object and object"
+    );
+}
+
+#[test]
+fn field_accessors_and_missing_fields() {
+    assert_eq!(
+        eval(
+            r#"
+            type Temp {
+                init(c) { this.c = c }
+                get f() { this.c * 9 / 5 + 32 }
+                set f(value) { this.c = (value - 32) * 5 / 9 }
+                get kelvin() { this.c + 273 }
+                fn get(i) { "method " + i }
+            }
+            var t = Temp.new(100)
+            $print(t.f, " ")
+            t.f = 32
+            $print(t.c, " ", t.kelvin, " ", t.get(1), " ", $objfield(t, "kelvin"), " ")
+            try t.kelvin = 1 catch e $print(e, " ")
+            type Config {
+                init() { this.debug = true }
+                fn __missing(name) { "default " + name }
+            }
+            var c = Config.new()
+            $print(c.debug, " ", c.port, " ", t.nothing, " ")
+            let { nothing = 5, c: celsius } = t
+            $print(nothing, " ", celsius)
+            "#
+        ),
+        "212 0 273 method 1 true FieldError: field 'kelvin' is read-only \
+         true default port null 5 0"
+    );
+}
+
+#[test]
+fn strict_mode_rejects_absent_fields() {
+    jazzlight::meta::set_strict(true);
+    assert_eq!(
+        eval(
+            r#"
+            var o = $new(null)
+            o.name = "x"
+            try o.nmae catch e $print(e, " ")
+            let { age = 3 } = o
+            $print(o.name, " ", age, " ", $objfield(o, "nmae"))
+            "#
+        ),
+        "FieldError: object has no field 'nmae' x 3 false"
+    );
+}
+
+#[test]
+fn bound_functions() {
+    assert_eq!(
+        eval(
+            r#"
+            type Counter {
+                init(start) { this.n = start }
+                fn add(by) { this.n += by; this.n }
+            }
+            var c = Counter.new(10)
+            var add = $bind(Counter.add, c)
+            var add5 = $bind(c.add, c, 5)
+            var show = function(x) { $print(x, " ") }
+            show(add(1))
+            show(add5())
+            show($nargs(add))
+            show($nargs(add5))
+            var m = c::add
+            show(m(2))
+            show($apply(m, null, [3]))
+            show($call(add5, []))
+            var o = $new(null)
+            o.f = add5
+            show(o.f())
+            var tail = function(x) { add(x) }
+            show(tail(100))
+            var p = $bind($print, null, "<")
+            p("x", "> ")
+            var sum3 = function(a, b, c) { a + b + c }
+            var s1 = $bind(sum3, null, 1)
+            var s2 = $bind(s1, o, 2)
+            show(s2(3))
+            show([$nargs(sum3), $nargs(s1), $nargs(s2)])
+            var rest = function(a, ...xs) { $asize(xs) }
+            show([$nargs(rest), $nargs($bind(rest, null, 1, 2))])
+            try s2(3, 4) catch e show(e)
+            show(c::n)
+            show($typeof(m))
+            "#
+        ),
+        "11 16 1 0 18 21 26 31 131 <x> 6 [3,2,1] [-2,-1] Expected 3 arguments,found 4 131 function "
+    );
+}
@@ -0,0 +1,54 @@
+//! Checks that code produced by the compiler passes the bytecode verifier.
+
+use jazzlight::verifier::verify;
+use jazzlightc::codegen::{compile, module_from_context};
+use jazzlightc::parser::Parser;
+use jazzlightc::reader::Reader;
+use jazzlightc::resolver::resolve;
+
+fn check(src: &str) {
+    let mut ast = vec![];
+    Parser::new(Reader::from_string(src), &mut ast)
+        .parse()
+        .unwrap();
+    resolve(&mut ast).unwrap();
+    let mut ctx = compile(ast);
+    let m = module_from_context(&mut ctx);
+    let result = verify(&m.borrow());
+    if let Err(e) = result {
+        panic!("{}\n{}", src, e);
+    }
+}
+
+#[test]
+fn examples_verify() {
+    for entry in std::fs::read_dir("examples").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().map(|ext| ext == "jzl").unwrap_or(false) {
+            check(&std::fs::read_to_string(&path).unwrap());
+        }
+    }
+}
+
+#[test]
+fn control_flow_is_balanced() {
+    check("var x = 1; if x == 1 { x = 2; } x;");
+    check("var x = if true { 1 } else { 2 }; x;");
+    check("var i = 0; while i < 10 { i = i + 1; if i == 5 { break; } } i;");
+    check("var i = 0; while i < 10 { i = i + 1; continue; }");
+    check("var a = true && false; var b = false || true;");
+    check("try { throw 1; } catch e { $print(e); }");
+    check("var f = function(a) { var b = a; function() { b } }; f(1)();");
+}
+
+#[test]
+fn compound_assignment_is_balanced() {
+    check("var i = 0; i += 1; i++; ++i; var j = i--;");
+    check("var a = $array(1); a[0] *= 2; var x = a[0]++;");
+    check("var o = $new(null); o.x = 1; o.x -= 1; g = 1; g |= 2;");
+}
+
+#[test]
+fn types_are_balanced() {
+    check("type A { init(x) { this.x = x } get() { this.x } } type B : A { get() { super.get() + 1 } } B.new(1).get();");
+}
@@ -0,0 +1,834 @@
+//! Textual form of bytecode modules.
+//!
+//! `disassemble` prints a module in a format that `assemble` reads back, so a
+//! `.j` file survives a round-trip through text unchanged:
+//!
+//! ```text
+//! .global string "Hello"          ; 0 #0
+//! .global function f1 0 #2        ; 1 #1
+//! .const array #0 #0              ; #2
+//!
+//! .code
+//!     Jump L0004                  ; 0000
+//! f1:
+//!     LoadGlobal 0                ; 0001 "Hello"
+//!     Ret                         ; 0002
+//! L0004:
+//!     LoadNull                    ; 0003
+//! ```
+//!
+//! Constants are numbered in the order they are defined, by `.global` lines
+//! that define a new global and `.const` lines that define a value only
+//! referred to by other constants. Arrays, objects and function envs refer to
+//! constants with `#<number>`, which is how shared and cyclic values are
+//! written, and `.global #<number>` makes an existing constant a global:
+//!
+//! - `array #<item>...`
+//! - `object <#prototype or -> #<key> #<value>...`
+//! - `function <label> <argc> [#<env>]`, without env an empty array.
+//!
+//! Labels name bytecode addresses and are used as jump targets and function
+//! entry points, jumps outside of the code use a plain address instead.
+//! `.line <line> "<file>"` sets debug information for the instructions that
+//! follow it. Everything after `;` is a comment.
+
+use crate::opcode::Op;
+use crate::value::{Function, Object, Value};
+use crate::writer::{self, string_table, ConstantPool};
+use crate::*;
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// Operand of an instruction in textual form.
+enum Operand {
+    None,
+    Int(i64),
+    Index(u32),
+    Target(u32),
+    Name(String),
+}
+
+fn split_op(op: &Op) -> (&'static str, Operand) {
+    use Operand::*;
+    match op {
+        Op::LoadNull => ("LoadNull", None),
+        Op::LoadTrue => ("LoadTrue", None),
+        Op::LoadFalse => ("LoadFalse", None),
+        Op::LoadInt(x) => ("LoadInt", Int(*x)),
+        Op::LoadGlobal(x) => ("LoadGlobal", Index(*x)),
+        Op::StoreGlobal(x) => ("StoreGlobal", Index(*x)),
+        Op::LoadEnv(x) => ("LoadEnv", Index(*x as _)),
+        Op::LoadLocal(x) => ("LoadLocal", Index(*x as _)),
+        Op::LoadBuiltin(name) => ("LoadBuiltin", Name(name.clone())),
+        Op::LoadThis => ("LoadThis", None),
+        Op::Load => ("Load", None),
+        Op::Store => ("Store", None),
+        Op::StoreEnv(x) => ("StoreEnv", Index(*x as _)),
+        Op::StoreLocal(x) => ("StoreLocal", Index(*x as _)),
+        Op::StoreThis => ("StoreThis", None),
+        Op::Pop(x) => ("Pop", Index(*x as _)),
+        Op::Call(x) => ("Call", Index(*x as _)),
+        Op::ObjCall(x) => ("ObjCall", Index(*x as _)),
+        Op::TailCall(x) => ("TailCall", Index(*x as _)),
+        Op::Jump(x) => ("Jump", Target(*x)),
+        Op::JumpIf(x) => ("JumpIf", Target(*x)),
+        Op::JumpIfNot(x) => ("JumpIfNot", Target(*x)),
+        Op::CatchPush(x) => ("CatchPush", Target(*x)),
+        Op::Throw => ("Throw", None),
+        Op::Ret => ("Ret", None),
+        Op::MakeEnv(x) => ("MakeEnv", Index(*x as _)),
+        Op::MakeArray(x) => ("MakeArray", Index(*x as _)),
+        Op::IsNull => ("IsNull", None),
+        Op::IsNotNull => ("IsNotNull", None),
+        Op::Add => ("Add", None),
+        Op::Sub => ("Sub", None),
+        Op::Div => ("Div", None),
+        Op::Mul => ("Mul", None),
+        Op::Mod => ("Mod", None),
+        Op::Shl => ("Shl", None),
+        Op::Shr => ("Shr", None),
+        Op::UShr => ("UShr", None),
+        Op::Or => ("Or", None),
+        Op::And => ("And", None),
+        Op::Xor => ("Xor", None),
+        Op::Eq => ("Eq", None),
+        Op::Neq => ("Neq", None),
+        Op::Same => ("Same", None),
+        Op::NotSame => ("NotSame", None),
+        Op::Gt => ("Gt", None),
+        Op::Gte => ("Gte", None),
+        Op::Lt => ("Lt", None),
+        Op::Lte => ("Lte", None),
+        Op::Not => ("Not", None),
+        Op::Neg => ("Neg", None),
+        Op::Hash => ("Hash", None),
+        Op::New => ("New", None),
+        Op::Nop => ("Nop", None),
+        Op::Last => ("Last", None),
+        Op::Dup => ("Dup", None),
+        Op::BitNot => ("BitNot", None),
+        Op::CatchPop => ("CatchPop", None),
+    }
+}
+
+/// Opcode without operand with the given name.
+fn simple_op(name: &str) -> Option<Op> {
+    Some(match name {
+        "LoadNull" => Op::LoadNull,
+        "LoadTrue" => Op::LoadTrue,
+        "LoadFalse" => Op::LoadFalse,
+        "LoadThis" => Op::LoadThis,
+        "Load" => Op::Load,
+        "Store" => Op::Store,
+        "StoreThis" => Op::StoreThis,
+        "Throw" => Op::Throw,
+        "Ret" => Op::Ret,
+        "IsNull" => Op::IsNull,
+        "IsNotNull" => Op::IsNotNull,
+        "Add" => Op::Add,
+        "Sub" => Op::Sub,
+        "Div" => Op::Div,
+        "Mul" => Op::Mul,
+        "Mod" => Op::Mod,
+        "Shl" => Op::Shl,
+        "Shr" => Op::Shr,
+        "UShr" => Op::UShr,
+        "Or" => Op::Or,
+        "And" => Op::And,
+        "Xor" => Op::Xor,
+        "Eq" => Op::Eq,
+        "Neq" => Op::Neq,
+        "Same" => Op::Same,
+        "NotSame" => Op::NotSame,
+        "Gt" => Op::Gt,
+        "Gte" => Op::Gte,
+        "Lt" => Op::Lt,
+        "Lte" => Op::Lte,
+        "Not" => Op::Not,
+        "Neg" => Op::Neg,
+        "Hash" => Op::Hash,
+        "New" => Op::New,
+        "Nop" => Op::Nop,
+        "Last" => Op::Last,
+        "Dup" => Op::Dup,
+        "BitNot" => Op::BitNot,
+        "CatchPop" => Op::CatchPop,
+        _ => return None,
+    })
+}
+
+/// Opcode taking a 16 bit operand with the given name.
+fn u16_op(name: &str) -> Option<fn(u16) -> Op> {
+    Some(match name {
+        "LoadEnv" => Op::LoadEnv,
+        "LoadLocal" => Op::LoadLocal,
+        "StoreEnv" => Op::StoreEnv,
+        "StoreLocal" => Op::StoreLocal,
+        "Pop" => Op::Pop,
+        "Call" => Op::Call,
+        "ObjCall" => Op::ObjCall,
+        "TailCall" => Op::TailCall,
+        "MakeEnv" => Op::MakeEnv,
+        "MakeArray" => Op::MakeArray,
+        _ => return None,
+    })
+}
+
+/// Opcode taking a jump target with the given name.
+fn jump_op(name: &str) -> Option<fn(u32) -> Op> {
+    Some(match name {
+        "Jump" => Op::Jump,
+        "JumpIf" => Op::JumpIf,
+        "JumpIfNot" => Op::JumpIfNot,
+        "CatchPush" => Op::CatchPush,
+        _ => return None,
+    })
+}
+
+/// Print constant `k` of `pool` on a line of its own, as a `.global` line
+/// if it defines global `global`.
+fn write_constant(
+    out: &mut String,
+    global: Option<usize>,
+    pool: &ConstantPool,
+    k: usize,
+    labels: &mut HashMap<usize, Vec<String>>,
+) {
+    let refs = |items: &[u32]| {
+        items
+            .iter()
+            .map(|idx| format!(" #{}", idx))
+            .collect::<String>()
+    };
+    let text = match &pool.constants[k] {
+        writer::Constant::Null => "null".to_owned(),
+        writer::Constant::Bool(x) => format!("bool {}", x),
+        writer::Constant::Int(x) => format!("int {}", x),
+        writer::Constant::BigInt(x) => format!("int {}", x),
+        writer::Constant::Char(x) => format!("char {}", *x as u32),
+        writer::Constant::String(s) => format!("string {:?}", s),
+        // NaN payloads have no decimal form.
+        writer::Constant::Float(x) if x.is_nan() => format!("float {:#x}", x.to_bits()),
+        writer::Constant::Float(x) => format!("float {:?}", x),
+        writer::Constant::Array(items) => format!("array{}", refs(items)),
+        writer::Constant::Object(prototype, table) => {
+            let prototype = prototype.map_or("-".to_owned(), |idx| format!("#{}", idx));
+            let entries = table
+                .iter()
+                .map(|(key, value)| refs(&[*key, *value]))
+                .collect::<String>();
+            format!("object {}{}", prototype, entries)
+        }
+        writer::Constant::Function(f, env) => {
+            let f = f.borrow();
+            let name = format!("f{}", k);
+            labels.entry(f.address).or_default().push(name.clone());
+            format!("function {} {} #{}", name, f.argc, env)
+        }
+    };
+    let (directive, comment) = match global {
+        Some(global) => (".global", format!("{} #{}", global, k)),
+        None => (".const", format!("#{}", k)),
+    };
+    let comment = match &pool.constants[k] {
+        writer::Constant::Char(x) => format!("{} {:?}", comment, x),
+        _ => comment,
+    };
+    let _ = writeln!(
+        out,
+        "{:<32}; {}",
+        format!("{} {}", directive, text),
+        comment
+    );
+}
+
+/// Print `m` in the format read by `assemble`.
+pub fn disassemble(m: &Module) -> String {
+    let mut out = String::new();
+    let mut labels: HashMap<usize, Vec<String>> = HashMap::new();
+
+    let strings = string_table(m);
+    if !strings.is_empty() {
+        out.push_str("; string table\n");
+        for (s, idx) in strings.iter() {
+            let _ = writeln!(out, ";   {} {:?}", idx, s);
+        }
+        out.push('\n');
+    }
+
+    // Constants in the order the bytecode writer stores them, so that their
+    // numbers are the pool indices. A global is defined on its own line
+    // unless an earlier constant already is the same value.
+    let pool = ConstantPool::new(m);
+    let mut defined = 0;
+    for (i, &idx) in pool.globals.iter().enumerate() {
+        let idx = idx as usize;
+        if idx < defined {
+            let _ = writeln!(out, "{:<32}; {}", format!(".global #{}", idx), i);
+            continue;
+        }
+        for k in defined..idx {
+            write_constant(&mut out, None, &pool, k, &mut labels);
+        }
+        write_constant(&mut out, Some(i), &pool, idx, &mut labels);
+        defined = idx + 1;
+    }
+    for k in defined..pool.constants.len() {
+        write_constant(&mut out, None, &pool, k, &mut labels);
+    }
+    for op in m.code.iter() {
+        if let (_, Operand::Target(to)) = split_op(op) {
+            if to as usize > m.code.len() {
+                continue;
+            }
+            labels
+                .entry(to as usize)
+                .or_insert_with(|| vec![format!("L{:04}", to)]);
+        }
+    }
+
+    out.push_str("\n.code\n");
+    let mut line = None;
+    for (pc, op) in m.code.iter().enumerate() {
+        for label in labels.get(&pc).into_iter().flatten() {
+            let _ = writeln!(out, "{}:", label);
+        }
+        let info = m.trace_info.get(&(pc as u32));
+        if info != line {
+            if let Some((l, file)) = info {
+                let _ = writeln!(out, ".line {} {:?}", l, file);
+            } else {
+                out.push_str(".line 0 \"\"\n");
+            }
+            line = info;
+        }
+        let (name, operand) = split_op(op);
+        let mut comment = String::new();
+        let text = match operand {
+            Operand::None => name.to_owned(),
+            Operand::Int(x) => format!("{} {}", name, x),
+            Operand::Index(x) => {
+                if let Op::LoadGlobal(_) | Op::StoreGlobal(_) = op {
+                    let idx = pool.globals.get(x as usize);
+                    comment = match m.globals.get(x as usize) {
+                        Some(Value::String(s)) => format!(" {:?}", &*s.borrow()),
+                        Some(Value::Char(c)) => format!(" {:?}", c),
+                        Some(Value::Function(f)) if !f.borrow().native => {
+                            format!(" function f{}", idx.unwrap())
+                        }
+                        Some(Value::Array(_)) | Some(Value::Object(_)) => {
+                            format!(" #{}", idx.unwrap())
+                        }
+                        Some(value) => format!(" {}", value),
+                        None => " <out of range>".to_owned(),
+                    };
+                }
+                format!("{} {}", name, x)
+            }
+            Operand::Target(to) => match labels.get(&(to as usize)) {
+                Some(labels) => format!("{} {}", name, labels[0]),
+                None => format!("{} {}", name, to),
+            },
+            Operand::Name(s) => format!("{} {:?}", name, s),
+        };
+        let _ = writeln!(out, "    {:<28}; {:04}{}", text, pc, comment);
+    }
+    for label in labels.get(&m.code.len()).into_iter().flatten() {
+        let _ = writeln!(out, "{}:", label);
+    }
+    out
+}
+
+/// Split a line into tokens. Quoted strings are kept as a single token
+/// including the quotes, `;` outside of strings starts a comment.
+fn tokenize(line: &str) -> Vec<&str> {
+    let mut tokens = vec![];
+    let mut start = None;
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut end = line.len();
+    for (i, c) in line.char_indices() {
+        if in_string {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_string = false;
+            }
+        } else if c == ';' {
+            end = i;
+            break;
+        } else if c.is_whitespace() {
+            if let Some(s) = start.take() {
+                tokens.push(&line[s..i]);
+            }
+        } else {
+            if start.is_none() {
+                start = Some(i);
+            }
+            in_string = c == '"';
+        }
+    }
+    if let Some(s) = start {
+        tokens.push(&line[s..end]);
+    }
+    tokens
+}
+
+/// Parse a string literal as printed by `{:?}`.
+fn unquote(token: &str) -> Result<String, String> {
+    if token.len() < 2 || !token.starts_with('"') || !token.ends_with('"') {
+        return Err(format!("string literal expected, found '{}'", token));
+    }
+    let mut out = String::new();
+    let mut chars = token[1..token.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('t') => out.push('\t'),
+            Some('0') => out.push('\0'),
+            Some('\\') => out.push('\\'),
+            Some('"') => out.push('"'),
+            Some('\'') => out.push('\''),
+            Some('u') => {
+                let rest = chars.as_str();
+                let end = rest.find('}').ok_or("unterminated unicode escape")?;
+                let code = rest
+                    .get(1..end)
+                    .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                    .and_then(std::char::from_u32)
+                    .ok_or("invalid unicode escape")?;
+                out.push(code);
+                chars = rest[end + 1..].chars();
+            }
+            c => return Err(format!("unknown escape '\\{}'", c.unwrap_or(' '))),
+        }
+    }
+    Ok(out)
+}
+
+fn number<T: std::str::FromStr>(token: Option<&&str>) -> Result<T, String> {
+    let token = token.ok_or("operand expected")?;
+    token
+        .parse()
+        .map_err(|_| format!("invalid number '{}'", token))
+}
+
+/// Reference `#<number>` to a constant.
+fn reference(token: Option<&&str>) -> Result<u32, String> {
+    let token = token.ok_or("constant reference expected")?;
+    match token.strip_prefix('#').map(str::parse) {
+        Some(Ok(idx)) => Ok(idx),
+        _ => Err(format!("invalid constant reference '{}'", token)),
+    }
+}
+
+fn float(token: Option<&&str>) -> Result<f64, String> {
+    match token.and_then(|token| token.strip_prefix("0x")) {
+        Some(bits) => u64::from_str_radix(bits, 16)
+            .map(f64::from_bits)
+            .map_err(|_| format!("invalid float bits '{}'", bits)),
+        None => number(token),
+    }
+}
+
+/// Constant as written in the text, composite constants refer to others by
+/// number.
+enum Constant {
+    Value(Value),
+    Array(Vec<u32>),
+    Object(Option<u32>, Vec<(u32, u32)>),
+    Function(String, i32, Option<u32>),
+}
+
+/// Parse the constant defined by `.const` or `.global` operands `args`.
+fn constant(args: &[&str]) -> Result<Constant, String> {
+    let expect_args = |n: usize| {
+        if args.len() != n {
+            Err(format!(
+                "'{}' constant expects {} operands, found {}",
+                args[0],
+                n - 1,
+                args.len() - 1
+            ))
+        } else {
+            Ok(())
+        }
+    };
+    let value = match args.get(0) {
+        Some(&"null") => {
+            expect_args(1)?;
+            Value::Null
+        }
+        Some(&"bool") => {
+            expect_args(2)?;
+            Value::Bool(number(args.get(1))?)
+        }
+        Some(&"int") => {
+            expect_args(2)?;
+            match number(args.get(1)) {
+                Ok(x) => Value::Int(x),
+                Err(e) => bigint::BigInt::parse(args[1], 10)
+                    .map(Value::from_bigint)
+                    .ok_or(e)?,
+            }
+        }
+        Some(&"char") => {
+            expect_args(2)?;
+            let x = number(args.get(1))?;
+            Value::Char(std::char::from_u32(x).ok_or_else(|| format!("invalid char {}", x))?)
+        }
+        Some(&"string") => {
+            expect_args(2)?;
+            Value::String(Ref(unquote(args[1])?))
+        }
+        Some(&"float") => {
+            expect_args(2)?;
+            Value::Float(float(args.get(1))?)
+        }
+        Some(&"array") => {
+            let items = args[1..]
+                .iter()
+                .map(|token| reference(Some(token)))
+                .collect::<Result<_, _>>()?;
+            return Ok(Constant::Array(items));
+        }
+        Some(&"object") => {
+            if args.len() % 2 != 0 {
+                return Err("'object' constant expects a prototype and key value pairs".to_owned());
+            }
+            let prototype = match args.get(1) {
+                Some(&"-") => None,
+                token => Some(reference(token)?),
+            };
+            let mut table = vec![];
+            for pair in args[2..].chunks(2) {
+                table.push((reference(pair.get(0))?, reference(pair.get(1))?));
+            }
+            return Ok(Constant::Object(prototype, table));
+        }
+        Some(&"function") => {
+            if args.len() != 3 && args.len() != 4 {
+                return Err("'function' constant expects a label, argc and env".to_owned());
+            }
+            let argc = number(args.get(2))?;
+            let env = match args.get(3) {
+                Some(token) => Some(reference(Some(token))?),
+                None => None,
+            };
+            return Ok(Constant::Function(args[1].to_owned(), argc, env));
+        }
+        _ => return Err("unknown constant kind".to_owned()),
+    };
+    Ok(Constant::Value(value))
+}
+
+/// Create the values of `constants` for module `m`, like the bytecode reader
+/// does: heap values are allocated first and filled afterwards, so constants
+/// may refer to each other in any order.
+fn link_constants(
+    m: &Ref<Module>,
+    constants: &[(Constant, usize)],
+    resolve: impl Fn(&str, usize) -> Result<usize, AsmError>,
+) -> Result<Vec<Value>, AsmError> {
+    let mut values = vec![];
+    for (constant, line) in constants.iter() {
+        values.push(match constant {
+            Constant::Value(value) => value.clone(),
+            Constant::Array(_) => Value::Array(Ref(vec![])),
+            Constant::Object(..) => Value::Object(Ref(Object {
+                prototype: None,
+                table: Default::default(),
+            })),
+            Constant::Function(label, argc, _) => Value::Function(Ref(Function {
+                native: false,
+                address: resolve(label, *line)?,
+                argc: *argc,
+                env: Value::Array(Ref(vec![])),
+                module: Some(m.clone()),
+                calls: Default::default(),
+                bound: None,
+            })),
+        });
+    }
+    let get = |idx: u32, line: usize| {
+        values.get(idx as usize).cloned().ok_or_else(|| AsmError {
+            line,
+            message: format!("undefined constant #{}", idx),
+        })
+    };
+    for ((constant, line), value) in constants.iter().zip(values.iter()) {
+        match (constant, value) {
+            (Constant::Array(items), Value::Array(array)) => {
+                let items = items
+                    .iter()
+                    .map(|idx| get(*idx, *line))
+                    .collect::<Result<_, _>>()?;
+                *array.borrow_mut() = items;
+            }
+            (Constant::Function(_, _, Some(env)), Value::Function(f)) => {
+                f.borrow_mut().env = get(*env, *line)?;
+            }
+            _ => (),
+        }
+    }
+    for ((constant, line), value) in constants.iter().zip(values.iter()) {
+        if let (Constant::Object(prototype, table), Value::Object(object)) = (constant, value) {
+            let mut object = object.borrow_mut();
+            if let Some(idx) = prototype {
+                match get(*idx, *line)? {
+                    Value::Object(proto) => object.prototype = Some(proto),
+                    _ => {
+                        return Err(AsmError {
+                            line: *line,
+                            message: format!("prototype #{} is not an object", idx),
+                        })
+                    }
+                }
+            }
+            for (key, value) in table.iter() {
+                object.table.insert(get(*key, *line)?, get(*value, *line)?);
+            }
+        }
+    }
+    Ok(values)
+}
+
+/// Build a module from text produced by `disassemble` or written by hand.
+pub fn assemble(src: &str) -> Result<Ref<Module>, AsmError> {
+    // Constants with the line defining them and globals as constant numbers.
+    let mut constants = vec![];
+    let mut globals = vec![];
+    let mut code = vec![];
+    let mut jumps = vec![];
+    let mut labels = HashMap::new();
+    let mut trace_info = HashMap::new();
+    let mut line_info: Option<(usize, String)> = None;
+
+    for (i, line) in src.lines().enumerate() {
+        let err = |message: String| AsmError {
+            line: i + 1,
+            message,
+        };
+        let tokens = tokenize(line);
+        let (first, args) = match tokens.split_first() {
+            Some((first, args)) => (*first, args),
+            None => continue,
+        };
+        if first.ends_with(':') && args.is_empty() {
+            let label = &first[..first.len() - 1];
+            if labels.insert(label.to_owned(), code.len()).is_some() {
+                return Err(err(format!("label '{}' defined twice", label)));
+            }
+            continue;
+        }
+        let expect_args = |n: usize| {
+            if args.len() != n {
+                Err(err(format!(
+                    "'{}' expects {} operands, found {}",
+                    first,
+                    n,
+                    args.len()
+                )))
+            } else {
+                Ok(())
+            }
+        };
+        match first {
+            ".code" => expect_args(0)?,
+            ".line" => {
+                expect_args(2)?;
+                let line = number(args.get(0)).map_err(err)?;
+                let file = unquote(args[1]).map_err(err)?;
+                line_info = if file.is_empty() {
+                    None
+                } else {
+                    Some((line, file))
+                };
+            }
+            ".global" if args.len() == 1 && args[0].starts_with('#') => {
+                globals.push((reference(args.get(0)).map_err(err)?, i + 1));
+            }
+            ".global" => {
+                globals.push((constants.len() as u32, i + 1));
+                constants.push((constant(args).map_err(err)?, i + 1));
+            }
+            ".const" => constants.push((constant(args).map_err(err)?, i + 1)),
+            name => {
+                let op = if let Some(op) = simple_op(name) {
+                    expect_args(0)?;
+                    op
+                } else if let Some(op) = u16_op(name) {
+                    expect_args(1)?;
+                    op(number(args.get(0)).map_err(err)?)
+                } else if let Some(op) = jump_op(name) {
+                    expect_args(1)?;
+                    match args[0].parse() {
+                        Ok(to) => op(to),
+                        Err(_) => {
+                            jumps.push((code.len(), args[0].to_owned(), i + 1));
+                            op(0)
+                        }
+                    }
+                } else if let "LoadInt" | "LoadGlobal" | "StoreGlobal" | "LoadBuiltin" = name {
+                    expect_args(1)?;
+                    match name {
+                        "LoadInt" => Op::LoadInt(number(args.get(0)).map_err(err)?),
+                        "LoadGlobal" => Op::LoadGlobal(number(args.get(0)).map_err(err)?),
+                        "StoreGlobal" => Op::StoreGlobal(number(args.get(0)).map_err(err)?),
+                        _ => Op::LoadBuiltin(unquote(args[0]).map_err(err)?),
+                    }
+                } else {
+                    return Err(err(format!("unknown instruction '{}'", name)));
+                };
+                if let Some(info) = &line_info {
+                    trace_info.insert(code.len() as u32, info.clone());
+                }
+                code.push(op);
+            }
+        }
+    }
+
+    let resolve = |label: &str, line: usize| {
+        labels.get(label).cloned().ok_or_else(|| AsmError {
+            line,
+            message: format!("undefined label '{}'", label),
+        })
+    };
+    for (at, label, line) in jumps {
+        let to = resolve(&label, line)? as u32;
+        code[at] = match &code[at] {
+            Op::Jump(_) => Op::Jump(to),
+            Op::JumpIf(_) => Op::JumpIf(to),
+            Op::JumpIfNot(_) => Op::JumpIfNot(to),
+            _ => Op::CatchPush(to),
+        };
+    }
+
+    let m = Ref(Module {
+        exports: Value::Object(Ref(Object {
+            prototype: None,
+            table: Default::default(),
+        })),
+        code,
+        globals: vec![],
+        trace_info,
+    });
+    let values = link_constants(&m, &constants, resolve)?;
+    for (idx, line) in globals {
+        let value = values.get(idx as usize).cloned().ok_or_else(|| AsmError {
+            line,
+            message: format!("undefined constant #{}", idx),
+        })?;
+        m.borrow_mut().globals.push(value);
+    }
+    Ok(m)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reader::tests::roundtrip::{random_module, same};
+    use crate::reader::BytecodeReader;
+    use crate::writer::BytecodeWriter;
+
+    const SRC: &str = r#"
+.global string "Hello, \"world\"\n"
+.global float 0.5
+.global function f2 1
+
+.code
+    Jump main
+f2:
+.line 3 "test.jzl"
+    LoadLocal 0
+    LoadGlobal 1            ; comment
+    Add
+    Ret
+main:
+.line 0 ""
+    LoadGlobal 0
+    LoadBuiltin "print"
+    Call 1
+    Pop 1
+    CatchPush end
+    LoadInt -7
+    Ret
+end:
+"#;
+
+    #[test]
+    fn assemble_text() {
+        let m = assemble(SRC).unwrap();
+        let m = m.borrow();
+        assert_eq!(m.code[0], Op::Jump(5));
+        assert_eq!(m.code[9], Op::CatchPush(12));
+        assert_eq!(m.code[10], Op::LoadInt(-7));
+        assert_eq!(m.trace_info.len(), 4);
+        assert_eq!(m.globals[0].to_string(), "Hello, \"world\"\n");
+        match &m.globals[2] {
+            Value::Function(f) => assert_eq!((f.borrow().address, f.borrow().argc), (1, 1)),
+            _ => panic!("function expected"),
+        }
+    }
+
+    #[test]
+    fn round_trip_through_bytecode() {
+        let m = assemble(SRC).unwrap();
+        let text = disassemble(&m.borrow());
+        let mut w = BytecodeWriter { bytecode: vec![] };
+        w.write_module(m);
+        let read = BytecodeReader::new(&w.bytecode).read_module().unwrap();
+        assert_eq!(disassemble(&read.borrow()), text);
+        let again = assemble(&text).unwrap();
+        assert_eq!(again.borrow().code, read.borrow().code);
+        assert_eq!(disassemble(&again.borrow()), text);
+    }
+
+    #[test]
+    fn round_trip_random_modules() {
+        for seed in 0..300 {
+            let m = random_module(seed);
+            let text = disassemble(&m.borrow());
+            let again = assemble(&text).unwrap_or_else(|e| panic!("seed {}: {}", seed, e));
+            let (m, again) = (m.borrow(), again.borrow());
+            assert_eq!(m.code, again.code, "seed {}", seed);
+            assert_eq!(m.trace_info, again.trace_info, "seed {}", seed);
+            assert_eq!(m.globals.len(), again.globals.len(), "seed {}", seed);
+            let mut seen = HashMap::new();
+            for (a, b) in m.globals.iter().zip(again.globals.iter()) {
+                assert!(same(a, b, &mut seen), "seed {}", seed);
+            }
+            assert_eq!(disassemble(&again), text, "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn report_errors_with_line() {
+        let err = |src| assemble(src).err().unwrap();
+        assert_eq!(err("Nop\nJump nowhere").line, 2);
+        assert_eq!(
+            err("Frobnicate").message,
+            "unknown instruction 'Frobnicate'"
+        );
+        assert_eq!(err("LoadInt").line, 1);
+    }
+}
@@ -0,0 +1,500 @@
+//! Arbitrary-precision integers, the values of `Value::BigInt`.
+//!
+//! A `BigInt` is kept normalized: no most significant zero limbs and no
+//! negative zero. Integer arithmetic promotes to it when a result doesn't fit
+//! in an `i64`, and `Value::from_bigint` turns results that fit back into
+//! `Value::Int`, so the same number never has two representations.
+
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::fmt;
+
+/// Largest count `<<` shifts by, so a shift can't ask for more memory than
+/// a result of `2^MAX_SHIFT` needs (8 MiB of limbs).
+pub const MAX_SHIFT: usize = 1 << 26;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BigInt {
+    negative: bool,
+    /// Magnitude in base 2^32, least significant limb first.
+    limbs: Vec<u32>,
+}
+
+impl BigInt {
+    /// Number with the given sign and magnitude, as stored in bytecode.
+    pub fn from_parts(negative: bool, limbs: Vec<u32>) -> BigInt {
+        let mut x = BigInt { negative, limbs };
+        x.normalize();
+        x
+    }
+
+    pub fn negative(&self) -> bool {
+        self.negative
+    }
+
+    pub fn limbs(&self) -> &[u32] {
+        &self.limbs
+    }
+
+    pub fn from_i64(x: i64) -> BigInt {
+        let magnitude = x.unsigned_abs();
+        BigInt::from_parts(x < 0, vec![magnitude as u32, (magnitude >> 32) as u32])
+    }
+
+    pub fn to_i64(&self) -> Option<i64> {
+        if self.limbs.len() > 2 {
+            return None;
+        }
+        let magnitude = self
+            .limbs
+            .iter()
+            .rev()
+            .fold(0u64, |acc, &limb| (acc << 32) | limb as u64);
+        if self.negative {
+            0i64.checked_sub_unsigned(magnitude)
+        } else {
+            i64::try_from(magnitude).ok()
+        }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        let magnitude = self
+            .limbs
+            .iter()
+            .rev()
+            .fold(0.0, |acc, &limb| acc * 4294967296.0 + limb as f64);
+        if self.negative {
+            -magnitude
+        } else {
+            magnitude
+        }
+    }
+
+    /// The integer `x`, which must be finite and have no fractional part.
+    pub fn from_f64(x: f64) -> BigInt {
+        let bits = x.to_bits();
+        let exponent = ((bits >> 52) & 0x7ff) as i32;
+        let mut mantissa = bits & ((1 << 52) - 1);
+        if exponent != 0 {
+            mantissa |= 1 << 52;
+        }
+        // x = mantissa * 2^shift
+        let shift = exponent - 1075;
+        if shift <= 0 {
+            let magnitude = mantissa.checked_shr(-shift as u32).unwrap_or(0);
+            let limbs = vec![magnitude as u32, (magnitude >> 32) as u32];
+            return BigInt::from_parts(x < 0.0, limbs);
+        }
+        let wide = (mantissa as u128) << (shift % 32);
+        let mut limbs = vec![0; (shift / 32) as usize];
+        limbs.extend(&[wide as u32, (wide >> 32) as u32, (wide >> 64) as u32]);
+        BigInt::from_parts(x < 0.0, limbs)
+    }
+
+    /// Parse digits in base `radix` with an optional sign.
+    pub fn parse(text: &str, radix: u32) -> Option<BigInt> {
+        let (negative, digits) = match text.as_bytes().first() {
+            Some(b'-') => (true, &text[1..]),
+            Some(b'+') => (false, &text[1..]),
+            _ => (false, text),
+        };
+        if digits.is_empty() {
+            return None;
+        }
+        let mut limbs = vec![];
+        for ch in digits.chars() {
+            let digit = ch.to_digit(radix)?;
+            mul_small_add(&mut limbs, radix, digit);
+        }
+        Some(BigInt::from_parts(negative, limbs))
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.limbs.is_empty()
+    }
+
+    pub fn neg(&self) -> BigInt {
+        BigInt::from_parts(!self.negative, self.limbs.clone())
+    }
+
+    pub fn add(&self, other: &BigInt) -> BigInt {
+        if self.negative == other.negative {
+            return BigInt::from_parts(self.negative, add_mag(&self.limbs, &other.limbs));
+        }
+        match cmp_mag(&self.limbs, &other.limbs) {
+            Ordering::Less => {
+                BigInt::from_parts(other.negative, sub_mag(&other.limbs, &self.limbs))
+            }
+            _ => BigInt::from_parts(self.negative, sub_mag(&self.limbs, &other.limbs)),
+        }
+    }
+
+    pub fn sub(&self, other: &BigInt) -> BigInt {
+        self.add(&other.neg())
+    }
+
+    pub fn mul(&self, other: &BigInt) -> BigInt {
+        let mut limbs = vec![0u32; self.limbs.len() + other.limbs.len()];
+        for (i, &x) in self.limbs.iter().enumerate() {
+            let mut carry = 0u64;
+            for (j, &y) in other.limbs.iter().enumerate() {
+                let t = limbs[i + j] as u64 + x as u64 * y as u64 + carry;
+                limbs[i + j] = t as u32;
+                carry = t >> 32;
+            }
+            limbs[i + other.limbs.len()] = carry as u32;
+        }
+        BigInt::from_parts(self.negative != other.negative, limbs)
+    }
+
+    /// Quotient rounded toward zero and remainder with the sign of `self`,
+    /// like `i64` division. `None` when dividing by zero.
+    pub fn div_rem(&self, other: &BigInt) -> Option<(BigInt, BigInt)> {
+        if other.is_zero() {
+            return None;
+        }
+        let (quotient, remainder) = div_rem_mag(&self.limbs, &other.limbs);
+        Some((
+            BigInt::from_parts(self.negative != other.negative, quotient),
+            BigInt::from_parts(self.negative, remainder),
+        ))
+    }
+
+    /// Bitwise `&`, `|` or `^`, given as `f` on limbs, of the infinite two's
+    /// complement forms, like for `i64`.
+    pub fn bitwise(&self, other: &BigInt, f: impl Fn(u32, u32) -> u32) -> BigInt {
+        let len = self.limbs.len().max(other.limbs.len()) + 1;
+        let (x, y) = (self.twos(len), other.twos(len));
+        let limbs = x.iter().zip(y.iter()).map(|(&x, &y)| f(x, y)).collect();
+        BigInt::from_twos(limbs)
+    }
+
+    /// Bitwise complement `-self - 1`.
+    pub fn not(&self) -> BigInt {
+        self.neg().sub(&BigInt::from_i64(1))
+    }
+
+    /// `self * 2^n`.
+    pub fn shl(&self, n: usize) -> BigInt {
+        let mut limbs = vec![0; n / 32];
+        let mut carry = 0;
+        for &limb in self.limbs.iter() {
+            let wide = (limb as u64) << (n % 32);
+            limbs.push(wide as u32 | carry);
+            carry = (wide >> 32) as u32;
+        }
+        limbs.push(carry);
+        BigInt::from_parts(self.negative, limbs)
+    }
+
+    /// `self / 2^n` rounded toward negative infinity, like `>>` on `i64`.
+    pub fn shr(&self, n: usize) -> BigInt {
+        if self.negative {
+            // -x >> n == -((x - 1 >> n) + 1)
+            let x = self.neg().sub(&BigInt::from_i64(1));
+            return x.shr(n).add(&BigInt::from_i64(1)).neg();
+        }
+        let skip = n / 32;
+        if skip >= self.limbs.len() {
+            return BigInt::from_i64(0);
+        }
+        let limbs = &self.limbs[skip..];
+        let shift = (n % 32) as u32;
+        let limbs = (0..limbs.len())
+            .map(|i| {
+                let high = limbs.get(i + 1).cloned().unwrap_or(0) as u64;
+                (((high << 32) | limbs[i] as u64) >> shift) as u32
+            })
+            .collect();
+        BigInt::from_parts(false, limbs)
+    }
+
+    /// Two's complement form in `len` limbs, which must be enough to hold
+    /// the magnitude and a sign bit.
+    fn twos(&self, len: usize) -> Vec<u32> {
+        let mut limbs = self.limbs.clone();
+        limbs.resize(len, 0);
+        if self.negative {
+            let mut carry = 1u64;
+            for limb in limbs.iter_mut() {
+                let t = (!*limb) as u64 + carry;
+                *limb = t as u32;
+                carry = t >> 32;
+            }
+        }
+        limbs
+    }
+
+    fn from_twos(mut limbs: Vec<u32>) -> BigInt {
+        let negative = limbs.last().map_or(false, |&limb| limb >> 31 == 1);
+        if negative {
+            let mut carry = 1u64;
+            for limb in limbs.iter_mut() {
+                let t = (!*limb) as u64 + carry;
+                *limb = t as u32;
+                carry = t >> 32;
+            }
+        }
+        BigInt::from_parts(negative, limbs)
+    }
+
+    fn normalize(&mut self) {
+        while self.limbs.last() == Some(&0) {
+            self.limbs.pop();
+        }
+        if self.limbs.is_empty() {
+            self.negative = false;
+        }
+    }
+}
+
+fn cmp_mag(x: &[u32], y: &[u32]) -> Ordering {
+    x.len()
+        .cmp(&y.len())
+        .then_with(|| x.iter().rev().cmp(y.iter().rev()))
+}
+
+fn add_mag(x: &[u32], y: &[u32]) -> Vec<u32> {
+    let (long, short) = if x.len() >= y.len() { (x, y) } else { (y, x) };
+    let mut limbs = Vec::with_capacity(long.len() + 1);
+    let mut carry = 0u64;
+    for (i, &limb) in long.iter().enumerate() {
+        let t = limb as u64 + short.get(i).cloned().unwrap_or(0) as u64 + carry;
+        limbs.push(t as u32);
+        carry = t >> 32;
+    }
+    limbs.push(carry as u32);
+    limbs
+}
+
+/// `x - y` for `x >= y`.
+fn sub_mag(x: &[u32], y: &[u32]) -> Vec<u32> {
+    let mut limbs = Vec::with_capacity(x.len());
+    let mut borrow = 0i64;
+    for (i, &limb) in x.iter().enumerate() {
+        let mut t = limb as i64 - y.get(i).cloned().unwrap_or(0) as i64 - borrow;
+        borrow = 0;
+        if t < 0 {
+            t += 1 << 32;
+            borrow = 1;
+        }
+        limbs.push(t as u32);
+    }
+    limbs
+}
+
+fn mul_small_add(limbs: &mut Vec<u32>, factor: u32, addend: u32) {
+    let mut carry = addend as u64;
+    for limb in limbs.iter_mut() {
+        let t = *limb as u64 * factor as u64 + carry;
+        *limb = t as u32;
+        carry = t >> 32;
+    }
+    if carry != 0 {
+        limbs.push(carry as u32);
+    }
+}
+
+/// Divide in place by a single limb and return the remainder.
+fn div_small(limbs: &mut [u32], divisor: u32) -> u32 {
+    let mut remainder = 0u64;
+    for limb in limbs.iter_mut().rev() {
+        let t = (remainder << 32) | *limb as u64;
+        *limb = (t / divisor as u64) as u32;
+        remainder = t % divisor as u64;
+    }
+    remainder as u32
+}
+
+/// Schoolbook binary long division of magnitudes.
+fn div_rem_mag(x: &[u32], y: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    if y.len() == 1 {
+        let mut quotient = x.to_vec();
+        let remainder = div_small(&mut quotient, y[0]);
+        return (quotient, vec![remainder]);
+    }
+    let mut quotient = vec![0u32; x.len()];
+    let mut remainder: Vec<u32> = vec![];
+    for i in (0..x.len() * 32).rev() {
+        // remainder = remainder * 2 + bit i of x
+        let mut carry = (x[i / 32] >> (i % 32)) & 1;
+        for limb in remainder.iter_mut() {
+            let next = *limb >> 31;
+            *limb = (*limb << 1) | carry;
+            carry = next;
+        }
+        if carry != 0 {
+            remainder.push(carry);
+        }
+        if cmp_mag(&remainder, y) != Ordering::Less {
+            remainder = sub_mag(&remainder, y);
+            while remainder.last() == Some(&0) {
+                remainder.pop();
+            }
+            quotient[i / 32] |= 1 << (i % 32);
+        }
+    }
+    (quotient, remainder)
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &BigInt) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_mag(&self.limbs, &other.limbs),
+            (true, true) => cmp_mag(&other.limbs, &self.limbs),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &BigInt) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_zero() {
+            return write!(f, "0");
+        }
+        // Base 10^9 chunks, least significant first.
+        let mut limbs = self.limbs.clone();
+        let mut chunks = vec![];
+        while !limbs.is_empty() {
+            chunks.push(div_small(&mut limbs, 1_000_000_000));
+            while limbs.last() == Some(&0) {
+                limbs.pop();
+            }
+        }
+        if self.negative {
+            write!(f, "-")?;
+        }
+        write!(f, "{}", chunks.last().unwrap())?;
+        for chunk in chunks.iter().rev().skip(1) {
+            write!(f, "{:09}", chunk)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn big(text: &str) -> BigInt {
+        BigInt::parse(text, 10).unwrap()
+    }
+
+    #[test]
+    fn round_trips_through_strings() {
+        for text in &[
+            "0",
+            "-1",
+            "18446744073709551616",
+            "-123456789012345678901234567890",
+        ] {
+            assert_eq!(big(text).to_string(), *text);
+        }
+        assert_eq!(BigInt::parse("-0", 10), Some(BigInt::from_i64(0)));
+        assert_eq!(BigInt::parse("ff", 16), Some(BigInt::from_i64(255)));
+        assert_eq!(BigInt::parse("", 10), None);
+        assert_eq!(BigInt::parse("12a", 10), None);
+    }
+
+    #[test]
+    fn converts_from_and_to_i64() {
+        for &x in &[0, 1, -1, i64::MAX, i64::MIN] {
+            assert_eq!(BigInt::from_i64(x).to_i64(), Some(x));
+            assert_eq!(BigInt::from_i64(x).to_string(), x.to_string());
+        }
+        assert_eq!(big("9223372036854775808").to_i64(), None);
+        assert_eq!(big("-9223372036854775809").to_i64(), None);
+    }
+
+    #[test]
+    fn converts_integral_floats() {
+        for &x in &[
+            0.0,
+            -0.0,
+            1.0,
+            -3.0,
+            4503599627370497.0,
+            -9007199254740993.0,
+        ] {
+            assert_eq!(BigInt::from_f64(x), BigInt::from_i64(x as i64));
+        }
+        assert_eq!(BigInt::from_f64(1e20).to_string(), "100000000000000000000");
+        assert_eq!(
+            BigInt::from_f64(-2f64.powi(70)).to_string(),
+            "-1180591620717411303424"
+        );
+        assert_eq!(BigInt::from_f64(2f64.powi(100)).to_f64(), 2f64.powi(100));
+    }
+
+    #[test]
+    fn arithmetic_matches_i64_where_it_fits() {
+        let values = [0, 1, -1, 7, -7, 1 << 40, -(1 << 40) + 3, 123_456_789_012];
+        for &x in values.iter() {
+            for &y in values.iter() {
+                let (bx, by) = (BigInt::from_i64(x), BigInt::from_i64(y));
+                assert_eq!(bx.add(&by).to_i64(), Some(x + y));
+                assert_eq!(bx.sub(&by).to_i64(), Some(x - y));
+                assert_eq!(bx.cmp(&by), x.cmp(&y));
+                if let Some(product) = x.checked_mul(y) {
+                    assert_eq!(bx.mul(&by).to_i64(), Some(product));
+                }
+                if y != 0 {
+                    let (q, r) = bx.div_rem(&by).unwrap();
+                    assert_eq!((q.to_i64(), r.to_i64()), (Some(x / y), Some(x % y)));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn bit_operations_match_i64_where_they_fit() {
+        let values = [0, 1, -1, 7, -8, 1 << 40, -(1 << 40) + 3, i64::MAX, i64::MIN];
+        for &x in values.iter() {
+            let bx = BigInt::from_i64(x);
+            assert_eq!(bx.not().to_i64(), Some(!x));
+            for n in 0..70 {
+                assert_eq!(bx.shr(n).to_i64(), Some(x >> n.min(63)));
+            }
+            for &y in values.iter() {
+                let by = BigInt::from_i64(y);
+                assert_eq!(bx.bitwise(&by, |x, y| x & y).to_i64(), Some(x & y));
+                assert_eq!(bx.bitwise(&by, |x, y| x | y).to_i64(), Some(x | y));
+                assert_eq!(bx.bitwise(&by, |x, y| x ^ y).to_i64(), Some(x ^ y));
+            }
+        }
+        assert_eq!(
+            BigInt::from_i64(1).shl(70).to_string(),
+            "1180591620717411303424"
+        );
+        assert_eq!(
+            BigInt::from_i64(-3).shl(64).to_string(),
+            "-55340232221128654848"
+        );
+        let x = big("-1180591620717411303425");
+        assert_eq!(x.shr(70).to_i64(), Some(-2));
+        assert_eq!(x.shl(3).shr(3), x);
+        assert_eq!(x.bitwise(&BigInt::from_i64(-1), |x, y| x & y), x);
+        assert_eq!(x.bitwise(&x.not(), |x, y| x ^ y).to_i64(), Some(-1));
+    }
+
+    #[test]
+    fn large_arithmetic() {
+        let x = big("340282366920938463463374607431768211457");
+        let y = big("-18446744073709551629");
+        assert_eq!(
+            x.mul(&y).to_string(),
+            "-6277101735386680768259460193179866441144672085150730813453"
+        );
+        let (q, r) = x.div_rem(&y).unwrap();
+        assert_eq!(q.to_string(), "-18446744073709551603");
+        assert_eq!(q.mul(&y).add(&r), x);
+        assert!(x.div_rem(&BigInt::from_i64(0)).is_none());
+    }
+}
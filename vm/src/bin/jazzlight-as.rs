@@ -0,0 +1,46 @@
+use jazzlight::asm::assemble;
+use jazzlight::writer::BytecodeWriter;
+
+fn main() {
+    let mut file = None;
+    let mut output = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "-o" {
+            output = args.next();
+        } else {
+            file = Some(arg);
+        }
+    }
+    let file = match file {
+        Some(file) => file,
+        None => {
+            eprintln!("Usage: jazzlight-as <file.jasm> [-o <file.j>]");
+            std::process::exit(1);
+        }
+    };
+    let src = match std::fs::read_to_string(&file) {
+        Ok(src) => src,
+        Err(e) => {
+            eprintln!("Failed to open '{}': {}", file, e);
+            std::process::exit(1);
+        }
+    };
+    let m = match assemble(&src) {
+        Ok(m) => m,
+        Err(e) => {
+            eprintln!("{}:{}", file, e);
+            std::process::exit(1);
+        }
+    };
+    let output = output.unwrap_or_else(|| {
+        let path = std::path::Path::new(&file);
+        format!("{}.j", path.file_stem().unwrap().to_str().unwrap())
+    });
+    let mut w = BytecodeWriter { bytecode: vec![] };
+    w.write_module(m);
+    if let Err(e) = std::fs::write(&output, &w.bytecode) {
+        eprintln!("Failed to write '{}': {}", output, e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,35 @@
+use jazzlight::asm::disassemble;
+use jazzlight::reader::{validate, BytecodeError, BytecodeReader};
+use jazzlight::verifier::verify;
+
+fn main() {
+    let file = match std::env::args().nth(1) {
+        Some(file) => file,
+        None => {
+            eprintln!("Usage: jazzlight-dis <file.j>");
+            std::process::exit(1);
+        }
+    };
+    let contents = match std::fs::read(&file) {
+        Ok(contents) => contents,
+        Err(e) => {
+            eprintln!("Failed to open '{}': {}", file, e);
+            std::process::exit(1);
+        }
+    };
+    // Malformed code is listed too, problems with it are reported after the listing.
+    let m = match BytecodeReader::new(&contents).parse_module() {
+        Ok(m) => m,
+        Err(e) => {
+            eprintln!("Failed to load '{}': {}", file, e);
+            std::process::exit(1);
+        }
+    };
+    let m = m.borrow();
+    print!("{}", disassemble(&m));
+    let check = validate(&m).and_then(|_| verify(&m).map_err(BytecodeError::Verify));
+    if let Err(e) = check {
+        eprintln!("'{}' is invalid: {}", file, e);
+        std::process::exit(1);
+    }
+}
@@ -13,9 +13,31 @@ pub fn get_builtin(field: &str) -> Option<Value> {
     BUILTINS.with(|builtins| builtins.get(field).cloned())
 }
 
+thread_local! {
+    /// When set `$print` writes here instead of stdout, see `capture_output`.
+    static OUTPUT: std::cell::RefCell<Option<String>> = std::cell::RefCell::new(None);
+}
+
+/// Run `f` and return everything it printed with `$print`.
+pub fn capture_output(f: impl FnOnce()) -> String {
+    let saved = OUTPUT.with(|out| out.borrow_mut().replace(String::new()));
+    f();
+    OUTPUT.with(|out| std::mem::replace(&mut *out.borrow_mut(), saved).unwrap_or_default())
+}
+
 pub fn builtin_print(args: &[Value]) -> Result<Value, Value> {
     for val in args.iter() {
-        print!("{}", val);
+        let text = format::display(val)?;
+        let captured = OUTPUT.with(|out| match &mut *out.borrow_mut() {
+            Some(out) => {
+                out.push_str(&text);
+                true
+            }
+            None => false,
+        });
+        if !captured {
+            print!("{}", text);
+        }
     }
     Ok(Value::Null)
 }
@@ -37,6 +59,42 @@ pub fn builtin_apply(args: &[Value]) -> Result<Value, Value> {
     }
 }
 
+/// `$bind(f, this, args...)`: function calling `f` with `this` and `args`
+/// followed by its own arguments.
+pub fn builtin_bind(args: &[Value]) -> Result<Value, Value> {
+    match args.get(0) {
+        Some(Value::Function(f)) => {
+            let this = args.get(1).cloned().unwrap_or(Value::Null);
+            let bound = f.borrow().bind(this, args.get(2..).unwrap_or(&[]));
+            Ok(Value::Function(Ref(bound)))
+        }
+        _ => Err(Value::String(Ref("bind: Function expected".to_owned()))),
+    }
+}
+
+/// `$method(object, name)`, what `object::name` compiles to: the field like
+/// `object.name`, but bound to `object` if it's a function.
+pub fn builtin_method(args: &[Value]) -> Result<Value, Value> {
+    let field = match &args[0] {
+        Value::Object(object) => {
+            let field = object.borrow().get(args[1].clone());
+            match field {
+                Some(field) => field,
+                None => meta::missing_field(object, &args[1])?,
+            }
+        }
+        Value::User(_) => match &args[1] {
+            Value::String(name) => meta::method(&args[0], &name.borrow()).unwrap_or(Value::Null),
+            _ => Value::Null,
+        },
+        _ => return Err(Value::String(Ref("method: Object expected".to_owned()))),
+    };
+    match field {
+        Value::Function(f) => Ok(Value::Function(Ref(f.borrow().bind(args[0].clone(), &[])))),
+        field => Ok(field),
+    }
+}
+
 pub fn builtin_array(args: &[Value]) -> Result<Value, Value> {
     Ok(Value::Array(Ref(args.to_vec())))
 }
@@ -78,6 +136,69 @@ pub fn builtin_acopy(args: &[Value]) -> Result<Value, Value> {
     }
 }
 
+/// `$asub(array, pos, len)`: new array with `len` elements of `array` starting at `pos`.
+pub fn builtin_asub(args: &[Value]) -> Result<Value, Value> {
+    match (&args[0], &args[1], &args[2]) {
+        (Value::Array(array), Value::Int(pos), Value::Int(len)) => {
+            let array = array.borrow();
+            let (pos, len) = (*pos as usize, *len as usize);
+            match array.get(pos..pos.saturating_add(len)) {
+                Some(sub) => Ok(Value::Array(Ref(sub.to_vec()))),
+                None => Err(Value::String(Ref("asub: Out of bounds".to_owned()))),
+            }
+        }
+        _ => Err(Value::String(Ref(
+            "asub: Array and two integers expected".to_owned()
+        ))),
+    }
+}
+
+/// `$aconcat(arrays...)`: new array with the elements of all arguments.
+pub fn builtin_aconcat(args: &[Value]) -> Result<Value, Value> {
+    let mut result = vec![];
+    for array in args.iter() {
+        match array {
+            Value::Array(array) => result.extend(array.borrow().iter().cloned()),
+            _ => return Err(Value::String(Ref("aconcat: Array expected".to_owned()))),
+        }
+    }
+    Ok(Value::Array(Ref(result)))
+}
+
+/// `$call(f, args)`: call `f` with the elements of array `args` as arguments.
+pub fn builtin_call(args: &[Value]) -> Result<Value, Value> {
+    let array = match &args[1] {
+        Value::Array(array) => array.borrow().clone(),
+        _ => {
+            return Err(Value::String(Ref(
+                "call: Array of arguments expected".to_owned()
+            )))
+        }
+    };
+    match &args[0] {
+        Value::Function(f) if f.borrow().native && f.borrow().bound.is_none() => {
+            let f = f.borrow();
+            f.check_args(array.len())?;
+            let fun: fn(&[Value]) -> Result<Value, Value> =
+                unsafe { std::mem::transmute(f.address) };
+            fun(&array)
+        }
+        Value::Function(_) => val_callex(args[0].clone(), Value::Null, &array),
+        _ => Err(Value::String(Ref("call: Function expected".to_owned()))),
+    }
+}
+
+/// `$objfield(object, field)`: whether `object` or its prototypes have `field`.
+pub fn builtin_objfield(args: &[Value]) -> Result<Value, Value> {
+    match &args[0] {
+        Value::Object(object) => Ok(Value::Bool(
+            object.borrow().get(args[1].clone()).is_some()
+                || meta::getter(object, &args[1]).is_some(),
+        )),
+        _ => Ok(Value::Bool(false)),
+    }
+}
+
 pub fn builtin_scopy(args: &[Value]) -> Result<Value, Value> {
     match &args[0] {
         Value::String(s) => Ok(Value::String(Ref(s.borrow().to_owned()))),
@@ -139,43 +260,114 @@ pub fn builtin_sfind(args: &[Value]) -> Result<Value, Value> {
     }
 }
 
+/// `$instanceof(object, proto)`: whether `proto` is anywhere on the
+/// prototype chain of `object`. An object without prototype is an instance of
+/// itself.
 pub fn builtin_instanceof(args: &[Value]) -> Result<Value, Value> {
     match &args[0] {
         Value::Object(obj) => match &args[1] {
-            Value::Object(obj2) => match &obj.borrow().prototype {
-                Some(proto) => return Ok(Value::Bool(Rc::ptr_eq(proto, obj2))),
-                None => return Ok(Value::Bool(Rc::ptr_eq(obj, obj2))),
-            },
+            Value::Object(obj2) => {
+                let mut current = match &obj.borrow().prototype {
+                    Some(proto) => proto.clone(),
+                    None => return Ok(Value::Bool(Rc::ptr_eq(obj, obj2))),
+                };
+                loop {
+                    if Rc::ptr_eq(&current, obj2) {
+                        return Ok(Value::Bool(true));
+                    }
+                    let next = match &current.borrow().prototype {
+                        Some(proto) => proto.clone(),
+                        None => return Ok(Value::Bool(false)),
+                    };
+                    current = next;
+                }
+            }
             _ => return Ok(Value::Bool(false)),
         },
         _ => return Ok(Value::Bool(false)),
     }
 }
 
+/// `$construct(proto, args...)`: create an object with prototype `proto` and
+/// call its `init` method with `args`. Types declared with `type` store it as
+/// their `new` method.
+pub fn builtin_construct(args: &[Value]) -> Result<Value, Value> {
+    let proto = match args.get(0) {
+        Some(Value::Object(proto)) => proto.clone(),
+        _ => return Err(Value::String(Ref("construct: Object expected".to_owned()))),
+    };
+    let object = Value::Object(Ref(Object {
+        prototype: Some(proto.clone()),
+        table: hashlink::LinkedHashMap::new(),
+    }));
+    let init = proto.borrow().get(Value::String(Ref("init".to_owned())));
+    match init {
+        Some(init @ Value::Function(_)) => {
+            val_callex(init, object.clone(), &args[1..])?;
+        }
+        _ if args.len() > 1 => {
+            return Err(Value::String(Ref(format!(
+                "Expected 0 arguments,found {}",
+                args.len() - 1
+            ))))
+        }
+        _ => (),
+    }
+    Ok(object)
+}
+
 pub fn builtin_string(args: &[Value]) -> Result<Value, Value> {
-    let value = args[0].to_string();
+    let value = format::display(&args[0])?;
     return Ok(Value::String(Ref(value)));
 }
+
+/// `$repr(x)`: debug form of `x`, see `format`.
+pub fn builtin_repr(args: &[Value]) -> Result<Value, Value> {
+    Ok(Value::String(Ref(format::repr(&args[0])?)))
+}
+/// `$compare(a, b)`: -1, 0 or 1 as `a` is less than, equal to or greater
+/// than `b`, for sort routines.
+pub fn builtin_compare(args: &[Value]) -> Result<Value, Value> {
+    Ok(Value::Int(numeric::total_order(&args[0], &args[1])? as i64))
+}
+/// `$int(x)`: integer value of a number, char or decimal string, null for
+/// strings that aren't integers. Long strings give big integers.
+/// Integer value of a number, char or decimal string. Floats are truncated
+/// toward zero and become big integers when they don't fit in an `i64`.
+pub fn builtin_int(args: &[Value]) -> Result<Value, Value> {
+    Ok(match &args[0] {
+        Value::Int(_) | Value::BigInt(_) => args[0].clone(),
+        Value::Float(x) if !x.is_finite() => {
+            return Err(Value::String(Ref(format!(
+                "ValueError: cannot convert {} to an integer",
+                args[0]
+            ))))
+        }
+        Value::Float(x) => Value::from_bigint(bigint::BigInt::from_f64(*x)),
+        Value::Char(x) => Value::Int(*x as i64),
+        Value::String(s) => match bigint::BigInt::parse(s.borrow().trim(), 10) {
+            Some(x) => Value::from_bigint(x),
+            None => Value::Null,
+        },
+        _ => Value::Null,
+    })
+}
+
+/// Hash of any value, equal for values that are `==` as object keys.
+pub fn builtin_hash(args: &[Value]) -> Result<Value, Value> {
+    use std::hash::{Hash, Hasher};
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    args[0].hash(&mut hasher);
+    Ok(Value::Int(hasher.finish() as i64))
+}
+
 pub fn builtin_typeof(args: &[Value]) -> Result<Value, Value> {
-    let tag = args[0].tag();
-    Ok(Value::String(Ref(match tag {
-        ValTag::Array => "array",
-        ValTag::Null => "null",
-        ValTag::Float => "float",
-        ValTag::Int => "int",
-        ValTag::Str => "string",
-        ValTag::Bool => "bool",
-        ValTag::Object => "object",
-        ValTag::Char => "char",
-        ValTag::Func => "function",
-        ValTag::User(x) => x,
-    }
-    .to_owned())))
+    Ok(Value::String(Ref(args[0].type_name().to_owned())))
 }
 
 pub fn builtin_nargs(args: &[Value]) -> Result<Value, Value> {
     match &args[0] {
-        Value::Function(fun) => Ok(Value::Int(fun.borrow().argc as _)),
+        Value::Function(fun) => Ok(Value::Int(fun.borrow().arity() as _)),
         _ => Ok(Value::Null),
     }
 }
@@ -206,7 +398,15 @@ pub fn builtin_load(args: &[Value]) -> Result<Value, Value> {
                 bytes: std::io::Cursor::new(&contents),
             };
 
-            let m = r.read_module();
+            let m = match r.read_module() {
+                Ok(m) => m,
+                Err(e) => {
+                    return Err(Value::String(Ref(format!(
+                        "load: invalid module at '{}': {}",
+                        path, e
+                    ))))
+                }
+            };
 
             let mut vm = Vm::new();
             vm.save_state_exit();
@@ -271,6 +471,8 @@ pub fn new_native_fn(x: fn(&[Value]) -> Result<Value, Value>, argc: i32) -> Valu
         env: Value::Null,
         module: None,
         argc,
+        calls: Default::default(),
+        bound: None,
     }))
 }
 
@@ -284,9 +486,19 @@ pub fn builtins_init() -> HashMap<String, Value> {
     map.insert("apush".to_owned(), new_native_fn(builtin_apush, 2));
     map.insert("apop".to_owned(), new_native_fn(builtin_apop, 0));
     map.insert("acopy".to_owned(), new_native_fn(builtin_acopy, 1));
+    map.insert("asub".to_owned(), new_native_fn(builtin_asub, 3));
+    map.insert("objfield".to_owned(), new_native_fn(builtin_objfield, 2));
+    map.insert("aconcat".to_owned(), new_native_fn(builtin_aconcat, -1));
+    map.insert("call".to_owned(), new_native_fn(builtin_call, 2));
+    map.insert("bind".to_owned(), new_native_fn(builtin_bind, -1));
+    map.insert("method".to_owned(), new_native_fn(builtin_method, 2));
     map.insert("nargs".to_owned(), new_native_fn(builtin_nargs, 1));
     map.insert("typeof".to_owned(), new_native_fn(builtin_typeof, 1));
     map.insert("string".to_owned(), new_native_fn(builtin_string, 1));
+    map.insert("int".to_owned(), new_native_fn(builtin_int, 1));
+    map.insert("hash".to_owned(), new_native_fn(builtin_hash, 1));
+    map.insert("repr".to_owned(), new_native_fn(builtin_repr, 1));
+    map.insert("compare".to_owned(), new_native_fn(builtin_compare, 2));
     map.insert("load".to_owned(), new_native_fn(builtin_load, 1));
     map.insert(
         "load_native".to_owned(),
@@ -306,6 +518,7 @@ pub fn builtins_init() -> HashMap<String, Value> {
         "instanceof".to_owned(),
         new_native_fn(builtin_instanceof, 2),
     );
+    map.insert("construct".to_owned(), new_native_fn(builtin_construct, -1));
 
     io::file_builtins(&mut map);
     return map;
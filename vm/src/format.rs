@@ -0,0 +1,243 @@
+//! Text forms of values.
+//!
+//! - `Display` (and `Value::to_string`) is the plain form used in error
+//!   messages and string concatenation; it never runs script code.
+//! - `display` is what `$print` and `$string` produce: the plain form, except
+//!   that objects with a `__string` method on their prototype chain print as
+//!   the result of calling it.
+//! - `repr` is the debug form returned by `$repr`: strings and chars are
+//!   quoted and escaped, floats always have a decimal point or exponent, big
+//!   integers get their `L` suffix and objects print as `{key: value}`.
+//!
+//! In every form a container that contains itself prints `<cycle>` where it
+//! recurs, and containers with more than `MAX_ITEMS` entries are cut off
+//! with `...`.
+
+use crate::interp::val_callex;
+use crate::value::{Object, Value};
+use crate::*;
+use std::fmt::Write;
+
+/// Entries of an array or object printed before the rest is elided.
+pub const MAX_ITEMS: usize = 100;
+
+/// Field holding the method that converts an object to a string.
+pub const STRING_METHOD: &str = "__string";
+
+thread_local! {
+    /// Objects whose `__string` method is running. Printing one of them again
+    /// from inside the method uses the plain form instead of recursing.
+    static IN_HOOK: RefCell<Vec<usize>> = RefCell::new(vec![]);
+}
+
+pub fn display(value: &Value) -> Result<String, Value> {
+    let mut printer = Printer::new(false, true);
+    printer.value(value)?;
+    Ok(printer.out)
+}
+
+pub fn repr(value: &Value) -> Result<String, Value> {
+    let mut printer = Printer::new(true, true);
+    printer.value(value)?;
+    Ok(printer.out)
+}
+
+/// Plain form without `__string` calls, what `Display for Value` writes.
+pub fn plain(value: &Value) -> String {
+    let mut printer = Printer::new(false, false);
+    match printer.value(value) {
+        Ok(()) => printer.out,
+        Err(_) => unreachable!("printing without hooks can't fail"),
+    }
+}
+
+struct Printer {
+    out: String,
+    repr: bool,
+    /// Call `__string` methods.
+    hooks: bool,
+    /// Containers being printed, to detect cycles.
+    path: Vec<usize>,
+}
+
+impl Printer {
+    fn new(repr: bool, hooks: bool) -> Printer {
+        Printer {
+            out: String::new(),
+            repr,
+            hooks,
+            path: vec![],
+        }
+    }
+
+    fn value(&mut self, value: &Value) -> Result<(), Value> {
+        match value {
+            Value::String(s) if self.repr => quote(&mut self.out, &s.borrow(), '"'),
+            Value::Char(x) if self.repr => quote(&mut self.out, &x.to_string(), '\''),
+            Value::Float(x) if self.repr => {
+                let _ = write!(self.out, "{:?}", x);
+            }
+            Value::BigInt(x) if self.repr => {
+                let _ = write!(self.out, "{}L", x);
+            }
+            Value::Array(array) => {
+                let ptr = Rc::as_ptr(array) as *const u8 as usize;
+                if self.path.contains(&ptr) {
+                    self.out.push_str("<cycle>");
+                    return Ok(());
+                }
+                self.path.push(ptr);
+                let separator = if self.repr { ", " } else { "," };
+                self.out.push('[');
+                for (idx, item) in array.borrow().iter().enumerate() {
+                    if idx > 0 {
+                        self.out.push_str(separator);
+                    }
+                    if idx == MAX_ITEMS {
+                        self.out.push_str("...");
+                        break;
+                    }
+                    self.value(item)?;
+                }
+                self.out.push(']');
+                self.path.pop();
+            }
+            Value::Object(object) => {
+                let ptr = Rc::as_ptr(object) as *const u8 as usize;
+                if self.path.contains(&ptr) {
+                    self.out.push_str("<cycle>");
+                    return Ok(());
+                }
+                self.path.push(ptr);
+                let result = self.object(value, object, ptr);
+                self.path.pop();
+                result?;
+            }
+            _ => {
+                let _ = write!(self.out, "{}", ScalarDisplay(value));
+            }
+        }
+        Ok(())
+    }
+
+    fn object(&mut self, value: &Value, object: &Ref<Object>, ptr: usize) -> Result<(), Value> {
+        let in_hook = IN_HOOK.with(|objects| objects.borrow().contains(&ptr));
+        if self.hooks && !in_hook {
+            let method = object
+                .borrow()
+                .get(Value::String(Ref(STRING_METHOD.to_owned())));
+            if let Some(method @ Value::Function(_)) = method {
+                IN_HOOK.with(|objects| objects.borrow_mut().push(ptr));
+                let result = val_callex(method, value.clone(), &[]);
+                IN_HOOK.with(|objects| objects.borrow_mut().pop());
+                match result? {
+                    Value::String(s) => self.out.push_str(&s.borrow()),
+                    result => self.out.push_str(&plain(&result)),
+                }
+                return Ok(());
+            }
+        }
+        let object = object.borrow();
+        if self.repr {
+            self.out.push('{');
+            for (i, (key, val)) in object.table.iter().enumerate() {
+                if i > 0 {
+                    self.out.push_str(", ");
+                }
+                if i == MAX_ITEMS {
+                    self.out.push_str("...");
+                    break;
+                }
+                match key {
+                    Value::String(s) if is_identifier(&s.borrow()) => {
+                        self.out.push_str(&s.borrow())
+                    }
+                    _ => self.value(key)?,
+                }
+                self.out.push_str(": ");
+                self.value(val)?;
+            }
+            self.out.push('}');
+        } else {
+            self.out.push_str("{\n");
+            for (i, (key, val)) in object.table.iter().enumerate() {
+                if i == MAX_ITEMS {
+                    self.out.push_str("  ...\n");
+                    break;
+                }
+                self.out.push_str("  ");
+                self.value(key)?;
+                self.out.push_str(" => ");
+                self.value(val)?;
+                if i < object.table.len() - 1 {
+                    self.out.push(',');
+                }
+                self.out.push('\n');
+            }
+            self.out.push('}');
+        }
+        Ok(())
+    }
+}
+
+/// `Display` of values without nested values.
+struct ScalarDisplay<'a>(&'a Value);
+
+impl std::fmt::Display for ScalarDisplay<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            Value::Int(x) => write!(f, "{}", x),
+            Value::BigInt(x) => write!(f, "{}", x),
+            Value::Float(x) => write!(f, "{}", x),
+            Value::Char(x) => write!(f, "{}", x),
+            Value::Function(func) => {
+                if func.borrow().native {
+                    write!(f, "<function {:x}>", func.borrow().address)
+                } else {
+                    write!(f, "<function at {:x}>", func.borrow().address)
+                }
+            }
+            Value::User(x) => write!(f, "{}", x.borrow()),
+            Value::Null => write!(f, "null"),
+            Value::String(s) => write!(f, "{}", *s.borrow()),
+            Value::Bool(x) => write!(f, "{}", x),
+            Value::Array(_) | Value::Object(_) => unreachable!(),
+        }
+    }
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(ch) if ch.is_alphabetic() || ch == '_' => {
+            chars.all(|ch| ch.is_alphanumeric() || ch == '_')
+        }
+        _ => false,
+    }
+}
+
+/// `s` between `quote`s with the escapes the lexer understands, other
+/// control characters as `\u{..}`.
+fn quote(out: &mut String, s: &str, quote: char) {
+    out.push(quote);
+    let mut chars = s.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            '\0' => out.push_str("\\0"),
+            '$' if quote == '"' && chars.peek() == Some(&'{') => out.push_str("\\$"),
+            ch if ch == quote => {
+                out.push('\\');
+                out.push(ch);
+            }
+            ch if ch.is_control() => {
+                let _ = write!(out, "\\u{{{:x}}}", ch as u32);
+            }
+            ch => out.push(ch),
+        }
+    }
+    out.push(quote);
+}
@@ -13,16 +13,31 @@ pub enum Infos {
     ),
 }
 
+use std::cmp::Ordering;
 use std::collections::HashMap;
 
+/// Exception handler installed by `CatchPush`.
+#[derive(Clone)]
+pub struct Handler {
+    /// Address of the catch block.
+    pub catch: usize,
+    pub state: Infos,
+    /// Length of the call stack (`info_stack`) when the handler was installed.
+    pub frames: usize,
+    /// Depth of the operand stack when the handler was installed.
+    pub depth: usize,
+}
+
 pub struct Vm {
     pub pc: usize,
     pub stack: Ref<Vec<Value>>,
-    pub exception_stack: Vec<(usize, Infos)>,
+    pub exception_stack: Vec<Handler>,
     pub info_stack: Vec<Infos>,
     pub env: Value,
     pub locals: Ref<HashMap<u16, Value>>,
     pub this: Value,
+    /// Source location of the exception that is currently unwinding.
+    pub error_info: Option<(usize, String)>,
 }
 
 thread_local! {
@@ -46,6 +61,7 @@ impl Vm {
             env: Value::Null,
             locals: Ref(HashMap::new()),
             this: Value::Null,
+            error_info: None,
         };
 
         vm
@@ -86,8 +102,366 @@ impl Vm {
         self.stack.borrow_mut()
     }
 
-    pub fn interp(&mut self, mut m: Ref<Module>) -> Value {
+    /// Remove handlers installed by frames that are no longer on the call stack.
+    fn drop_handlers(&mut self) {
+        let frames = self.info_stack.len();
+        while let Some(handler) = self.exception_stack.last() {
+            if handler.frames <= frames {
+                break;
+            }
+            self.exception_stack.pop();
+        }
+    }
+
+    /// Execute `op` if it only works with the operand stack, locals, environment
+    /// and module globals. Returns `Ok(false)` for control flow opcodes, those
+    /// are handled by `interp` itself.
+    pub fn step(&mut self, m: &Ref<Module>, op: &opcode::Op) -> Result<bool, Value> {
         use opcode::Op;
+        match op.clone() {
+            Op::LoadBuiltin(name) => {
+                if name == "exports" {
+                    self.stack().push(m.borrow().exports.clone());
+                    return Ok(true);
+                }
+                use crate::builtins::get_builtin;
+                let value = get_builtin(&name);
+                if let Some(value) = value {
+                    self.stack().push(value);
+                } else {
+                    return Err(Value::String(Ref(format!("Builtin '{}' not found", name))));
+                }
+            }
+            Op::LoadNull => self.stack().push(Value::Null),
+            Op::LoadInt(x) => self.stack().push(Value::Int(x)),
+            Op::LoadTrue => self.stack().push(Value::Bool(true)),
+            Op::LoadFalse => self.stack().push(Value::Bool(false)),
+            Op::LoadGlobal(idx) => {
+                let idx = idx as usize;
+                self.stack()
+                    .push(m.borrow().globals.get(idx).cloned().unwrap_or(Value::Null));
+            }
+            Op::LoadLocal(idx) => {
+                self.stack().push(
+                    self.locals
+                        .borrow()
+                        .get(&idx)
+                        .cloned()
+                        .unwrap_or(Value::Null),
+                );
+            }
+            Op::LoadEnv(idx) => {
+                let value = match &self.env {
+                    Value::Array(array) => array.borrow().get(idx as usize).cloned(),
+                    _ => None,
+                };
+                match value {
+                    Some(value) => self.stack().push(value),
+                    None => {
+                        return Err(Value::String(
+                            Ref("LoadEnv: Reading outside env".to_owned()),
+                        ))
+                    }
+                }
+            }
+            Op::LoadThis => {
+                self.stack().push(self.this.clone());
+            }
+            Op::StoreThis => {
+                let value = self.stack().pop();
+                match value {
+                    Some(val) => self.this = val,
+                    _ => return Err(Value::String(Ref("StoreThis: Stack empty".to_owned()))),
+                }
+            }
+            Op::StoreEnv(idx) => {
+                let idx = idx as usize;
+                let value = self.stack().pop();
+                match value {
+                    Some(value) => match &self.env {
+                        Value::Array(array) if idx < array.borrow().len() => {
+                            array.borrow_mut()[idx] = value;
+                        }
+                        _ => {
+                            return Err(Value::String(Ref(
+                                "StoreEnv: Writing outside env".to_owned()
+                            )))
+                        }
+                    },
+                    _ => return Err(Value::String(Ref("StoreEnv: Stack empty".to_owned()))),
+                }
+            }
+            Op::StoreGlobal(idx) => {
+                let value = self.stack().pop();
+                match value {
+                    Some(value) => match m.borrow_mut().globals.get_mut(idx as usize) {
+                        Some(global) => *global = value,
+                        None => {
+                            return Err(Value::String(
+                                Ref("StoreGlobal: Invalid global".to_owned()),
+                            ))
+                        }
+                    },
+                    _ => return Err(Value::String(Ref("StoreGlobal: Stack empty".to_owned()))),
+                }
+            }
+            Op::StoreLocal(idx) => {
+                let value = self.stack().pop();
+                match value {
+                    Some(value) => {
+                        self.locals.borrow_mut().insert(idx, value);
+                    }
+                    _ => return Err(Value::String(Ref("StoreLocal: Stack empty".to_owned()))),
+                }
+            }
+            Op::Nop => {}
+            Op::Pop(count) => {
+                for _ in 0..count {
+                    self.stack().pop();
+                }
+            }
+            Op::MakeEnv(count) => {
+                let function = self.stack().pop().unwrap();
+                let (func, env) = match &function {
+                    Value::Function(func) => match &func.borrow().env {
+                        Value::Array(env) => (func.clone(), env.clone()),
+                        _ => return Err(Value::String(Ref("MakeEnv: Invalid env".to_owned()))),
+                    },
+                    _ => return Err(Value::String(Ref("MakeEnv: Not a function".to_owned()))),
+                };
+                let values = (0..count)
+                    .into_iter()
+                    .map(|_| self.stack().pop().unwrap_or(Value::Null))
+                    .collect::<Vec<Value>>();
+                let mut env = env.borrow().clone();
+                env.extend(values);
+                // Every closure gets its own function value, the one in the
+                // globals is shared by all closures made from it.
+                let func = func.borrow();
+                let closure = Function {
+                    native: func.native,
+                    address: func.address,
+                    env: Value::Array(Ref(env)),
+                    module: func.module.clone(),
+                    argc: func.argc,
+                    calls: Default::default(),
+                    bound: None,
+                };
+                self.stack().push(Value::Function(Ref(closure)));
+            }
+
+            Op::Load => {
+                let object = self.stack().pop().unwrap();
+                let key = self.stack().pop().unwrap();
+                if let Some(value) = meta::get(&object, &key) {
+                    let value = value?;
+                    self.stack().push(value);
+                    return Ok(true);
+                }
+                match object {
+                    Value::Array(array) => match key {
+                        Value::Int(x) => self.stack().push(
+                            array
+                                .borrow()
+                                .get(x as usize)
+                                .cloned()
+                                .unwrap_or(Value::Null),
+                        ),
+                        Value::Float(x) => self.stack().push(
+                            array
+                                .borrow()
+                                .get(x as usize)
+                                .cloned()
+                                .unwrap_or(Value::Null),
+                        ),
+                        _ => self.stack().push(Value::Null),
+                    },
+                    Value::Object(object) => {
+                        let value = object.borrow().get(key.clone());
+                        let value = match value {
+                            Some(value) => value,
+                            None => meta::missing_field(&object, &key)?,
+                        };
+                        self.stack().push(value);
+                    }
+                    _ => self.stack().push(Value::Null),
+                }
+            }
+            Op::Store => {
+                let object = self.stack().pop().unwrap();
+                let key = self.stack().pop().unwrap();
+                let value = self.stack().pop().unwrap();
+                if let Some(result) = meta::set(&object, &key, &value) {
+                    result?;
+                    return Ok(true);
+                }
+                match object {
+                    Value::Array(array) => match key {
+                        Value::Int(x) => {
+                            if x as usize >= array.borrow().len() {
+                                return Err(Value::String(Ref(
+                                    "Array index out of bounds".to_owned()
+                                )));
+                            }
+                            array.borrow_mut()[x as usize] = value;
+                        }
+                        Value::Float(x) => {
+                            if x as usize >= array.borrow().len() {
+                                return Err(Value::String(Ref(
+                                    "Array index out of bounds".to_owned()
+                                )));
+                            }
+                            array.borrow_mut()[x as usize] = value;
+                        }
+                        _ => (),
+                    },
+                    Value::Object(object) => match meta::set_field(&object, &key, &value) {
+                        Some(result) => result?,
+                        None => object.borrow_mut().set(key, value),
+                    },
+                    _ => return Err(Value::String(Ref("Invalid store operation".to_string()))),
+                }
+            }
+            Op::MakeArray(count) => {
+                let values = (0..count)
+                    .into_iter()
+                    .map(|_| self.stack().pop().unwrap())
+                    .collect::<Vec<Value>>();
+
+                self.stack().push(Value::Array(Ref(values)));
+            }
+            Op::Add
+            | Op::Sub
+            | Op::Mul
+            | Op::Div
+            | Op::Mod
+            | Op::Shl
+            | Op::Shr
+            | Op::UShr
+            | Op::And
+            | Op::Or
+            | Op::Xor => {
+                let lhs = self.stack().pop().unwrap();
+                let rhs = self.stack().pop().unwrap();
+                let value = match meta::binary(op, &lhs, &rhs) {
+                    Some(value) => value?,
+                    None => numeric::binary(op, lhs, rhs)?,
+                };
+                self.stack().push(value);
+            }
+
+            Op::Gt | Op::Gte | Op::Lt | Op::Lte => {
+                let lhs = self.stack().pop().unwrap();
+                let rhs = self.stack().pop().unwrap();
+                if let Some(result) = meta::compare(op, &lhs, &rhs) {
+                    let result = result?;
+                    self.stack().push(Value::Bool(result));
+                    return Ok(true);
+                }
+                let result = match numeric::order(op, &lhs, &rhs)? {
+                    Some(ordering) => match op {
+                        Op::Gt => ordering == Ordering::Greater,
+                        Op::Gte => ordering != Ordering::Less,
+                        Op::Lt => ordering == Ordering::Less,
+                        _ => ordering != Ordering::Greater,
+                    },
+                    None => false,
+                };
+                self.stack().push(Value::Bool(result));
+            }
+            Op::Eq | Op::Neq => {
+                let lhs = self.stack().pop().unwrap();
+                let rhs = self.stack().pop().unwrap();
+                let equal = match meta::equal(&lhs, &rhs) {
+                    Some(equal) => equal?,
+                    None => lhs.equals(&rhs),
+                };
+                self.stack().push(Value::Bool(equal == (*op == Op::Eq)));
+            }
+            Op::Same => {
+                let lhs = self.stack().pop().unwrap();
+                let rhs = self.stack().pop().unwrap();
+                self.stack().push(Value::Bool(lhs.same(&rhs)));
+            }
+            Op::NotSame => {
+                let lhs = self.stack().pop().unwrap();
+                let rhs = self.stack().pop().unwrap();
+                self.stack().push(Value::Bool(!lhs.same(&rhs)));
+            }
+            Op::IsNull => {
+                let val = self.stack().pop().unwrap();
+                self.stack().push(Value::Bool(val.tag() == ValTag::Null));
+            }
+            Op::IsNotNull => {
+                let val = self.stack().pop().unwrap();
+                self.stack().push(Value::Bool(val.tag() != ValTag::Null));
+            }
+            Op::Not => {
+                let val = self.stack().pop().unwrap();
+                self.stack().push(Value::Bool(!val.to_bool()));
+            }
+            Op::Neg => {
+                let value = self.stack().pop().unwrap();
+                let value = match meta::neg(&value) {
+                    Some(value) => value?,
+                    None => numeric::neg(value)?,
+                };
+                self.stack().push(value);
+            }
+            Op::BitNot => {
+                let value = self.stack().pop().unwrap();
+                let value = numeric::bit_not(value)?;
+                self.stack().push(value);
+            }
+            Op::Dup => {
+                let value = self.stack().last().cloned().unwrap_or(Value::Null);
+                self.stack().push(value);
+            }
+            Op::New => {
+                let proto = self.stack().pop().unwrap();
+                let proto = match proto {
+                    Value::Null => None,
+                    Value::Object(obj) => Some(obj),
+                    _ => {
+                        return Err(Value::String(Ref(
+                            "Object or null expected as prototype".to_owned()
+                        )))
+                    }
+                };
+                let object = Object {
+                    prototype: proto,
+                    table: hashlink::LinkedHashMap::new(),
+                };
+                self.stack().push(Value::Object(Ref(object)));
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Run `m` from `self.pc` and report an uncaught exception before exiting
+    /// the process.
+    pub fn interp(&mut self, m: Ref<Module>) -> Value {
+        match self.run(m) {
+            Ok(value) => value,
+            Err(e) => {
+                match self.error_info.take() {
+                    Some((line, file)) => eprintln!("Error in {}:{}: {}", file, line, e),
+                    None => eprintln!("Error: {}", e),
+                }
+                std::process::exit(1);
+            }
+        }
+    }
+
+    /// Run `m` from `self.pc` until the frame marked by `save_state_exit`
+    /// returns. An exception that isn't caught by a handler installed during
+    /// this run unwinds the call stack and operand stack to where they were on
+    /// entry and is returned as `Err`, its location is kept in `error_info`.
+    pub fn run(&mut self, mut m: Ref<Module>) -> Result<Value, Value> {
+        use opcode::Op;
+        let frames = self.info_stack.len();
+        let depth = self.stack().len();
         macro_rules! throw {
             ($val: expr) => {
                 catch!(Err($val));
@@ -98,31 +472,53 @@ impl Vm {
                 match $e {
                     Ok(val) => val,
                     Err(e) => {
-                        if self.exception_stack.is_empty() {
-                            let info = m.borrow().trace_info.get(&(self.pc as u32)).cloned();
-                            if let Some((line, file)) = info {
-                                eprintln!("Error in {}:{}: {}", file, line, e);
-                            } else {
-                                eprintln!("Error: {}", e);
-                            }
-                            std::process::exit(1);
-                        } else {
-                            if let Some((catch, Infos::Info(module, _, env, this, locals))) =
-                                self.exception_stack.pop()
-                            {
-                                self.pc = catch as _;
-                                match module {
-                                    Some(module) => m = module,
-                                    _ => (),
+                        let handler = match self.exception_stack.last() {
+                            Some(handler) if handler.frames >= frames => self.exception_stack.pop(),
+                            _ => None,
+                        };
+                        match handler {
+                            Some(Handler {
+                                catch,
+                                state: Infos::Info(module, _, env, this, locals),
+                                frames,
+                                depth,
+                            }) => {
+                                self.pc = catch;
+                                if let Some(module) = module {
+                                    m = module;
                                 }
                                 self.env = env;
                                 self.this = this;
                                 self.locals = locals;
+                                self.info_stack.truncate(frames);
+                                self.stack().truncate(depth);
                                 self.stack().push(e);
+                                self.error_info = None;
                                 continue;
-                            } else {
-                                unreachable!()
                             }
+                            Some(_) => unreachable!(),
+                            None => {
+                                if self.error_info.is_none() {
+                                    let pc = self.pc.saturating_sub(1) as u32;
+                                    self.error_info = m.borrow().trace_info.get(&pc).cloned();
+                                }
+                                self.info_stack.truncate(frames.saturating_sub(1));
+                                self.stack().truncate(depth);
+                                return Err(e);
+                            }
+                        }
+                    }
+                }
+            };
+        }
+        macro_rules! enter_jit {
+            () => {
+                if let Some(code) = jit::lookup(&m, self.pc) {
+                    match code.run(self, self.pc) {
+                        jit::Exit::Pc(pc) => self.pc = pc,
+                        jit::Exit::Throw(pc, e) => {
+                            self.pc = pc + 1;
+                            throw!(e);
                         }
                     }
                 }
@@ -133,89 +529,16 @@ impl Vm {
             let op = m.borrow().code[self.pc].clone();
             self.pc += 1;
             match op {
-                Op::LoadBuiltin(name) => {
-                    if name == "exports" {
-                        self.stack().push(m.borrow().exports.clone());
-                        continue;
-                    }
-                    use crate::builtins::get_builtin;
-                    let value = get_builtin(&name);
-                    if let Some(value) = value {
-                        self.stack().push(value);
-                    } else {
-                        throw!(Value::String(Ref(format!("Builtin '{}' not found", name))));
-                    }
-                }
-                Op::LoadNull => self.stack().push(Value::Null),
-                Op::LoadInt(x) => self.stack().push(Value::Int(x)),
-                Op::LoadTrue => self.stack().push(Value::Bool(true)),
-                Op::LoadFalse => self.stack().push(Value::Bool(false)),
-                Op::LoadGlobal(idx) => {
-                    let idx = idx as usize;
-                    self.stack()
-                        .push(m.borrow().globals.get(idx).cloned().unwrap_or(Value::Null));
-                }
-                Op::LoadLocal(idx) => {
-                    self.stack().push(
-                        self.locals
-                            .borrow()
-                            .get(&idx)
-                            .cloned()
-                            .unwrap_or(Value::Null),
-                    );
-                }
-                Op::LoadEnv(idx) => {
-                    let idx = idx as usize;
-                    match &self.env {
-                        Value::Array(array) => {
-                            if idx >= array.borrow().len() {
-                                panic!("JZVM RUNTIME ERROR: Reading outside env");
-                            }
-                            self.stack().push(array.borrow()[idx].clone());
-                        }
-                        _ => unreachable!(),
-                    }
-                }
-                Op::LoadThis => {
-                    self.stack().push(self.this.clone());
-                }
-                Op::StoreThis => {
-                    let value = self.stack().pop();
-                    match value {
-                        Some(val) => self.this = val,
-                        _ => throw!(Value::String(Ref("StoreThis: Stack empty".to_owned()))),
-                    }
-                }
-                Op::StoreEnv(idx) => {
-                    let idx = idx as usize;
-                    let value = self.stack().pop();
-                    match value {
-                        Some(value) => match &self.env {
-                            Value::Array(array) => {
-                                array.borrow_mut()[idx] = value;
-                            }
-                            _ => unreachable!(),
-                        },
-                        _ => throw!(Value::String(Ref("StoreEnv: Stack empty".to_owned()))),
-                    }
-                }
-                Op::StoreLocal(idx) => {
-                    let value = self.stack().pop();
-                    match value {
-                        Some(value) => {
-                            self.locals.borrow_mut().insert(idx, value);
-                        }
-                        _ => throw!(Value::String(Ref("StoreLocal: Stack empty".to_owned()))),
-                    }
-                }
                 Op::Ret => {
                     let value = self.stack().pop().unwrap_or(Value::Null);
                     let exit = self.pop_state(Some(&mut m));
+                    self.drop_handlers();
                     if exit {
-                        return value;
+                        return Ok(value);
                     } else {
                         self.stack().push(value);
                     }
+                    enter_jit!();
                 }
                 Op::CatchPush(addr) => {
                     let info = Infos::Info(
@@ -225,7 +548,16 @@ impl Vm {
                         self.this.clone(),
                         self.locals.clone(),
                     );
-                    self.exception_stack.push((addr as usize, info));
+                    let handler = Handler {
+                        catch: addr as usize,
+                        state: info,
+                        frames: self.info_stack.len(),
+                        depth: self.stack().len(),
+                    };
+                    self.exception_stack.push(handler);
+                }
+                Op::CatchPop => {
+                    self.exception_stack.pop();
                 }
                 Op::Throw => {
                     let value = self.stack().pop().unwrap();
@@ -237,52 +569,46 @@ impl Vm {
                         .into_iter()
                         .map(|_| self.stack().pop().unwrap_or(Value::Null))
                         .collect::<Vec<Value>>();
+                    let (function, this, args) = unbind(function, None, args);
                     match function {
                         Value::Function(function) => {
                             let function = function.borrow();
-                            if function.argc != -1 {
-                                if args.len() < function.argc as usize
-                                    || args.len() > function.argc as usize
-                                {
-                                    throw!(Value::String(Ref(format!(
-                                        "Expected {} arguments,found {}",
-                                        function.argc,
-                                        args.len()
-                                    ))));
-                                }
-                            }
+                            catch!(function.check_args(args.len()));
                             if !function.native {
+                                let calls = function.calls.get() + 1;
+                                function.calls.set(calls);
+                                if jit::should_compile(calls) {
+                                    jit::compile(
+                                        function.module.as_ref().unwrap(),
+                                        function.address,
+                                    );
+                                }
                                 if let Op::TailCall(_) = op {
                                     self.pop_state(Some(&mut m));
                                 }
                                 self.save_state(Some(m.clone()));
                                 self.env = function.env.clone();
-                                self.locals = Ref(HashMap::new());
+                                self.locals = function.locals(args);
                                 m = function.module.as_ref().unwrap().clone();
-                                let mut locals = self.locals.borrow_mut();
-
-                                for (i, arg) in args.iter().enumerate() {
-                                    locals.insert(i as u16, arg.clone());
-                                }
-                                self.this = Value::Null;
+                                self.this = this.unwrap_or(Value::Null);
                                 self.pc = function.address;
                             } else {
-                                let fun: fn(&[Value]) -> Result<Value, Value> =
-                                    unsafe { std::mem::transmute(function.address) };
-
-                                let result = catch!(fun(&args));
+                                let result = catch!(call_native(&function, this, args));
                                 self.stack().push(result);
-                                /*match fun(&args) {
-                                    Ok(val) => self.stack().push(val),
-                                    Err(e) => throw!(Err(e)),
-                                }*/
                             }
                         }
-                        _ => throw!(Value::String(Ref(format!(
-                            "Call at {:x}: Function expected",
-                            self.pc - 1
-                        )))),
+                        function => match meta::call(&function, &args) {
+                            Some(result) => {
+                                let result = catch!(result);
+                                self.stack().push(result);
+                            }
+                            None => throw!(Value::String(Ref(format!(
+                                "Call at {:x}: Function expected",
+                                self.pc - 1
+                            )))),
+                        },
                     }
+                    enter_jit!();
                 }
                 Op::ObjCall(argc) => {
                     let function = self.stack().pop().unwrap();
@@ -295,417 +621,44 @@ impl Vm {
                     for _ in 0..argc {
                         args.push(self.stack().pop().unwrap_or(Value::Null));
                     }
+                    let (function, this, args) = unbind(function, Some(this), args);
 
                     match function {
                         Value::Function(function) => {
                             let function = function.borrow();
-                            self.save_state(Some(m.clone()));
-                            self.env = function.env.clone();
-                            if function.argc != -1 {
-                                if args.len() < function.argc as usize
-                                    || args.len() > function.argc as usize
-                                {
-                                    throw!(Value::String(Ref(format!(
-                                        "Expected {} arguments,found {}",
-                                        function.argc,
-                                        args.len()
-                                    ))));
-                                }
-                            }
+                            catch!(function.check_args(args.len()));
                             if !function.native {
-                                self.locals = Ref(HashMap::new());
+                                let calls = function.calls.get() + 1;
+                                function.calls.set(calls);
                                 if let Some(module) = &function.module {
-                                    m = module.clone();
+                                    if jit::should_compile(calls) {
+                                        jit::compile(module, function.address);
+                                    }
                                 }
-                                let mut locals = self.locals.borrow_mut();
-                                for (i, arg) in args.iter().enumerate() {
-                                    locals.insert(i as u16, arg.clone());
+                                self.save_state(Some(m.clone()));
+                                self.env = function.env.clone();
+                                self.locals = function.locals(args);
+                                if let Some(module) = &function.module {
+                                    m = module.clone();
                                 }
-                                self.this = this;
+                                self.this = this.unwrap_or(Value::Null);
                                 self.pc = function.address;
                             } else {
-                                let fun: fn(&[Value]) -> Result<Value, Value> =
-                                    unsafe { std::mem::transmute(function.address) };
-                                let mut new_args = vec![this];
-                                for i in args.iter() {
-                                    new_args.push(i.clone());
-                                }
-                                let result = catch!(fun(&new_args));
+                                let result = catch!(call_native(&function, this, args));
                                 self.stack().push(result);
-                                /*match fun(&args) {
-                                    Ok(val) => self.stack().push(val),
-                                    Err(e) => throw!(Err(e)),
-                                }*/
-                            }
-                        }
-                        _ => throw!(Value::String(Ref("ObjCall: Function expected".to_owned()))),
-                    }
-                }
-                Op::Nop => {}
-                Op::MakeEnv(count) => {
-                    let function = self.stack().pop().unwrap();
-                    assert_eq!(function.tag(), ValTag::Func);
-                    let values = (0..count)
-                        .into_iter()
-                        .map(|_| self.stack().pop().unwrap_or(Value::Null))
-                        .collect::<Vec<Value>>();
-                    match &function {
-                        Value::Function(func) => match &func.borrow().env {
-                            Value::Array(array) => array.borrow_mut().extend(values),
-                            _ => unreachable!(),
-                        },
-                        _ => unreachable!(),
-                    }
-                    self.stack().push(function);
-                }
-
-                Op::Load => {
-                    let object = self.stack().pop().unwrap();
-                    let key = self.stack().pop().unwrap();
-                    match object {
-                        Value::Array(array) => match key {
-                            Value::Int(x) => self.stack().push(
-                                array
-                                    .borrow()
-                                    .get(x as usize)
-                                    .cloned()
-                                    .unwrap_or(Value::Null),
-                            ),
-                            Value::Float(x) => self.stack().push(
-                                array
-                                    .borrow()
-                                    .get(x as usize)
-                                    .cloned()
-                                    .unwrap_or(Value::Null),
-                            ),
-                            _ => self.stack().push(Value::Null),
-                        },
-                        Value::Object(object) => {
-                            self.stack()
-                                .push(object.borrow().get(key).unwrap_or(Value::Null));
-                        }
-                        _ => self.stack().push(Value::Null),
-                    }
-                }
-                Op::Store => {
-                    let object = self.stack().pop().unwrap();
-                    let key = self.stack().pop().unwrap();
-                    let value = self.stack().pop().unwrap();
-                    match object {
-                        Value::Array(array) => match key {
-                            Value::Int(x) => {
-                                if x as usize >= array.borrow().len() {
-                                    throw!(Value::String(Ref(
-                                        "Array index out of bounds".to_owned()
-                                    )));
-                                }
-                                array.borrow_mut()[x as usize] = value;
-                            }
-                            Value::Float(x) => {
-                                if x as usize >= array.borrow().len() {
-                                    throw!(Value::String(Ref(
-                                        "Array index out of bounds".to_owned()
-                                    )));
-                                }
-                                array.borrow_mut()[x as usize] = value;
                             }
-                            _ => (),
-                        },
-                        Value::Object(object) => {
-                            object.borrow_mut().set(key, value);
                         }
-                        _ => throw!(Value::String(Ref("Invalid store operation".to_string()))),
-                    }
-                }
-                Op::MakeArray(count) => {
-                    let values = (0..count)
-                        .into_iter()
-                        .map(|_| self.stack().pop().unwrap())
-                        .collect::<Vec<Value>>();
-
-                    self.stack().push(Value::Array(Ref(values)));
-                }
-                Op::Add => {
-                    let lhs = self.stack().pop().unwrap();
-                    let rhs = self.stack().pop().unwrap();
-                    match lhs {
-                        Value::String(x) => {
-                            self.stack()
-                                .push(Value::String(Ref(format!("{}{}", *x.borrow(), rhs))))
-                        }
-                        Value::Int(x) => match rhs {
-                            Value::Int(y) => self.stack().push(Value::Int(x + y)),
-                            Value::Float(y) => self.stack().push(Value::Float(x as f64 + y)),
-                            _ => self.stack().push(Value::Null),
-                        },
-                        Value::Char(x) => match rhs {
-                            Value::Char(y) => self.stack().push(Value::Char(unsafe {
-                                std::char::from_u32_unchecked(x as u32 + y as u32)
-                            })),
-                            Value::Int(y) => self.stack().push(Value::Char(unsafe {
-                                std::char::from_u32_unchecked(x as u32 + y as u32)
-                            })),
-                            _ => self.stack().push(Value::Null),
-                        },
-                        Value::Float(x) => match rhs {
-                            Value::Int(y) => self.stack().push(Value::Float(x + y as f64)),
-                            Value::Float(y) => self.stack().push(Value::Float(x + y as f64)),
-                            _ => self.stack().push(Value::Null),
-                        },
-                        _ => self.stack().push(Value::Null),
-                    }
-                }
-                Op::Sub => {
-                    let lhs = self.stack().pop().unwrap();
-                    let rhs = self.stack().pop().unwrap();
-                    match lhs {
-                        Value::Int(x) => match rhs {
-                            Value::Int(y) => self.stack().push(Value::Int(x - y)),
-                            Value::Float(y) => self.stack().push(Value::Float(x as f64 - y)),
-                            _ => self.stack().push(Value::Null),
-                        },
-                        Value::Char(x) => match rhs {
-                            Value::Char(y) => self.stack().push(Value::Char(unsafe {
-                                std::char::from_u32_unchecked(x as u32 - y as u32)
-                            })),
-                            Value::Int(y) => self.stack().push(Value::Char(unsafe {
-                                std::char::from_u32_unchecked(x as u32 - y as u32)
-                            })),
-                            _ => self.stack().push(Value::Null),
-                        },
-                        Value::Float(x) => match rhs {
-                            Value::Int(y) => self.stack().push(Value::Float(x - y as f64)),
-                            Value::Float(y) => self.stack().push(Value::Float(x - y as f64)),
-                            _ => self.stack().push(Value::Null),
-                        },
-                        _ => self.stack().push(Value::Null),
-                    }
-                }
-                Op::Div => {
-                    let lhs = self.stack().pop().unwrap();
-                    let rhs = self.stack().pop().unwrap();
-                    match lhs {
-                        Value::Int(x) => match rhs {
-                            Value::Int(y) => self.stack().push(Value::Int(x / y)),
-                            Value::Float(y) => self.stack().push(Value::Float(x as f64 / y)),
-                            _ => self.stack().push(Value::Null),
-                        },
-                        Value::Float(x) => match rhs {
-                            Value::Int(y) => self.stack().push(Value::Float(x / y as f64)),
-                            Value::Float(y) => self.stack().push(Value::Float(x / y as f64)),
-                            _ => self.stack().push(Value::Null),
-                        },
-                        _ => self.stack().push(Value::Null),
-                    }
-                }
-                Op::Mul => {
-                    let lhs = self.stack().pop().unwrap();
-                    let rhs = self.stack().pop().unwrap();
-                    match lhs {
-                        Value::Int(x) => match rhs {
-                            Value::Int(y) => self.stack().push(Value::Int(x * y)),
-                            Value::Float(y) => self.stack().push(Value::Float(x as f64 * y)),
-                            _ => self.stack().push(Value::Null),
-                        },
-                        Value::Float(x) => match rhs {
-                            Value::Int(y) => self.stack().push(Value::Float(x * y as f64)),
-                            Value::Float(y) => self.stack().push(Value::Float(x * y as f64)),
-                            _ => self.stack().push(Value::Null),
-                        },
-                        _ => self.stack().push(Value::Null),
-                    }
-                }
-                Op::Mod => {
-                    let lhs = self.stack().pop().unwrap();
-                    let rhs = self.stack().pop().unwrap();
-                    match lhs {
-                        Value::Int(x) => match rhs {
-                            Value::Int(y) => self.stack().push(Value::Int(x % y)),
-                            Value::Float(y) => self.stack().push(Value::Float(x as f64 % y)),
-                            _ => self.stack().push(Value::Null),
-                        },
-                        Value::Float(x) => match rhs {
-                            Value::Int(y) => self.stack().push(Value::Float(x % y as f64)),
-                            Value::Float(y) => self.stack().push(Value::Float(x % y as f64)),
-                            _ => self.stack().push(Value::Null),
-                        },
-                        _ => self.stack().push(Value::Null),
-                    }
-                }
-                Op::Shr => {
-                    let lhs = self.stack().pop().unwrap();
-                    let rhs = self.stack().pop().unwrap();
-                    match (lhs, rhs) {
-                        (Value::Int(x), Value::Int(y)) => self.stack().push(Value::Int(x >> y)),
-                        _ => self.stack().push(Value::Null),
-                    }
-                }
-                Op::Shl => {
-                    let lhs = self.stack().pop().unwrap();
-                    let rhs = self.stack().pop().unwrap();
-                    match (lhs, rhs) {
-                        (Value::Int(x), Value::Int(y)) => self.stack().push(Value::Int(x << y)),
-                        (Value::Array(array), any_value) => {
-                            self.stack().push(any_value.clone());
-                            array.borrow_mut().push(any_value);
-                        }
-                        _ => self.stack().push(Value::Null),
-                    }
-                }
-
-                Op::Gt => {
-                    let lhs = self.stack().pop().unwrap();
-                    let rhs = self.stack().pop().unwrap();
-                    match lhs {
-                        Value::Char(x) => match rhs {
-                            Value::Char(y) => self.stack().push(Value::Bool(x > y)),
-                            Value::Int(y) => self.stack().push(Value::Bool(x as u32 > y as u32)),
-                            _ => self.stack().push(Value::Null),
-                        },
-                        Value::String(x) => match rhs {
-                            Value::String(y) => self
-                                .stack()
-                                .push(Value::Bool(x.borrow().len() > y.borrow().len())),
-                            _ => self.stack().push(Value::Bool(false)),
-                        },
-                        Value::Int(x) => match rhs {
-                            Value::Int(y) => self.stack().push(Value::Bool(x > y)),
-                            Value::Float(y) => self.stack().push(Value::Bool((x as f64) > y)),
-                            _ => self.stack().push(Value::Bool(false)),
-                        },
-                        Value::Float(x) => match rhs {
-                            Value::Int(y) => self.stack().push(Value::Bool(x > y as f64)),
-                            Value::Float(y) => self.stack().push(Value::Bool(x > y as f64)),
-                            _ => self.stack().push(Value::Bool(false)),
-                        },
-                        Value::Array(x) => match rhs {
-                            Value::Array(y) => self
-                                .stack()
-                                .push(Value::Bool(x.borrow().len() > y.borrow().len())),
-                            _ => self.stack().push(Value::Bool(false)),
-                        },
-                        _ => self.stack().push(Value::Bool(false)),
-                    }
-                }
-                Op::Gte => {
-                    let lhs = self.stack().pop().unwrap();
-                    let rhs = self.stack().pop().unwrap();
-                    match lhs {
-                        Value::Char(x) => match rhs {
-                            Value::Char(y) => self.stack().push(Value::Bool(x >= y)),
-                            Value::Int(y) => self.stack().push(Value::Bool(x as u32 >= y as u32)),
-                            _ => self.stack().push(Value::Null),
-                        },
-                        Value::String(x) => match rhs {
-                            Value::String(y) => {
-                                self.stack().push(Value::Bool(*x.borrow() >= *y.borrow()))
+                        function => match meta::call(&function, &args) {
+                            Some(result) => {
+                                let result = catch!(result);
+                                self.stack().push(result);
                             }
-                            _ => self.stack().push(Value::Bool(false)),
-                        },
-                        Value::Int(x) => match rhs {
-                            Value::Int(y) => self.stack().push(Value::Bool(x >= y)),
-                            Value::Float(y) => self.stack().push(Value::Bool((x as f64) >= y)),
-                            _ => self.stack().push(Value::Bool(false)),
-                        },
-                        Value::Float(x) => match rhs {
-                            Value::Int(y) => self.stack().push(Value::Bool(x >= y as f64)),
-                            Value::Float(y) => self.stack().push(Value::Bool(x >= y as f64)),
-                            _ => self.stack().push(Value::Bool(false)),
-                        },
-                        Value::Array(x) => match rhs {
-                            Value::Array(y) => self.stack().push(Value::Bool(
-                                (x.borrow().len() > y.borrow().len()) || *x.borrow() == *y.borrow(),
-                            )),
-                            _ => self.stack().push(Value::Bool(false)),
-                        },
-                        _ => self.stack().push(Value::Bool(false)),
-                    }
-                }
-                Op::Lte => {
-                    let lhs = self.stack().pop().unwrap();
-                    let rhs = self.stack().pop().unwrap();
-                    match lhs {
-                        Value::String(x) => match rhs {
-                            Value::String(y) => {
-                                self.stack().push(Value::Bool(*x.borrow() >= *y.borrow()))
+                            None => {
+                                throw!(Value::String(Ref("ObjCall: Function expected".to_owned())))
                             }
-                            _ => self.stack().push(Value::Bool(false)),
-                        },
-                        Value::Char(x) => match rhs {
-                            Value::Char(y) => self.stack().push(Value::Bool(x <= y)),
-                            Value::Int(y) => self.stack().push(Value::Bool((x as u32) <= y as u32)),
-                            _ => self.stack().push(Value::Null),
-                        },
-                        Value::Int(x) => match rhs {
-                            Value::Int(y) => self.stack().push(Value::Bool(x >= y)),
-                            Value::Float(y) => self.stack().push(Value::Bool((x as f64) >= y)),
-                            _ => self.stack().push(Value::Bool(false)),
-                        },
-                        Value::Float(x) => match rhs {
-                            Value::Int(y) => self.stack().push(Value::Bool(x >= y as f64)),
-                            Value::Float(y) => self.stack().push(Value::Bool(x >= y as f64)),
-                            _ => self.stack().push(Value::Bool(false)),
-                        },
-                        Value::Array(x) => match rhs {
-                            Value::Array(y) => self.stack().push(Value::Bool(
-                                (x.borrow().len() < y.borrow().len()) || *x.borrow() == *y.borrow(),
-                            )),
-                            _ => self.stack().push(Value::Bool(false)),
-                        },
-                        _ => self.stack().push(Value::Bool(false)),
-                    }
-                }
-                Op::Lt => {
-                    let lhs = self.stack().pop().unwrap();
-                    let rhs = self.stack().pop().unwrap();
-                    match lhs {
-                        Value::String(x) => match rhs {
-                            Value::String(y) => self
-                                .stack()
-                                .push(Value::Bool(x.borrow().len() < y.borrow().len())),
-                            _ => self.stack().push(Value::Bool(false)),
-                        },
-                        Value::Char(x) => match rhs {
-                            Value::Char(y) => self.stack().push(Value::Bool(x < y)),
-                            Value::Int(y) => self.stack().push(Value::Bool((x as u32) < y as u32)),
-                            _ => self.stack().push(Value::Null),
-                        },
-                        Value::Int(x) => match rhs {
-                            Value::Int(y) => self.stack().push(Value::Bool(x < y)),
-                            Value::Float(y) => self.stack().push(Value::Bool((x as f64) < y)),
-                            _ => self.stack().push(Value::Bool(false)),
-                        },
-                        Value::Float(x) => match rhs {
-                            Value::Int(y) => self.stack().push(Value::Bool(x < y as f64)),
-                            Value::Float(y) => self.stack().push(Value::Bool(x < y as f64)),
-                            _ => self.stack().push(Value::Bool(false)),
                         },
-                        Value::Array(x) => match rhs {
-                            Value::Array(y) => self
-                                .stack()
-                                .push(Value::Bool(x.borrow().len() < y.borrow().len())),
-                            _ => self.stack().push(Value::Bool(false)),
-                        },
-                        _ => self.stack().push(Value::Bool(false)),
                     }
-                }
-                Op::Eq => {
-                    let lhs = self.stack().pop().unwrap();
-                    let rhs = self.stack().pop().unwrap();
-                    self.stack().push(Value::Bool(lhs == rhs));
-                }
-                Op::Neq => {
-                    let lhs = self.stack().pop().unwrap();
-                    let rhs = self.stack().pop().unwrap();
-                    self.stack().push(Value::Bool(lhs != rhs));
-                }
-                Op::IsNull => {
-                    let val = self.stack().pop().unwrap();
-                    self.stack().push(Value::Bool(val.tag() == ValTag::Null));
-                }
-                Op::IsNotNull => {
-                    let val = self.stack().pop().unwrap();
-                    self.stack().push(Value::Bool(val.tag() != ValTag::Null));
+                    enter_jit!();
                 }
                 Op::Jump(to) => {
                     self.pc = to as _;
@@ -722,107 +675,88 @@ impl Vm {
                         self.pc = to as _;
                     }
                 }
-                Op::Not => {
-                    let val = self.stack().pop().unwrap();
-                    match val {
-                        Value::Int(x) => self.stack().push(Value::Int(!x)),
-                        _ => self.stack().push(Value::Bool(!val.to_bool())),
-                    }
-                }
-                Op::Neg => {
-                    let val = self.stack().pop().unwrap();
-                    match val {
-                        Value::Int(x) => self.stack().push(Value::Int(-x)),
-                        Value::Float(x) => self.stack().push(Value::Float(-x)),
-                        _ => self.stack().push(Value::Null),
-                    }
-                }
-                Op::And => {
-                    let (x, y) = (self.stack().pop().unwrap(), self.stack().pop().unwrap());
-                    match (x.clone(), y.clone()) {
-                        (Value::Int(x), Value::Int(y)) => self.stack().push(Value::Int(x & y)),
-                        (Value::Bool(x), Value::Bool(y)) => self.stack().push(Value::Bool(x & y)),
-                        _ => self.stack().push(Value::Bool(x.to_bool() & y.to_bool())),
-                    }
-                }
-                Op::Or => {
-                    let (x, y) = (self.stack().pop().unwrap(), self.stack().pop().unwrap());
-                    match (x.clone(), y.clone()) {
-                        (Value::Int(x), Value::Int(y)) => self.stack().push(Value::Int(x | y)),
-                        (Value::Bool(x), Value::Bool(y)) => self.stack().push(Value::Bool(x | y)),
-                        _ => self.stack().push(Value::Bool(x.to_bool() & y.to_bool())),
-                    }
-                }
-                Op::Xor => {
-                    let (x, y) = (self.stack().pop().unwrap(), self.stack().pop().unwrap());
-                    match (x.clone(), y.clone()) {
-                        (Value::Int(x), Value::Int(y)) => self.stack().push(Value::Int(x ^ y)),
-                        _ => self.stack().push(Value::Null),
+                Op::Last => break 'inner,
+                op => {
+                    if !catch!(self.step(&m, &op)) {
+                        throw!(Value::String(Ref(format!("Unsupported opcode {:?}", op))))
                     }
                 }
-                Op::New => {
-                    let proto = self.stack().pop().unwrap();
-                    let proto = match proto {
-                        Value::Null => None,
-                        Value::Object(obj) => Some(obj),
-                        _ => throw!(Value::String(Ref(
-                            "Object or null expected as prototype".to_owned()
-                        ))),
-                    };
-                    let object = Object {
-                        prototype: proto,
-                        table: hashlink::LinkedHashMap::new(),
-                    };
-                    self.stack().push(Value::Object(Ref(object)));
-                }
-                Op::Last => break 'inner,
-                _ => unimplemented!(),
             }
         }
-        self.stack().pop().unwrap_or(Value::Null)
+        Ok(self.stack().pop().unwrap_or(Value::Null))
     }
 }
 
 pub fn val_callex(f: Value, this: Value, args: &[Value]) -> Result<Value, Value> {
     let mut vm = get_vm!();
+    let (f, this, args) = unbind(f, Some(this), args.to_vec());
     match f {
         Value::Function(f) => {
             let function = f.borrow();
             if function.native {
-                let fun: fn(&[Value]) -> Result<Value, Value> =
-                    unsafe { std::mem::transmute(function.address) };
-                let mut new_args = vec![this];
-                for i in args.iter() {
-                    new_args.push(i.clone());
-                }
-
-                return fun(&new_args);
+                return call_native(&function, this, args);
             } else {
+                function.check_args(args.len())?;
                 vm.save_state_exit();
                 let env = vm.env.clone();
                 let locals = vm.locals.clone();
                 let pc = vm.pc.clone();
                 let this_ = vm.this.clone();
                 vm.pc = function.address;
-                vm.this = this;
+                vm.this = this.unwrap_or(Value::Null);
                 vm.env = function.env.clone();
-                vm.locals = Ref(HashMap::new());
-                if args.len() > function.argc as usize {
-                    return Err(Value::String(Ref("Too many arguments".to_owned())));
-                } else if args.len() < function.argc as usize {
-                    return Err(Value::String(Ref("Unexpected arguments count".to_owned())));
-                }
-                for (i, arg) in args.iter().enumerate() {
-                    vm.locals.borrow_mut().insert(i as u16, arg.clone());
-                }
-                let value = vm.interp(function.module.as_ref().unwrap().clone());
+                vm.locals = function.locals(args);
+                let result = vm.run(function.module.as_ref().unwrap().clone());
                 vm.env = env;
                 vm.locals = locals;
                 vm.pc = pc;
                 vm.this = this_;
-                return Ok(value);
+                return result;
             }
         }
-        _ => return Err(Value::String(Ref("Function expected".to_owned()))),
+        f => match meta::call(&f, &args) {
+            Some(result) => result,
+            None => Err(Value::String(Ref("Function expected".to_owned()))),
+        },
+    }
+}
+
+/// Function, `this` and arguments a call runs with. `this` is `None` for
+/// plain calls, whose native callee doesn't get it as first argument. A
+/// bound function supplies its own `this`, calling it as `f(args)` when that
+/// is null, and leading arguments.
+fn unbind(
+    function: Value,
+    this: Option<Value>,
+    args: Vec<Value>,
+) -> (Value, Option<Value>, Vec<Value>) {
+    let bound = match &function {
+        Value::Function(f) => f.borrow().bound.clone(),
+        _ => None,
+    };
+    match bound {
+        Some(bound) => {
+            let this = match &bound.this {
+                Value::Null => None,
+                this => Some(this.clone()),
+            };
+            let args = bound.args.iter().cloned().chain(args).collect();
+            (function, this, args)
+        }
+        None => (function, this, args),
+    }
+}
+
+/// Call a native function, passing `this` first when there is one.
+fn call_native(function: &Function, this: Option<Value>, args: Vec<Value>) -> Result<Value, Value> {
+    let fun: fn(&[Value]) -> Result<Value, Value> =
+        unsafe { std::mem::transmute(function.address) };
+    match this {
+        Some(this) => {
+            let mut new_args = vec![this];
+            new_args.extend(args);
+            fun(&new_args)
+        }
+        None => fun(&args),
     }
 }
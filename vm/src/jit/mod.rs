@@ -1 +1,409 @@
+//! Baseline JIT compiler.
+//!
+//! Hot script functions are translated to machine code. Integer and boolean
+//! constants, integer locals, integer arithmetic, comparisons, `Dup`, `Pop`
+//! and jumps inside the function run natively, other opcodes that only work
+//! with the stack, locals and globals call back into `Vm::step`. Calls,
+//! returns, exceptions and any other opcode exit back to `Vm::interp`, which
+//! executes the opcode and re-enters compiled code on the next call or return.
+//! So does a local that turns out not to hold an integer or arithmetic that
+//! overflows into a big integer.
+//!
+//! Compiled code is cached per module and only holds a weak reference to it,
+//! the code of a module is dropped once the module is.
+//!
+//! The JIT is configured with the `JAZZLIGHT_JIT` environment variable: `off`
+//! disables it, `force` compiles every function on its first call and `on`
+//! (the default) compiles functions that were called `JAZZLIGHT_JIT_THRESHOLD`
+//! times.
 
+use crate::interp::Vm;
+use crate::opcode::Op;
+use crate::value::Value;
+use crate::*;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+mod x64;
+
+pub const DEFAULT_THRESHOLD: usize = 1000;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum JitMode {
+    /// Never compile, always interpret.
+    Off,
+    /// Compile functions once they become hot.
+    On,
+    /// Compile every function on its first call.
+    Force,
+}
+
+impl JitMode {
+    pub fn parse(s: &str) -> Option<JitMode> {
+        match s {
+            "off" | "0" | "false" => Some(JitMode::Off),
+            "on" | "1" | "true" => Some(JitMode::On),
+            "force" => Some(JitMode::Force),
+            _ => None,
+        }
+    }
+}
+
+fn mode_from_env() -> JitMode {
+    std::env::var("JAZZLIGHT_JIT")
+        .ok()
+        .and_then(|s| JitMode::parse(&s))
+        .unwrap_or(JitMode::On)
+}
+
+fn threshold_from_env() -> usize {
+    std::env::var("JAZZLIGHT_JIT_THRESHOLD")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(DEFAULT_THRESHOLD)
+}
+
+/// Result of running compiled code.
+pub enum Exit {
+    /// Compiled code stopped before the opcode at this address, the interpreter
+    /// should continue from there.
+    Pc(usize),
+    /// Opcode at this address raised an exception.
+    Throw(usize, Value),
+}
+
+/// Machine code for one function together with everything it references at runtime.
+pub struct Compiled {
+    module: Weak<RefCell<Module>>,
+    /// Copy of the module code, generated code holds pointers into it.
+    #[allow(dead_code)]
+    ops: Box<[Op]>,
+    /// Native offset of every compiled bytecode address.
+    entries: HashMap<usize, usize>,
+    #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+    code: x64::Code,
+}
+
+impl Compiled {
+    /// Run compiled code starting at bytecode address `pc`.
+    pub fn run(&self, vm: &mut Vm, pc: usize) -> Exit {
+        self.run_native(vm, pc)
+    }
+
+    #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+    fn run_native(&self, vm: &mut Vm, pc: usize) -> Exit {
+        let offset = self.entries[&pc];
+        let result = unsafe { self.code.call(vm, self, offset) };
+        let pc = (result & 0xffff_ffff) as usize;
+        if result & EXIT_THROW != 0 {
+            let e = PENDING
+                .with(|p| p.borrow_mut().take())
+                .unwrap_or(Value::Null);
+            Exit::Throw(pc, e)
+        } else {
+            Exit::Pc(pc)
+        }
+    }
+
+    #[cfg(not(all(target_arch = "x86_64", target_os = "linux")))]
+    fn run_native(&self, _: &mut Vm, pc: usize) -> Exit {
+        Exit::Pc(pc)
+    }
+}
+
+/// Set in the value returned by compiled code when an opcode raised an exception.
+pub const EXIT_THROW: u64 = 1 << 32;
+
+/// Compiled code of one module.
+struct ModuleCode {
+    /// Keeps the allocation of the module, so no other module can get its
+    /// address while this entry exists.
+    module: Weak<RefCell<Module>>,
+    /// Compiled code by bytecode address.
+    entries: HashMap<usize, Rc<Compiled>>,
+    /// Functions that were compiled or rejected already.
+    seen: HashSet<usize>,
+}
+
+#[derive(Default)]
+struct Cache {
+    /// Compiled code by module address.
+    modules: HashMap<usize, ModuleCode>,
+}
+
+thread_local! {
+    static MODE: Cell<JitMode> = Cell::new(mode_from_env());
+    static THRESHOLD: Cell<usize> = Cell::new(threshold_from_env());
+    static CACHE: RefCell<Cache> = RefCell::new(Cache::default());
+    static PENDING: RefCell<Option<Value>> = RefCell::new(None);
+}
+
+pub fn mode() -> JitMode {
+    MODE.with(|m| m.get())
+}
+
+pub fn set_mode(mode: JitMode) {
+    MODE.with(|m| m.set(mode));
+}
+
+pub fn set_threshold(calls: usize) {
+    THRESHOLD.with(|t| t.set(calls));
+}
+
+/// Whether a function that was just called for the `calls`th time should be compiled.
+pub fn should_compile(calls: usize) -> bool {
+    match mode() {
+        JitMode::Off => false,
+        JitMode::Force => calls == 1,
+        JitMode::On => calls == THRESHOLD.with(|t| t.get()),
+    }
+}
+
+fn module_key(m: &Ref<Module>) -> usize {
+    Rc::as_ptr(m) as *const u8 as usize
+}
+
+/// Find compiled code containing bytecode address `pc` of module `m`.
+pub fn lookup(m: &Ref<Module>, pc: usize) -> Option<Rc<Compiled>> {
+    if mode() == JitMode::Off {
+        return None;
+    }
+    CACHE.with(|c| {
+        c.borrow()
+            .modules
+            .get(&module_key(m))?
+            .entries
+            .get(&pc)
+            .cloned()
+    })
+}
+
+/// Compile function starting at `address`. Does nothing if it was compiled
+/// before or the JIT is not supported on this platform.
+pub fn compile(m: &Ref<Module>, address: usize) {
+    let key = module_key(m);
+    let first = CACHE.with(|c| {
+        let mut c = c.borrow_mut();
+        if !c.modules.contains_key(&key) {
+            // A new module, drop the code of modules that are gone.
+            c.modules.retain(|_, code| code.module.strong_count() > 0);
+        }
+        c.modules
+            .entry(key)
+            .or_insert_with(|| ModuleCode {
+                module: Rc::downgrade(m),
+                entries: HashMap::new(),
+                seen: HashSet::new(),
+            })
+            .seen
+            .insert(address)
+    });
+    if !first {
+        return;
+    }
+    if let Some(compiled) = compile_function(m, address) {
+        let compiled = Rc::new(compiled);
+        CACHE.with(|c| {
+            let mut c = c.borrow_mut();
+            let code = c.modules.get_mut(&key).unwrap();
+            for pc in compiled.entries.keys() {
+                code.entries.entry(*pc).or_insert_with(|| compiled.clone());
+            }
+        });
+    }
+}
+
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+fn compile_function(m: &Ref<Module>, address: usize) -> Option<Compiled> {
+    let ops = m.borrow().code.clone().into_boxed_slice();
+    let reachable = reachable(&ops, address);
+    let (code, entries) = x64::translate(&ops, &reachable)?;
+    Some(Compiled {
+        module: Rc::downgrade(m),
+        ops,
+        entries,
+        code,
+    })
+}
+
+#[cfg(not(all(target_arch = "x86_64", target_os = "linux")))]
+fn compile_function(_: &Ref<Module>, _: usize) -> Option<Compiled> {
+    None
+}
+
+/// Collect bytecode addresses reachable from `start` without leaving the function.
+pub fn reachable(code: &[Op], start: usize) -> Vec<bool> {
+    let mut seen = vec![false; code.len()];
+    let mut worklist = vec![start];
+    while let Some(pc) = worklist.pop() {
+        if pc >= code.len() || seen[pc] {
+            continue;
+        }
+        seen[pc] = true;
+        match &code[pc] {
+            Op::Jump(to) => worklist.push(*to as usize),
+            Op::JumpIf(to) | Op::JumpIfNot(to) | Op::CatchPush(to) => {
+                worklist.push(*to as usize);
+                worklist.push(pc + 1);
+            }
+            Op::Ret | Op::Throw | Op::Last => (),
+            _ => worklist.push(pc + 1),
+        }
+    }
+    seen
+}
+
+/// Whether `op` is executed by `Vm::step`, anything else exits to the interpreter.
+pub fn is_step_op(op: &Op) -> bool {
+    match op {
+        Op::Ret
+        | Op::CatchPush(_)
+        | Op::CatchPop
+        | Op::Throw
+        | Op::Call(_)
+        | Op::TailCall(_)
+        | Op::ObjCall(_)
+        | Op::Jump(_)
+        | Op::JumpIf(_)
+        | Op::JumpIfNot(_)
+        | Op::Hash
+        | Op::Last => false,
+        _ => true,
+    }
+}
+
+/// Called from compiled code to execute `op`. Returns 1 if it raised an exception.
+extern "C" fn jit_step(vm: *mut Vm, compiled: *const Compiled, op: *const Op) -> u32 {
+    let (vm, compiled, op) = unsafe { (&mut *vm, &*compiled, &*op) };
+    let module = compiled
+        .module
+        .upgrade()
+        .expect("module of running code was dropped");
+    match vm.step(&module, op) {
+        Ok(_) => 0,
+        Err(e) => {
+            PENDING.with(|p| *p.borrow_mut() = Some(e));
+            1
+        }
+    }
+}
+
+/// Called from compiled code to pop a jump condition.
+extern "C" fn jit_truthy(vm: *mut Vm) -> u32 {
+    let vm = unsafe { &mut *vm };
+    let value = vm.stack().pop().unwrap().to_bool();
+    value as u32
+}
+
+/// Called from compiled code to push an integer from a native slot.
+extern "C" fn jit_push_int(vm: *mut Vm, x: i64) {
+    let vm = unsafe { &mut *vm };
+    vm.stack().push(Value::Int(x));
+}
+
+/// Called from compiled code to push a boolean from a native slot.
+extern "C" fn jit_push_bool(vm: *mut Vm, x: u64) {
+    let vm = unsafe { &mut *vm };
+    vm.stack().push(Value::Bool(x != 0));
+}
+
+/// Called from compiled code to read local `idx` into a native slot. Returns 0
+/// if it doesn't hold an integer.
+extern "C" fn jit_load_int(vm: *mut Vm, idx: u64, out: *mut i64) -> u32 {
+    let vm = unsafe { &mut *vm };
+    match vm.locals.borrow().get(&(idx as u16)) {
+        Some(Value::Int(x)) => {
+            unsafe { *out = *x };
+            1
+        }
+        _ => 0,
+    }
+}
+
+/// Called from compiled code to store an integer from a native slot in local `idx`.
+extern "C" fn jit_store_int(vm: *mut Vm, idx: u64, x: i64) {
+    let vm = unsafe { &mut *vm };
+    vm.locals.borrow_mut().insert(idx as u16, Value::Int(x));
+}
+
+/// Called from compiled code to store a boolean from a native slot in local `idx`.
+extern "C" fn jit_store_bool(vm: *mut Vm, idx: u64, x: u64) {
+    let vm = unsafe { &mut *vm };
+    vm.locals
+        .borrow_mut()
+        .insert(idx as u16, Value::Bool(x != 0));
+}
+
+#[cfg(all(test, target_arch = "x86_64", target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    fn module(code: Vec<Op>) -> Ref<Module> {
+        Ref(Module {
+            exports: Value::Null,
+            code,
+            globals: vec![],
+            trace_info: HashMap::new(),
+        })
+    }
+
+    /// Compile `code` and run it, returns where it stopped and the stack.
+    fn run(code: Vec<Op>) -> (usize, Vec<String>) {
+        set_mode(JitMode::On);
+        let m = module(code);
+        compile(&m, 0);
+        let mut vm = Vm::new();
+        match lookup(&m, 0).unwrap().run(&mut vm, 0) {
+            Exit::Pc(pc) => (pc, vm.stack().iter().map(|v| v.to_string()).collect()),
+            Exit::Throw(..) => panic!("unexpected exception"),
+        }
+    }
+
+    #[test]
+    fn native_slots_reach_the_vm_stack() {
+        let (pc, stack) = run(vec![
+            Op::LoadInt(40),
+            Op::LoadInt(2),
+            Op::Add,
+            Op::StoreLocal(0),
+            Op::LoadLocal(0),
+            Op::Dup,
+            Op::LoadInt(42),
+            Op::Eq,
+            Op::Ret,
+        ]);
+        assert_eq!(pc, 8);
+        assert_eq!(stack, ["42", "true"]);
+
+        // Overflow leaves the addition to the interpreter.
+        let (pc, stack) = run(vec![
+            Op::LoadInt(1),
+            Op::LoadInt(i64::MAX),
+            Op::Add,
+            Op::Ret,
+        ]);
+        assert_eq!(pc, 2);
+        assert_eq!(stack, ["1", "9223372036854775807"]);
+
+        // So does a local that doesn't hold an integer.
+        let (pc, stack) = run(vec![Op::LoadInt(1), Op::LoadLocal(3), Op::Add, Op::Ret]);
+        assert_eq!(pc, 1);
+        assert_eq!(stack, ["1"]);
+    }
+
+    #[test]
+    fn compiled_code_does_not_keep_modules_alive() {
+        set_mode(JitMode::On);
+        let m = module(vec![Op::LoadInt(1), Op::Ret]);
+        compile(&m, 0);
+        let weak = Rc::downgrade(&m);
+        drop(m);
+        assert!(weak.upgrade().is_none());
+
+        let m = module(vec![Op::LoadInt(2), Op::Ret]);
+        compile(&m, 0);
+        assert!(lookup(&m, 0).is_some());
+        assert_eq!(CACHE.with(|c| c.borrow().modules.len()), 1);
+    }
+}
@@ -0,0 +1,530 @@
+//! x86_64 System V backend.
+//!
+//! Register usage in generated code: `rbx` holds the `Vm`, `r12` the
+//! `Compiled` unit, `r13` is only pushed to keep the stack 16 byte aligned.
+//!
+//! Integers and booleans pushed by `LoadInt`, `LoadTrue`, `LoadFalse` and
+//! `LoadLocal` of an integer local live in native slots in the frame of the
+//! generated code, at `[rsp + 8 * slot]`, while the opcodes translated here
+//! work on them. The slots are pushed to the `Vm` stack in order before any
+//! other opcode, before jump targets and whenever compiled code is left, so
+//! the interpreter always sees the same stack it would have built itself.
+//! When a local doesn't hold an integer or arithmetic overflows, the slots
+//! are pushed the same way and the interpreter executes the opcode instead.
+
+use super::{
+    is_step_op, jit_load_int, jit_push_bool, jit_push_int, jit_step, jit_store_bool, jit_store_int,
+    jit_truthy, Compiled, EXIT_THROW,
+};
+use crate::interp::Vm;
+use crate::opcode::Op;
+use std::collections::HashMap;
+
+const RAX: u8 = 0;
+const RDX: u8 = 2;
+const RSI: u8 = 6;
+
+/// Number of native slots.
+const SLOTS: usize = 16;
+
+/// Size of the native slots in the frame, a multiple of 16 to keep the stack aligned.
+const FRAME: usize = 8 * SLOTS;
+
+pub struct Assembler {
+    pub code: Vec<u8>,
+}
+
+impl Assembler {
+    pub fn new() -> Self {
+        Self { code: vec![] }
+    }
+
+    pub fn pos(&self) -> usize {
+        self.code.len()
+    }
+
+    fn emit(&mut self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+
+    fn emit_u64(&mut self, x: u64) {
+        self.code.extend_from_slice(&x.to_le_bytes());
+    }
+
+    fn emit_u32(&mut self, x: u32) {
+        self.code.extend_from_slice(&x.to_le_bytes());
+    }
+
+    /// `push rbx; push r12; push r13; sub rsp, FRAME; mov rbx, rdi; mov r12, rsi; jmp rdx`
+    pub fn prologue(&mut self) {
+        self.emit(&[0x53, 0x41, 0x54, 0x41, 0x55]);
+        self.emit(&[0x48, 0x81, 0xec]);
+        self.emit_u32(FRAME as u32);
+        self.emit(&[0x48, 0x89, 0xfb, 0x49, 0x89, 0xf4]);
+        self.emit(&[0xff, 0xe2]);
+    }
+
+    /// `add rsp, FRAME; pop r13; pop r12; pop rbx; ret`
+    pub fn epilogue(&mut self) {
+        self.emit(&[0x48, 0x81, 0xc4]);
+        self.emit_u32(FRAME as u32);
+        self.emit(&[0x41, 0x5d, 0x41, 0x5c, 0x5b, 0xc3]);
+    }
+
+    /// Emit `opcode` with register `reg` and memory operand `[rsp + 8 * slot]`.
+    fn slot_op(&mut self, opcode: &[u8], reg: u8, slot: usize) {
+        self.emit(opcode);
+        self.emit(&[0x84 | reg << 3, 0x24]);
+        self.emit_u32(8 * slot as u32);
+    }
+
+    /// `mov rax, [rsp + 8 * slot]`
+    pub fn load(&mut self, slot: usize) {
+        self.slot_op(&[0x48, 0x8b], RAX, slot);
+    }
+
+    /// `mov [rsp + 8 * slot], rax`
+    pub fn store(&mut self, slot: usize) {
+        self.slot_op(&[0x48, 0x89], RAX, slot);
+    }
+
+    /// `mov rsi, [rsp + 8 * slot]`
+    pub fn load_rsi(&mut self, slot: usize) {
+        self.slot_op(&[0x48, 0x8b], RSI, slot);
+    }
+
+    /// `lea rdx, [rsp + 8 * slot]`
+    pub fn lea_rdx(&mut self, slot: usize) {
+        self.slot_op(&[0x48, 0x8d], RDX, slot);
+    }
+
+    /// `add`, `sub`, `imul`, `and`, `or`, `xor` or `cmp` of `rax` and
+    /// `[rsp + 8 * slot]`.
+    pub fn arith(&mut self, op: &Op, slot: usize) {
+        let opcode: &[u8] = match op {
+            Op::Add => &[0x48, 0x03],
+            Op::Sub => &[0x48, 0x2b],
+            Op::Mul => &[0x48, 0x0f, 0xaf],
+            Op::And => &[0x48, 0x23],
+            Op::Or => &[0x48, 0x0b],
+            Op::Xor => &[0x48, 0x33],
+            _ => &[0x48, 0x3b],
+        };
+        self.slot_op(opcode, RAX, slot);
+    }
+
+    /// `setcc al; movzx eax, al` for the condition of a comparison opcode.
+    pub fn set(&mut self, op: &Op) {
+        let cc = match op {
+            Op::Eq => 0x4,
+            Op::Neq => 0x5,
+            Op::Lt => 0xc,
+            Op::Gte => 0xd,
+            Op::Lte => 0xe,
+            _ => 0xf,
+        };
+        self.emit(&[0x0f, 0x90 | cc, 0xc0, 0x0f, 0xb6, 0xc0]);
+    }
+
+    pub fn not_rax(&mut self) {
+        self.emit(&[0x48, 0xf7, 0xd0]);
+    }
+
+    pub fn neg_rax(&mut self) {
+        self.emit(&[0x48, 0xf7, 0xd8]);
+    }
+
+    /// `xor rax, 1`
+    pub fn flip_rax(&mut self) {
+        self.emit(&[0x48, 0x83, 0xf0, 0x01]);
+    }
+
+    pub fn test_rax(&mut self) {
+        self.emit(&[0x48, 0x85, 0xc0]);
+    }
+
+    pub fn mov_rsi(&mut self, imm: u64) {
+        self.emit(&[0x48, 0xbe]);
+        self.emit_u64(imm);
+    }
+
+    /// `mov rdi, rbx`
+    pub fn load_vm(&mut self) {
+        self.emit(&[0x48, 0x89, 0xdf]);
+    }
+
+    pub fn mov_rax(&mut self, imm: u64) {
+        self.emit(&[0x48, 0xb8]);
+        self.emit_u64(imm);
+    }
+
+    pub fn mov_rdx(&mut self, imm: u64) {
+        self.emit(&[0x48, 0xba]);
+        self.emit_u64(imm);
+    }
+
+    /// `mov rdi, rbx; mov rsi, r12`
+    pub fn load_context(&mut self) {
+        self.emit(&[0x48, 0x89, 0xdf, 0x4c, 0x89, 0xe6]);
+    }
+
+    pub fn call_rax(&mut self) {
+        self.emit(&[0xff, 0xd0]);
+    }
+
+    pub fn test_eax(&mut self) {
+        self.emit(&[0x85, 0xc0]);
+    }
+
+    /// Emit `jmp rel32` and return position of the displacement.
+    pub fn jmp(&mut self) -> usize {
+        self.emit(&[0xe9, 0, 0, 0, 0]);
+        self.pos() - 4
+    }
+
+    /// Emit `jz rel32` and return position of the displacement.
+    pub fn jz(&mut self) -> usize {
+        self.emit(&[0x0f, 0x84, 0, 0, 0, 0]);
+        self.pos() - 4
+    }
+
+    /// Emit `jnz rel32` and return position of the displacement.
+    pub fn jnz(&mut self) -> usize {
+        self.emit(&[0x0f, 0x85, 0, 0, 0, 0]);
+        self.pos() - 4
+    }
+
+    /// Emit `jo rel32` and return position of the displacement.
+    pub fn jo(&mut self) -> usize {
+        self.emit(&[0x0f, 0x80, 0, 0, 0, 0]);
+        self.pos() - 4
+    }
+
+    pub fn patch(&mut self, at: usize, target: usize) {
+        let rel = target as i64 - (at as i64 + 4);
+        self.code[at..at + 4].copy_from_slice(&(rel as i32).to_le_bytes());
+    }
+}
+
+/// Executable memory holding generated code.
+pub struct Code {
+    ptr: *mut u8,
+    len: usize,
+}
+
+impl Code {
+    pub fn new(bytes: &[u8]) -> Option<Code> {
+        unsafe {
+            let len = bytes.len();
+            let ptr = libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+            if ptr == libc::MAP_FAILED {
+                return None;
+            }
+            std::ptr::copy_nonoverlapping(bytes.as_ptr(), ptr as *mut u8, len);
+            if libc::mprotect(ptr, len, libc::PROT_READ | libc::PROT_EXEC) != 0 {
+                libc::munmap(ptr, len);
+                return None;
+            }
+            Some(Code {
+                ptr: ptr as *mut u8,
+                len,
+            })
+        }
+    }
+
+    /// Enter generated code at `offset`.
+    pub unsafe fn call(&self, vm: &mut Vm, compiled: &Compiled, offset: usize) -> u64 {
+        let entry: extern "C" fn(*mut Vm, *const Compiled, *const u8) -> u64 =
+            std::mem::transmute(self.ptr);
+        entry(vm, compiled, self.ptr.add(offset))
+    }
+}
+
+impl Drop for Code {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr as *mut _, self.len);
+        }
+    }
+}
+
+/// Type of the value in a native slot.
+#[derive(Copy, Clone, PartialEq)]
+enum Slot {
+    Int,
+    Bool,
+}
+
+/// Guard that failed or arithmetic that overflowed: `slots` are pushed to the
+/// `Vm` stack and the interpreter continues with the opcode at `pc`.
+struct SideExit {
+    at: usize,
+    slots: Vec<Slot>,
+    pc: usize,
+}
+
+/// Whether the top `n` of `slots` all hold values of type `ty`.
+fn top_is(slots: &[Slot], n: usize, ty: Slot) -> bool {
+    slots.len() >= n && slots[slots.len() - n..].iter().all(|t| *t == ty)
+}
+
+struct Translator {
+    asm: Assembler,
+    /// Native slots in use at the current position.
+    slots: Vec<Slot>,
+    side_exits: Vec<SideExit>,
+    /// Jumps to the epilogue.
+    returns: Vec<usize>,
+}
+
+impl Translator {
+    /// Push `slots` to the `Vm` stack.
+    fn push_slots(asm: &mut Assembler, slots: &[Slot]) {
+        for (slot, ty) in slots.iter().enumerate() {
+            asm.load_vm();
+            asm.load_rsi(slot);
+            match ty {
+                Slot::Int => asm.mov_rax(jit_push_int as *const () as u64),
+                Slot::Bool => asm.mov_rax(jit_push_bool as *const () as u64),
+            }
+            asm.call_rax();
+        }
+    }
+
+    fn flush(&mut self) {
+        Self::push_slots(&mut self.asm, &self.slots);
+        self.slots.clear();
+    }
+
+    /// Leave compiled code, the interpreter continues at `pc`.
+    fn exit(&mut self, pc: usize) {
+        self.flush();
+        self.asm.mov_rax(pc as u64);
+        self.returns.push(self.asm.jmp());
+    }
+
+    fn side_exit(&mut self, at: usize, pc: usize) {
+        self.side_exits.push(SideExit {
+            at,
+            slots: self.slots.clone(),
+            pc,
+        });
+    }
+
+    /// Translate `op` to code working on the native slots. Returns false if it
+    /// has to go through `jit_step` instead.
+    fn native(&mut self, pc: usize, op: &Op) -> bool {
+        let depth = self.slots.len();
+        let asm = &mut self.asm;
+        match op {
+            Op::LoadInt(_) | Op::LoadTrue | Op::LoadFalse if depth < SLOTS => {
+                let (x, ty) = match op {
+                    Op::LoadInt(x) => (*x as u64, Slot::Int),
+                    Op::LoadTrue => (1, Slot::Bool),
+                    _ => (0, Slot::Bool),
+                };
+                asm.mov_rax(x);
+                asm.store(depth);
+                self.slots.push(ty);
+            }
+            Op::LoadLocal(idx) if depth < SLOTS => {
+                asm.load_vm();
+                asm.mov_rsi(*idx as u64);
+                asm.lea_rdx(depth);
+                asm.mov_rax(jit_load_int as *const () as u64);
+                asm.call_rax();
+                asm.test_eax();
+                let at = asm.jz();
+                self.side_exit(at, pc);
+                self.slots.push(Slot::Int);
+            }
+            Op::StoreLocal(idx) if depth > 0 => {
+                let ty = self.slots.pop().unwrap();
+                asm.load_vm();
+                asm.mov_rsi(*idx as u64);
+                asm.slot_op(&[0x48, 0x8b], RDX, depth - 1);
+                match ty {
+                    Slot::Int => asm.mov_rax(jit_store_int as *const () as u64),
+                    Slot::Bool => asm.mov_rax(jit_store_bool as *const () as u64),
+                }
+                asm.call_rax();
+            }
+            Op::Dup if depth > 0 && depth < SLOTS => {
+                asm.load(depth - 1);
+                asm.store(depth);
+                self.slots.push(self.slots[depth - 1]);
+            }
+            Op::Pop(count) if *count as usize <= depth => {
+                self.slots.truncate(depth - *count as usize);
+            }
+            Op::Add | Op::Sub | Op::Mul | Op::And | Op::Or | Op::Xor
+                if top_is(&self.slots, 2, Slot::Int) =>
+            {
+                // The left operand is on top.
+                asm.load(depth - 1);
+                asm.arith(op, depth - 2);
+                if let Op::Add | Op::Sub | Op::Mul = op {
+                    let at = asm.jo();
+                    self.side_exit(at, pc);
+                }
+                self.asm.store(depth - 2);
+                self.slots.pop();
+            }
+            Op::Lt | Op::Lte | Op::Gt | Op::Gte | Op::Eq | Op::Neq
+                if top_is(&self.slots, 2, Slot::Int)
+                    || (*op == Op::Eq || *op == Op::Neq) && top_is(&self.slots, 2, Slot::Bool) =>
+            {
+                asm.load(depth - 1);
+                asm.arith(op, depth - 2);
+                asm.set(op);
+                asm.store(depth - 2);
+                self.slots.pop();
+                self.slots[depth - 2] = Slot::Bool;
+            }
+            Op::Not if top_is(&self.slots, 1, Slot::Bool) => {
+                asm.load(depth - 1);
+                asm.flip_rax();
+                asm.store(depth - 1);
+            }
+            Op::BitNot if top_is(&self.slots, 1, Slot::Int) => {
+                asm.load(depth - 1);
+                asm.not_rax();
+                asm.store(depth - 1);
+            }
+            Op::Neg if top_is(&self.slots, 1, Slot::Int) => {
+                asm.load(depth - 1);
+                asm.neg_rax();
+                let at = asm.jo();
+                self.side_exit(at, pc);
+                self.asm.store(depth - 1);
+            }
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// Translate every reachable opcode of `ops` to machine code. Returns the code
+/// and native offset of each bytecode address compiled code can be entered at.
+pub fn translate(ops: &[Op], reachable: &[bool]) -> Option<(Code, HashMap<usize, usize>)> {
+    let mut targets = vec![false; ops.len()];
+    for (pc, op) in ops.iter().enumerate() {
+        match op {
+            Op::Jump(to) | Op::JumpIf(to) | Op::JumpIfNot(to) | Op::CatchPush(to)
+                if reachable[pc] && (*to as usize) < ops.len() =>
+            {
+                targets[*to as usize] = true
+            }
+            _ => (),
+        }
+    }
+
+    let mut t = Translator {
+        asm: Assembler::new(),
+        slots: vec![],
+        side_exits: vec![],
+        returns: vec![],
+    };
+    let mut labels = HashMap::new();
+    let mut jumps = vec![];
+    let mut exits = vec![];
+    t.asm.prologue();
+
+    for (pc, op) in ops.iter().enumerate() {
+        if !reachable[pc] {
+            continue;
+        }
+        if targets[pc] {
+            t.flush();
+        }
+        if t.slots.is_empty() {
+            labels.insert(pc, t.asm.pos());
+        }
+        match op {
+            Op::Jump(to) => {
+                t.flush();
+                jumps.push((t.asm.jmp(), *to as usize));
+            }
+            Op::JumpIf(to) | Op::JumpIfNot(to) => {
+                if top_is(&t.slots, 1, Slot::Bool) {
+                    t.slots.pop();
+                    let slot = t.slots.len();
+                    t.flush();
+                    t.asm.load(slot);
+                    t.asm.test_rax();
+                } else {
+                    t.flush();
+                    t.asm.mov_rax(jit_truthy as *const () as u64);
+                    t.asm.load_context();
+                    t.asm.call_rax();
+                    t.asm.test_eax();
+                }
+                let at = match op {
+                    Op::JumpIf(_) => t.asm.jnz(),
+                    _ => t.asm.jz(),
+                };
+                jumps.push((at, *to as usize));
+            }
+            op if t.native(pc, op) => (),
+            op if is_step_op(op) => {
+                t.flush();
+                let asm = &mut t.asm;
+                asm.mov_rax(jit_step as *const () as u64);
+                asm.load_context();
+                asm.mov_rdx(op as *const Op as u64);
+                asm.call_rax();
+                asm.test_eax();
+                let ok = asm.jz();
+                asm.mov_rax(EXIT_THROW | pc as u64);
+                t.returns.push(asm.jmp());
+                let here = asm.pos();
+                asm.patch(ok, here);
+            }
+            _ => {
+                // Let the interpreter execute this opcode.
+                t.exit(pc);
+                continue;
+            }
+        }
+        if pc + 1 >= ops.len() || !reachable[pc + 1] {
+            if let Op::Jump(_) = op {
+                continue;
+            }
+            t.exit(pc + 1);
+        }
+    }
+
+    for (at, to) in jumps {
+        match labels.get(&to) {
+            Some(target) => t.asm.patch(at, *target),
+            None => exits.push((at, to)),
+        }
+    }
+    for (at, to) in exits {
+        let here = t.asm.pos();
+        t.asm.patch(at, here);
+        t.exit(to);
+    }
+    for exit in std::mem::take(&mut t.side_exits) {
+        let here = t.asm.pos();
+        t.asm.patch(exit.at, here);
+        Translator::push_slots(&mut t.asm, &exit.slots);
+        t.asm.mov_rax(exit.pc as u64);
+        t.returns.push(t.asm.jmp());
+    }
+    let epilogue = t.asm.pos();
+    t.asm.epilogue();
+    for at in t.returns {
+        t.asm.patch(at, epilogue);
+    }
+
+    let code = Code::new(&t.asm.code)?;
+    Some((code, labels))
+}
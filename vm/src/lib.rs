@@ -6,14 +6,20 @@ extern crate mopa;
 
 #[macro_use]
 pub mod interp;
+pub mod asm;
 pub mod atomic_ref;
+pub mod bigint;
 pub mod builtins;
+pub mod format;
 pub mod gc;
 
 pub mod jit;
+pub mod meta;
+pub mod numeric;
 pub mod opcode;
 pub mod reader;
 pub mod value;
+pub mod verifier;
 pub mod writer;
 
 use mimalloc::MiMalloc;
@@ -2,13 +2,32 @@
 extern crate jazzlight;
 
 use jazzlight::interp::*;
+use jazzlight::jit;
 
 use jazzlight::reader::BytecodeReader;
 use jazzlight::value::Value;
 use std::io::Cursor;
 
 fn main() {
-    let file = std::env::args().nth(1);
+    let mut file = None;
+    let mut verify_only = false;
+    for arg in std::env::args().skip(1) {
+        if arg == "--verify" {
+            verify_only = true;
+        } else if arg == "--strict" {
+            jazzlight::meta::set_strict(true);
+        } else if arg.starts_with("--jit=") {
+            match jit::JitMode::parse(&arg["--jit=".len()..]) {
+                Some(mode) => jit::set_mode(mode),
+                None => {
+                    eprintln!("Unknown JIT mode '{}', expected off, on or force", arg);
+                    std::process::exit(1);
+                }
+            }
+        } else {
+            file = Some(arg);
+        }
+    }
     if file.is_none() {
         eprintln!("Please select JazzLight bytecode file");
         std::process::exit(1);
@@ -21,7 +40,18 @@ fn main() {
             let mut reader = BytecodeReader {
                 bytes: Cursor::new(&contents),
             };
-            let m = reader.read_module();
+            let m = match reader.read_module() {
+                Ok(m) => m,
+                Err(e) => {
+                    eprintln!("Failed to load '{}': {}", file, e);
+                    std::process::exit(1);
+                }
+            };
+            // `read_module` already ran the verifier.
+            if verify_only {
+                println!("{}: ok", file);
+                return;
+            }
             let vm = get_vm!();
             vm.save_state_exit();
             match vm.interp(m) {
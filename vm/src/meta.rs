@@ -0,0 +1,276 @@
+//! Operator overloading.
+//!
+//! When an operand of an operator is an object, the VM looks for a method
+//! named after the operator with `Object::get`, so it may be defined on the
+//! object itself or anywhere on its prototype chain. User values provide
+//! the same methods through `UserKind::method`.
+//!
+//! - `+ - * / % << >> >>> & | ^` call `__add`, `__sub`, `__mul`, `__div`,
+//!   `__mod`, `__shl`, `__shr`, `__ushr`, `__and`, `__or` and `__xor`, and
+//!   unary `-` calls `__neg`.
+//! - `==` and `!=` call `__eq`. `<` and `>` call `__lt` with the operands
+//!   swapped for `>`; `<=` and `>=` call `__le` the same way, or negate
+//!   `__lt` of the swapped operands when there is no `__le`.
+//! - `a[key]` and `a[key] = value` call `__get(key)` and `__set(key, value)`
+//!   when `key` isn't a string. String keys always name fields, so
+//!   `this.items` inside those methods is an ordinary field access.
+//! - Calling the value calls `__call(args...)`.
+//!
+//! Field access has hooks of its own, used for string keys that no object on
+//! the prototype chain stores:
+//!
+//! - A getter stored under `"get name"` on the chain makes `o.name` call it,
+//!   a setter under `"set name"` makes `o.name = value` call it with the
+//!   value. Assigning a field that has only a getter throws a `FieldError`.
+//! - Otherwise reading the field calls `__missing(name)` if the chain has it.
+//! - Otherwise it reads as null, or throws a `FieldError` naming the field
+//!   in strict mode. Strict mode is off unless enabled with `set_strict`,
+//!   the VM's `--strict` flag or the `JAZZLIGHT_STRICT` environment variable.
+//!
+//! Operator methods are looked up on the left operand first, then on the
+//! right one, and receive both operands as arguments in source order, so
+//! `2 * v` and `v * 2` can both be handled by `v`'s `__mul`. `this` is the
+//! operand the method was found on. Comparison results are converted with
+//! `to_bool`. `===` and `!==` are never overloaded.
+
+use crate::interp::val_callex;
+use crate::opcode::Op;
+use crate::value::{Object, Value};
+use crate::Ref;
+use std::cell::Cell;
+
+pub const GET: &str = "__get";
+pub const SET: &str = "__set";
+pub const CALL: &str = "__call";
+pub const MISSING: &str = "__missing";
+
+thread_local! {
+    static STRICT: Cell<bool> = Cell::new(
+        std::env::var("JAZZLIGHT_STRICT").map_or(false, |s| s != "0" && s != "false")
+    );
+}
+
+/// Whether reading an absent field throws instead of giving null.
+pub fn strict() -> bool {
+    STRICT.with(|strict| strict.get())
+}
+
+pub fn set_strict(strict: bool) {
+    STRICT.with(|s| s.set(strict));
+}
+
+fn field_error(message: String) -> Value {
+    Value::String(Ref(format!("FieldError: {}", message)))
+}
+
+/// Method `name` of an object or user value.
+pub fn method(value: &Value, name: &str) -> Option<Value> {
+    match value {
+        Value::Object(object) => match object.borrow().get(Value::String(Ref(name.to_owned()))) {
+            Some(method @ Value::Function(_)) => Some(method),
+            _ => None,
+        },
+        Value::User(user) => user.borrow().method(name),
+        _ => None,
+    }
+}
+
+fn binary_method(op: &Op) -> Option<&'static str> {
+    Some(match op {
+        Op::Add => "__add",
+        Op::Sub => "__sub",
+        Op::Mul => "__mul",
+        Op::Div => "__div",
+        Op::Mod => "__mod",
+        Op::Shl => "__shl",
+        Op::Shr => "__shr",
+        Op::UShr => "__ushr",
+        Op::And => "__and",
+        Op::Or => "__or",
+        Op::Xor => "__xor",
+        _ => return None,
+    })
+}
+
+/// Call method `name` of `lhs` or else `rhs` with both operands.
+fn call_binary(name: &str, lhs: &Value, rhs: &Value) -> Option<Result<Value, Value>> {
+    let (method, this) = match method(lhs, name) {
+        Some(method) => (method, lhs),
+        None => (method(rhs, name)?, rhs),
+    };
+    Some(val_callex(
+        method,
+        this.clone(),
+        &[lhs.clone(), rhs.clone()],
+    ))
+}
+
+/// Result of an arithmetic or bitwise operator when an operand overloads it.
+pub fn binary(op: &Op, lhs: &Value, rhs: &Value) -> Option<Result<Value, Value>> {
+    call_binary(binary_method(op)?, lhs, rhs)
+}
+
+pub fn neg(value: &Value) -> Option<Result<Value, Value>> {
+    let method = method(value, "__neg")?;
+    Some(val_callex(method, value.clone(), &[value.clone()]))
+}
+
+/// `lhs == rhs` when an operand defines `__eq`.
+pub fn equal(lhs: &Value, rhs: &Value) -> Option<Result<bool, Value>> {
+    Some(call_binary("__eq", lhs, rhs)?.map(|result| result.to_bool()))
+}
+
+/// Result of `<`, `<=`, `>` or `>=` when an operand defines `__lt` or `__le`.
+pub fn compare(op: &Op, lhs: &Value, rhs: &Value) -> Option<Result<bool, Value>> {
+    let result = match op {
+        Op::Lt => call_binary("__lt", lhs, rhs),
+        Op::Gt => call_binary("__lt", rhs, lhs),
+        Op::Lte => call_binary("__le", lhs, rhs).or_else(|| negate(call_binary("__lt", rhs, lhs)?)),
+        Op::Gte => call_binary("__le", rhs, lhs).or_else(|| negate(call_binary("__lt", lhs, rhs)?)),
+        _ => None,
+    };
+    Some(result?.map(|result| result.to_bool()))
+}
+
+fn negate(result: Result<Value, Value>) -> Option<Result<Value, Value>> {
+    Some(result.map(|result| Value::Bool(!result.to_bool())))
+}
+
+/// `object[key]` through `__get`, for keys that don't name a field.
+pub fn get(object: &Value, key: &Value) -> Option<Result<Value, Value>> {
+    if let Value::String(_) = key {
+        return None;
+    }
+    let method = method(object, GET)?;
+    Some(val_callex(method, object.clone(), &[key.clone()]))
+}
+
+/// `object[key] = value` through `__set`, for keys that don't name a field.
+pub fn set(object: &Value, key: &Value, value: &Value) -> Option<Result<Value, Value>> {
+    if let Value::String(_) = key {
+        return None;
+    }
+    let method = method(object, SET)?;
+    Some(val_callex(
+        method,
+        object.clone(),
+        &[key.clone(), value.clone()],
+    ))
+}
+
+/// Call a non-function value through its `__call` method.
+pub fn call(callee: &Value, args: &[Value]) -> Option<Result<Value, Value>> {
+    let method = method(callee, CALL)?;
+    Some(val_callex(method, callee.clone(), args))
+}
+
+/// Function stored under `"<prefix> <name>"` on the prototype chain.
+fn accessor(object: &Ref<Object>, prefix: &str, name: &str) -> Option<Value> {
+    let key = Value::String(Ref(format!("{} {}", prefix, name)));
+    match object.borrow().get(key) {
+        Some(function @ Value::Function(_)) => Some(function),
+        _ => None,
+    }
+}
+
+/// Getter of field `key` on the prototype chain.
+pub fn getter(object: &Ref<Object>, key: &Value) -> Option<Value> {
+    match key {
+        Value::String(name) => accessor(object, "get", &name.borrow()),
+        _ => None,
+    }
+}
+
+/// Value of field `key` of `object` when `Object::get` found nothing: the
+/// getter, `__missing`, null or a `FieldError`, see the module docs.
+pub fn missing_field(object: &Ref<Object>, key: &Value) -> Result<Value, Value> {
+    let name = match key {
+        Value::String(name) => name.borrow().clone(),
+        _ => return Ok(Value::Null),
+    };
+    let this = Value::Object(object.clone());
+    if let Some(getter) = accessor(object, "get", &name) {
+        return val_callex(getter, this, &[]);
+    }
+    if let Some(missing) = method(&this, MISSING) {
+        return val_callex(missing, this, &[key.clone()]);
+    }
+    if strict() {
+        return Err(field_error(format!("object has no field '{}'", name)));
+    }
+    Ok(Value::Null)
+}
+
+/// Assign field `key` of `object` through a setter when the object doesn't
+/// have the field itself. `None` when it should be stored as usual.
+pub fn set_field(object: &Ref<Object>, key: &Value, value: &Value) -> Option<Result<(), Value>> {
+    let name = match key {
+        Value::String(name) => name.borrow().clone(),
+        _ => return None,
+    };
+    if object.borrow().table.contains_key(key) {
+        return None;
+    }
+    let this = Value::Object(object.clone());
+    if let Some(setter) = accessor(object, "set", &name) {
+        return Some(val_callex(setter, this, &[value.clone()]).map(|_| ()));
+    }
+    if accessor(object, "get", &name).is_some() {
+        return Some(Err(field_error(format!("field '{}' is read-only", name))));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builtins::new_native_fn;
+    use crate::value::UserKind;
+    use std::fmt;
+
+    /// Native type whose `+` adds its number to the other operand.
+    #[derive(Debug)]
+    struct Counter(i64);
+
+    impl fmt::Display for Counter {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "Counter({})", self.0)
+        }
+    }
+
+    fn counter_add(args: &[Value]) -> Result<Value, Value> {
+        let this = match &args[0] {
+            Value::User(user) => user.borrow().downcast_ref::<Counter>().unwrap().0,
+            _ => unreachable!(),
+        };
+        match (&args[1], &args[2]) {
+            (Value::Int(x), _) | (_, Value::Int(x)) => Ok(Value::Int(this + x)),
+            _ => Err(Value::String(Ref("expected an int".to_owned()))),
+        }
+    }
+
+    impl UserKind for Counter {
+        fn get_kind(&self) -> &'static str {
+            "Counter"
+        }
+
+        fn method(&self, name: &str) -> Option<Value> {
+            match name {
+                "__add" => Some(new_native_fn(counter_add, 2)),
+                _ => None,
+            }
+        }
+    }
+
+    #[test]
+    fn user_values_provide_operator_methods() {
+        let counter = Value::User(Ref(Counter(40)));
+        match binary(&Op::Add, &Value::Int(2), &counter) {
+            Some(Ok(Value::Int(42))) => (),
+            _ => panic!("expected 42"),
+        }
+        assert!(binary(&Op::Add, &counter, &counter).unwrap().is_err());
+        assert!(binary(&Op::Sub, &counter, &Value::Int(1)).is_none());
+        assert!(method(&counter, CALL).is_none());
+    }
+}
@@ -0,0 +1,378 @@
+//! Semantics of the arithmetic, bitwise and comparison opcodes.
+//!
+//! - `+`, `-`, `*`, `/`, `%`, `<<` and unary `-` on integers give a big
+//!   integer when the result doesn't fit in an `i64`, and big integer results
+//!   that fit become plain integers again.
+//! - Mixing an integer with a float converts the integer, float arithmetic
+//!   follows IEEE 754 (`1.0 / 0` is `inf`).
+//! - Integer `/` and `%` by zero throw `ZeroDivisionError: division by zero`.
+//! - `x << n` is `x * 2^n` and `x >> n` is `x / 2^n` rounded down, for any
+//!   integer `x`. `>>>` is a logical shift of the 64-bit form, so it doesn't
+//!   accept big integers. Negative shift counts throw a `ValueError`, so do
+//!   left shifts by more than `bigint::MAX_SHIFT` bits.
+//! - `&`, `|`, `^` and `~` only accept integers and act on their two's
+//!   complement form, which for big integers extends the sign bit forever.
+//! - `+` with a string on the left concatenates the string form of the right
+//!   operand, `<<` with an array on the left appends to it.
+//! - A char plus or minus a char or an integer is a char, results outside the
+//!   Unicode range throw a `TypeError`.
+//!
+//! - `<`, `<=`, `>` and `>=` compare numbers by value, strings
+//!   lexicographically, chars by code point and arrays element by element,
+//!   a shorter array being smaller than one it is a prefix of. Comparisons
+//!   with NaN are false.
+//!
+//! Any other combination throws
+//! `TypeError: unsupported operand types for '<op>': <lhs> and <rhs>`.
+//! Errors are string values so scripts can catch and print them.
+
+use crate::bigint::{BigInt, MAX_SHIFT};
+use crate::opcode::Op;
+use crate::value::Value;
+use crate::Ref;
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::rc::Rc;
+
+fn error(message: String) -> Value {
+    Value::String(Ref(message))
+}
+
+fn zero_division() -> Value {
+    error("ZeroDivisionError: division by zero".to_owned())
+}
+
+fn unsupported(op: &str, lhs: &Value, rhs: &Value) -> Value {
+    error(format!(
+        "TypeError: unsupported operand types for '{}': {} and {}",
+        op,
+        lhs.type_name(),
+        rhs.type_name()
+    ))
+}
+
+fn bad_operand(op: &str, value: &Value) -> Value {
+    error(format!(
+        "TypeError: unsupported operand type for '{}': {}",
+        op,
+        value.type_name()
+    ))
+}
+
+/// Source form of a binary arithmetic or bitwise opcode.
+pub fn symbol(op: &Op) -> Option<&'static str> {
+    Some(match op {
+        Op::Add => "+",
+        Op::Sub => "-",
+        Op::Mul => "*",
+        Op::Div => "/",
+        Op::Mod => "%",
+        Op::Shl => "<<",
+        Op::Shr => ">>",
+        Op::UShr => ">>>",
+        Op::And => "&",
+        Op::Or => "|",
+        Op::Xor => "^",
+        _ => return None,
+    })
+}
+
+fn char_result(op: &str, lhs: &Value, rhs: &Value, code: Option<i64>) -> Result<Value, Value> {
+    code.filter(|code| *code >= 0 && *code <= u32::MAX as i64)
+        .and_then(|code| std::char::from_u32(code as u32))
+        .map(Value::Char)
+        .ok_or_else(|| {
+            error(format!(
+                "TypeError: result of {} {} {} is not a valid char",
+                lhs, op, rhs
+            ))
+        })
+}
+
+/// Apply the binary opcode `op` to `lhs` and `rhs`.
+pub fn binary(op: &Op, lhs: Value, rhs: Value) -> Result<Value, Value> {
+    let symbol = symbol(op).expect("not an arithmetic opcode");
+    let value = match (op, &lhs, &rhs) {
+        (Op::Add, Value::String(x), _) => Value::String(Ref(format!("{}{}", *x.borrow(), rhs))),
+        (Op::Shl, Value::Array(array), _) => {
+            array.borrow_mut().push(rhs.clone());
+            rhs
+        }
+
+        (Op::Div, Value::Int(_), Value::Int(0)) | (Op::Mod, Value::Int(_), Value::Int(0)) => {
+            return Err(zero_division())
+        }
+        (Op::Add, Value::Int(x), Value::Int(y)) => match x.checked_add(*y) {
+            Some(z) => Value::Int(z),
+            None => return big_binary(op, symbol, &lhs, &rhs),
+        },
+        (Op::Sub, Value::Int(x), Value::Int(y)) => match x.checked_sub(*y) {
+            Some(z) => Value::Int(z),
+            None => return big_binary(op, symbol, &lhs, &rhs),
+        },
+        (Op::Mul, Value::Int(x), Value::Int(y)) => match x.checked_mul(*y) {
+            Some(z) => Value::Int(z),
+            None => return big_binary(op, symbol, &lhs, &rhs),
+        },
+        (Op::Div, Value::Int(x), Value::Int(y)) => match x.checked_div(*y) {
+            Some(z) => Value::Int(z),
+            None => return big_binary(op, symbol, &lhs, &rhs),
+        },
+        (Op::Mod, Value::Int(x), Value::Int(y)) => Value::Int(x.wrapping_rem(*y)),
+        (Op::Shl, Value::Int(_), Value::Int(_))
+        | (Op::Shl, Value::Int(_), Value::BigInt(_))
+        | (Op::Shl, Value::BigInt(_), Value::Int(_))
+        | (Op::Shl, Value::BigInt(_), Value::BigInt(_))
+        | (Op::Shr, Value::Int(_), Value::Int(_))
+        | (Op::Shr, Value::Int(_), Value::BigInt(_))
+        | (Op::Shr, Value::BigInt(_), Value::Int(_))
+        | (Op::Shr, Value::BigInt(_), Value::BigInt(_))
+        | (Op::UShr, Value::Int(_), Value::Int(_))
+        | (Op::UShr, Value::Int(_), Value::BigInt(_))
+        | (Op::UShr, Value::BigInt(_), Value::Int(_))
+        | (Op::UShr, Value::BigInt(_), Value::BigInt(_)) => return shift(op, &lhs, &rhs),
+        (_, Value::BigInt(_), Value::Int(_))
+        | (_, Value::Int(_), Value::BigInt(_))
+        | (_, Value::BigInt(_), Value::BigInt(_)) => return big_binary(op, symbol, &lhs, &rhs),
+
+        (Op::Add, Value::Char(x), Value::Char(y)) => {
+            return char_result(symbol, &lhs, &rhs, (*x as i64).checked_add(*y as i64))
+        }
+        (Op::Add, Value::Char(x), Value::Int(y)) => {
+            return char_result(symbol, &lhs, &rhs, (*x as i64).checked_add(*y))
+        }
+        (Op::Sub, Value::Char(x), Value::Char(y)) => {
+            return char_result(symbol, &lhs, &rhs, (*x as i64).checked_sub(*y as i64))
+        }
+        (Op::Sub, Value::Char(x), Value::Int(y)) => {
+            return char_result(symbol, &lhs, &rhs, (*x as i64).checked_sub(*y))
+        }
+
+        (Op::And, Value::Int(x), Value::Int(y)) => Value::Int(x & y),
+        (Op::Or, Value::Int(x), Value::Int(y)) => Value::Int(x | y),
+        (Op::Xor, Value::Int(x), Value::Int(y)) => Value::Int(x ^ y),
+
+        (_, Value::Int(_), Value::Float(_))
+        | (_, Value::BigInt(_), Value::Float(_))
+        | (_, Value::Float(_), Value::Int(_))
+        | (_, Value::Float(_), Value::BigInt(_))
+        | (_, Value::Float(_), Value::Float(_)) => {
+            let x = float(&lhs);
+            let y = float(&rhs);
+            match op {
+                Op::Add => Value::Float(x + y),
+                Op::Sub => Value::Float(x - y),
+                Op::Mul => Value::Float(x * y),
+                Op::Div => Value::Float(x / y),
+                Op::Mod => Value::Float(x % y),
+                _ => return Err(unsupported(symbol, &lhs, &rhs)),
+            }
+        }
+        _ => return Err(unsupported(symbol, &lhs, &rhs)),
+    };
+    Ok(value)
+}
+
+fn float(value: &Value) -> f64 {
+    value.to_float().unwrap()
+}
+
+fn big(value: &Value) -> BigInt {
+    match value {
+        Value::Int(x) => BigInt::from_i64(*x),
+        Value::BigInt(x) => (**x).clone(),
+        _ => unreachable!(),
+    }
+}
+
+/// `op` on two integers of which at least one is big or the `i64` result
+/// overflowed.
+fn big_binary(op: &Op, symbol: &str, lhs: &Value, rhs: &Value) -> Result<Value, Value> {
+    let (x, y) = (big(lhs), big(rhs));
+    let result = match op {
+        Op::Add => x.add(&y),
+        Op::Sub => x.sub(&y),
+        Op::Mul => x.mul(&y),
+        Op::Div => x.div_rem(&y).ok_or_else(zero_division)?.0,
+        Op::Mod => x.div_rem(&y).ok_or_else(zero_division)?.1,
+        Op::And => x.bitwise(&y, |x, y| x & y),
+        Op::Or => x.bitwise(&y, |x, y| x | y),
+        Op::Xor => x.bitwise(&y, |x, y| x ^ y),
+        _ => return Err(unsupported(symbol, lhs, rhs)),
+    };
+    Ok(Value::from_bigint(result))
+}
+
+/// `<<`, `>>` or `>>>` on two integers.
+fn shift(op: &Op, lhs: &Value, rhs: &Value) -> Result<Value, Value> {
+    // `None` for counts too large for any value to be shifted left by.
+    let count = match rhs {
+        Value::Int(n) if *n >= 0 => usize::try_from(*n).ok().filter(|n| *n <= MAX_SHIFT),
+        Value::BigInt(n) if !n.negative() => None,
+        _ => return Err(error("ValueError: negative shift count".to_owned())),
+    };
+    let value = match (op, lhs) {
+        (Op::UShr, Value::Int(x)) => match count {
+            Some(n) if n < 64 => Value::Int((*x as u64 >> n) as i64),
+            _ => Value::Int(0),
+        },
+        (Op::UShr, _) => {
+            return Err(error(
+                "ValueError: '>>>' needs an integer that fits in 64 bits".to_owned(),
+            ))
+        }
+        (Op::Shr, Value::Int(x)) => Value::Int(x >> count.unwrap_or(63).min(63)),
+        (Op::Shr, _) => Value::from_bigint(big(lhs).shr(count.unwrap_or(usize::MAX))),
+        (_, Value::Int(0)) => Value::Int(0),
+        (_, Value::Int(x)) => match count.filter(|n| *n < 64) {
+            Some(n) if (x << n) >> n == *x => Value::Int(x << n),
+            _ => return shift_left_big(lhs, count),
+        },
+        _ => return shift_left_big(lhs, count),
+    };
+    Ok(value)
+}
+
+fn shift_left_big(lhs: &Value, count: Option<usize>) -> Result<Value, Value> {
+    match count {
+        Some(n) => Ok(Value::from_bigint(big(lhs).shl(n))),
+        None => Err(error("ValueError: shift count too large".to_owned())),
+    }
+}
+
+/// Unary minus, `-i64::MIN` is a big integer.
+pub fn neg(value: Value) -> Result<Value, Value> {
+    match value {
+        Value::Int(x) => Ok(match x.checked_neg() {
+            Some(x) => Value::Int(x),
+            None => Value::from_bigint(BigInt::from_i64(x).neg()),
+        }),
+        Value::BigInt(x) => Ok(Value::from_bigint(x.neg())),
+        Value::Float(x) => Ok(Value::Float(-x)),
+        _ => Err(bad_operand("-", &value)),
+    }
+}
+
+/// Bitwise complement `~`.
+pub fn bit_not(value: Value) -> Result<Value, Value> {
+    match value {
+        Value::Int(x) => Ok(Value::Int(!x)),
+        Value::BigInt(x) => Ok(Value::from_bigint(x.not())),
+        _ => Err(bad_operand("~", &value)),
+    }
+}
+
+/// Order of two numbers, `None` when either isn't a number or is NaN.
+pub fn compare(lhs: &Value, rhs: &Value) -> Option<Ordering> {
+    match (lhs, rhs) {
+        (Value::Int(x), Value::Int(y)) => Some(x.cmp(y)),
+        (Value::Float(x), Value::Float(y)) => x.partial_cmp(y),
+        (Value::Int(_), Value::Float(y)) | (Value::BigInt(_), Value::Float(y)) => {
+            compare_float(lhs, *y)
+        }
+        (Value::Float(x), Value::Int(_)) | (Value::Float(x), Value::BigInt(_)) => {
+            compare_float(rhs, *x).map(Ordering::reverse)
+        }
+        (Value::Int(_), Value::BigInt(_))
+        | (Value::BigInt(_), Value::Int(_))
+        | (Value::BigInt(_), Value::BigInt(_)) => Some(big(lhs).cmp(&big(rhs))),
+        _ => None,
+    }
+}
+
+/// Exact order of an integer and a float, which converting the integer
+/// would round.
+fn compare_float(x: &Value, y: f64) -> Option<Ordering> {
+    if y.is_nan() {
+        None
+    } else if y.is_infinite() {
+        Some(if y > 0.0 {
+            Ordering::Less
+        } else {
+            Ordering::Greater
+        })
+    } else {
+        let floor = y.floor();
+        match big(x).cmp(&BigInt::from_f64(floor)) {
+            Ordering::Equal if floor != y => Some(Ordering::Less),
+            ordering => Some(ordering),
+        }
+    }
+}
+
+/// Order of `lhs` and `rhs` for the comparison operator `op`, `None` when a
+/// NaN makes them unordered.
+pub fn order(op: &Op, lhs: &Value, rhs: &Value) -> Result<Option<Ordering>, Value> {
+    let symbol = match op {
+        Op::Gt => ">",
+        Op::Gte => ">=",
+        Op::Lt => "<",
+        _ => "<=",
+    };
+    order_values(symbol, false, lhs, rhs, &mut vec![])
+}
+
+/// Total order behind `$compare`: like the comparison operators, except that
+/// NaN is equal to itself and greater than every other number.
+pub fn total_order(lhs: &Value, rhs: &Value) -> Result<Ordering, Value> {
+    Ok(order_values("$compare", true, lhs, rhs, &mut vec![])?.unwrap_or(Ordering::Equal))
+}
+
+/// Arrays being compared are assumed to be equal when they recur.
+fn order_values(
+    op: &str,
+    total: bool,
+    lhs: &Value,
+    rhs: &Value,
+    assumed: &mut Vec<(usize, usize)>,
+) -> Result<Option<Ordering>, Value> {
+    match (lhs, rhs) {
+        (Value::Int(_), _) | (Value::BigInt(_), _) | (Value::Float(_), _) if is_number(rhs) => {
+            Ok(match compare(lhs, rhs) {
+                None if total => Some(is_nan(lhs).cmp(&is_nan(rhs))),
+                ordering => ordering,
+            })
+        }
+        (Value::String(x), Value::String(y)) => Ok(Some(x.borrow().as_str().cmp(&y.borrow()))),
+        (Value::Char(x), Value::Char(y)) => Ok(Some(x.cmp(y))),
+        (Value::Array(x), Value::Array(y)) => {
+            let pair = (Rc::as_ptr(x) as usize, Rc::as_ptr(y) as usize);
+            if assumed.contains(&pair) {
+                return Ok(Some(Ordering::Equal));
+            }
+            let (x, y) = (x.borrow(), y.borrow());
+            assumed.push(pair);
+            let mut result = Some(x.len().cmp(&y.len()));
+            for (x, y) in x.iter().zip(y.iter()) {
+                match order_values(op, total, x, y, assumed) {
+                    Ok(Some(Ordering::Equal)) => (),
+                    Ok(ordering) => {
+                        result = ordering;
+                        break;
+                    }
+                    Err(error) => {
+                        assumed.pop();
+                        return Err(error);
+                    }
+                }
+            }
+            assumed.pop();
+            Ok(result)
+        }
+        _ => Err(unsupported(op, lhs, rhs)),
+    }
+}
+
+fn is_number(value: &Value) -> bool {
+    match value {
+        Value::Int(_) | Value::BigInt(_) | Value::Float(_) => true,
+        _ => false,
+    }
+}
+
+fn is_nan(value: &Value) -> bool {
+    match value {
+        Value::Float(x) => x.is_nan(),
+        _ => false,
+    }
+}
@@ -1,4 +1,4 @@
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Op {
     LoadNull,
     LoadTrue,
@@ -25,6 +25,8 @@ pub enum Op {
     CatchPush(u32),
     Throw,
     Ret,
+    /// Pop a function and that many captured values and push a closure of the
+    /// function with the values appended to its env.
     MakeEnv(u16),
     MakeArray(u16),
     IsNull,
@@ -48,9 +50,22 @@ pub enum Op {
     Lte,
     Not,
     Neg,
+    /// Reserved, the VM can't execute it and the verifier rejects it.
     Hash,
     New,
     Nop,
+    /// Push a copy of the value on top of the stack.
+    Dup,
+    /// Bitwise not of an integer.
+    BitNot,
+    /// Remove the handler installed by the last `CatchPush`.
+    CatchPop,
+    /// Pop a value into a module global.
+    StoreGlobal(u32),
+    /// Whether the top two values are identical (`===`).
+    Same,
+    /// Negation of `Same` (`!==`).
+    NotSame,
 
     Last,
 }
@@ -1,7 +1,10 @@
 use crate::value::{Function, Object};
+use crate::verifier::{nested_functions, verify, VerifyError};
 use crate::*;
 use byteorder::{LittleEndian, ReadBytesExt};
-use std::io::Cursor;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{Cursor, Read};
 use value::*;
 
 pub struct BytecodeReader<'a> {
@@ -10,8 +13,124 @@ pub struct BytecodeReader<'a> {
 
 pub const TAG_STRING: u8 = 0;
 pub const TAG_FLOAT: u8 = 1;
-pub const TAG_DBGINFO: u8 = 2;
 pub const TAG_FUN: u8 = 3;
+pub const TAG_NULL: u8 = 4;
+pub const TAG_BOOL: u8 = 5;
+pub const TAG_INT: u8 = 6;
+pub const TAG_CHAR: u8 = 7;
+pub const TAG_ARRAY: u8 = 8;
+pub const TAG_OBJECT: u8 = 9;
+/// Sign byte, limb count and the limbs of a `BigInt`, least significant first.
+pub const TAG_BIGINT: u8 = 10;
+
+/// Stored instead of a constant index for objects without prototype.
+pub const NO_PROTOTYPE: u32 = u32::max_value();
+
+/// First bytes of every `.j` file.
+pub const MAGIC: [u8; 4] = *b"JZLB";
+/// Bytecode format version written by `BytecodeWriter`.
+pub const VERSION: u16 = 2;
+/// Size of magic, version, flags and checksum in bytes.
+pub const HEADER_SIZE: usize = 12;
+/// Header flag: module contains debug information.
+pub const FLAG_DBGINFO: u16 = 1;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum BytecodeError {
+    UnexpectedEof,
+    BadMagic,
+    UnsupportedVersion(u16),
+    ChecksumMismatch,
+    InvalidUtf8,
+    UnknownGlobalTag(u8),
+    UnknownOpcode(u8),
+    InvalidString(u32),
+    InvalidConstant(u32),
+    InvalidChar(u32),
+    InvalidArgc(i32),
+    InvalidJump { at: usize, to: u32 },
+    InvalidGlobal { at: usize, index: u32 },
+    InvalidLocal { at: usize, index: u16 },
+    InvalidFunction { global: usize, address: u32 },
+    InvalidNestedFunction { address: u32 },
+    Verify(VerifyError),
+}
+
+impl fmt::Display for BytecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BytecodeError::UnexpectedEof => write!(f, "unexpected end of bytecode"),
+            BytecodeError::BadMagic => write!(f, "not a JazzLight bytecode file"),
+            BytecodeError::UnsupportedVersion(v) => write!(
+                f,
+                "unsupported bytecode version {} (expected {})",
+                v, VERSION
+            ),
+            BytecodeError::ChecksumMismatch => write!(f, "bytecode checksum mismatch"),
+            BytecodeError::InvalidUtf8 => write!(f, "string constant is not valid UTF-8"),
+            BytecodeError::UnknownGlobalTag(tag) => write!(f, "unknown global tag {}", tag),
+            BytecodeError::UnknownOpcode(op) => write!(f, "unknown opcode {}", op),
+            BytecodeError::InvalidString(idx) => write!(f, "string index {} out of range", idx),
+            BytecodeError::InvalidConstant(idx) => write!(f, "invalid constant reference {}", idx),
+            BytecodeError::InvalidChar(x) => write!(f, "invalid char constant {:#x}", x),
+            BytecodeError::InvalidArgc(argc) => {
+                write!(f, "function argument count {} out of range", argc)
+            }
+            BytecodeError::InvalidJump { at, to } => {
+                write!(f, "{:04}: jump target {} out of range", at, to)
+            }
+            BytecodeError::InvalidGlobal { at, index } => {
+                write!(f, "{:04}: global index {} out of range", at, index)
+            }
+            BytecodeError::InvalidLocal { at, index } => {
+                write!(f, "{:04}: local {} is never stored", at, index)
+            }
+            BytecodeError::InvalidFunction { global, address } => write!(
+                f,
+                "function in global {} starts outside of code at {}",
+                global, address
+            ),
+            BytecodeError::InvalidNestedFunction { address } => write!(
+                f,
+                "function in a constant starts outside of code at {}",
+                address
+            ),
+            BytecodeError::Verify(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<std::io::Error> for BytecodeError {
+    fn from(_: std::io::Error) -> Self {
+        BytecodeError::UnexpectedEof
+    }
+}
+
+/// FNV-1a hash of module contents, stored in the header to detect corrupted files.
+pub fn checksum(bytes: &[u8]) -> u32 {
+    let mut hash = 0x811c9dc5u32;
+    for byte in bytes {
+        hash ^= *byte as u32;
+        hash = hash.wrapping_mul(0x01000193);
+    }
+    hash
+}
+
+type ReadResult<T> = Result<T, BytecodeError>;
+
+/// Constant pool entry as stored in the file, references are not resolved yet.
+enum Constant {
+    Null,
+    Bool(bool),
+    Int(i64),
+    BigInt(bigint::BigInt),
+    Float(f64),
+    Char(char),
+    String(String),
+    Array(Vec<u32>),
+    Object(Option<u32>, Vec<(u32, u32)>),
+    Function(usize, i32, u32),
+}
 
 impl<'a> BytecodeReader<'a> {
     pub fn new(bytes: &'a [u8]) -> Self {
@@ -20,35 +139,201 @@ impl<'a> BytecodeReader<'a> {
         }
     }
 
-    pub fn read_u8(&mut self) -> u8 {
-        self.bytes.read_u8().unwrap()
+    pub fn read_u8(&mut self) -> ReadResult<u8> {
+        Ok(self.bytes.read_u8()?)
     }
-    pub fn read_u16(&mut self) -> u16 {
-        self.bytes.read_u16::<LittleEndian>().unwrap()
+    pub fn read_u16(&mut self) -> ReadResult<u16> {
+        Ok(self.bytes.read_u16::<LittleEndian>()?)
     }
-    pub fn read_u32(&mut self) -> u32 {
-        self.bytes.read_u32::<LittleEndian>().unwrap()
+    pub fn read_u32(&mut self) -> ReadResult<u32> {
+        Ok(self.bytes.read_u32::<LittleEndian>()?)
     }
-    pub fn read_u64(&mut self) -> u64 {
-        self.bytes.read_u64::<LittleEndian>().unwrap()
+    pub fn read_u64(&mut self) -> ReadResult<u64> {
+        Ok(self.bytes.read_u64::<LittleEndian>()?)
+    }
+
+    fn string(strings: &[String], idx: u32) -> ReadResult<String> {
+        strings
+            .get(idx as usize)
+            .cloned()
+            .ok_or(BytecodeError::InvalidString(idx))
     }
+
     /// Read debug information
     pub fn read_dbginfo(
         &mut self,
         strings: &Vec<String>,
         csize: usize,
-    ) -> HashMap<u32, (usize, String)> {
+    ) -> ReadResult<HashMap<u32, (usize, String)>> {
         let mut map = HashMap::new();
         for i in 0..csize {
-            let line = self.read_u32() as usize;
-            let string_id = self.read_u32() as usize;
-            let string = strings[string_id].clone();
-            map.insert(i as _, (line, string));
+            let line = self.read_u32()? as usize;
+            let string_id = self.read_u32()?;
+            let string = Self::string(strings, string_id)?;
+            // Addresses without line information have an empty file name.
+            if !string.is_empty() {
+                map.insert(i as _, (line, string));
+            }
+        }
+        Ok(map)
+    }
+
+    /// Read and check magic number, version and checksum. Returns header flags.
+    pub fn read_header(&mut self) -> ReadResult<u16> {
+        let mut magic = [0; 4];
+        self.bytes.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(BytecodeError::BadMagic);
+        }
+        let version = self.read_u16()?;
+        if version != VERSION {
+            return Err(BytecodeError::UnsupportedVersion(version));
+        }
+        let flags = self.read_u16()?;
+        let sum = self.read_u32()?;
+        let start = self.bytes.position() as usize;
+        if checksum(&self.bytes.get_ref()[start..]) != sum {
+            return Err(BytecodeError::ChecksumMismatch);
         }
-        map
+        Ok(flags)
     }
 
-    pub fn read_module(&mut self) -> Ref<Module> {
+    fn read_constant(&mut self, strings: &[String]) -> ReadResult<Constant> {
+        let tag = self.read_u8()?;
+        Ok(match tag {
+            TAG_NULL => Constant::Null,
+            TAG_BOOL => Constant::Bool(self.read_u8()? != 0),
+            TAG_INT => Constant::Int(self.read_u64()? as i64),
+            TAG_BIGINT => {
+                let negative = self.read_u8()? != 0;
+                let len = self.read_u32()?;
+                let mut limbs = vec![];
+                for _ in 0..len {
+                    limbs.push(self.read_u32()?);
+                }
+                Constant::BigInt(bigint::BigInt::from_parts(negative, limbs))
+            }
+            TAG_FLOAT => Constant::Float(f64::from_bits(self.read_u64()?)),
+            TAG_CHAR => {
+                let x = self.read_u32()?;
+                Constant::Char(std::char::from_u32(x).ok_or(BytecodeError::InvalidChar(x))?)
+            }
+            TAG_STRING => {
+                let idx = self.read_u32()?;
+                Constant::String(Self::string(strings, idx)?)
+            }
+            TAG_ARRAY => {
+                let len = self.read_u32()?;
+                let mut items = vec![];
+                for _ in 0..len {
+                    items.push(self.read_u32()?);
+                }
+                Constant::Array(items)
+            }
+            TAG_OBJECT => {
+                let prototype = match self.read_u32()? {
+                    NO_PROTOTYPE => None,
+                    idx => Some(idx),
+                };
+                let len = self.read_u32()?;
+                let mut table = vec![];
+                for _ in 0..len {
+                    table.push((self.read_u32()?, self.read_u32()?));
+                }
+                Constant::Object(prototype, table)
+            }
+            TAG_FUN => {
+                let address = self.read_u32()? as usize;
+                let argc = self.read_u32()? as i32;
+                // Parameters are locals, whose indices are `u16`, and a
+                // variadic function also gets the argument array.
+                if argc > i32::from(u16::MAX) || argc < -i32::from(u16::MAX) - 1 {
+                    return Err(BytecodeError::InvalidArgc(argc));
+                }
+                let env = self.read_u32()?;
+                Constant::Function(address, argc, env)
+            }
+            _ => return Err(BytecodeError::UnknownGlobalTag(tag)),
+        })
+    }
+
+    /// Create values for the constant pool. Heap values are allocated first and
+    /// filled afterwards, so constants may refer to each other in any order.
+    fn link_constants(m: &Ref<Module>, constants: Vec<Constant>) -> ReadResult<Vec<Value>> {
+        let values = constants
+            .iter()
+            .map(|constant| match constant {
+                Constant::Null => Value::Null,
+                Constant::Bool(x) => Value::Bool(*x),
+                Constant::Int(x) => Value::Int(*x),
+                Constant::BigInt(x) => Value::from_bigint(x.clone()),
+                Constant::Float(x) => Value::Float(*x),
+                Constant::Char(x) => Value::Char(*x),
+                Constant::String(s) => Value::String(Ref(s.clone())),
+                Constant::Array(_) => Value::Array(Ref(vec![])),
+                Constant::Object(..) => Value::Object(Ref(Object {
+                    prototype: None,
+                    table: Default::default(),
+                })),
+                Constant::Function(address, argc, _) => Value::Function(Ref(Function {
+                    address: *address,
+                    native: false,
+                    env: Value::Null,
+                    argc: *argc,
+                    module: Some(m.clone()),
+                    calls: Default::default(),
+                    bound: None,
+                })),
+            })
+            .collect::<Vec<_>>();
+        let get = |idx: u32| {
+            values
+                .get(idx as usize)
+                .cloned()
+                .ok_or(BytecodeError::InvalidConstant(idx))
+        };
+        for (constant, value) in constants.iter().zip(values.iter()) {
+            match (constant, value) {
+                (Constant::Array(items), Value::Array(array)) => {
+                    let items = items
+                        .iter()
+                        .map(|idx| get(*idx))
+                        .collect::<Result<_, _>>()?;
+                    *array.borrow_mut() = items;
+                }
+                (Constant::Function(_, _, env), Value::Function(f)) => {
+                    f.borrow_mut().env = get(*env)?;
+                }
+                _ => (),
+            }
+        }
+        for (constant, value) in constants.iter().zip(values.iter()) {
+            if let (Constant::Object(prototype, table), Value::Object(object)) = (constant, value) {
+                let mut object = object.borrow_mut();
+                if let Some(idx) = prototype {
+                    match get(*idx)? {
+                        Value::Object(proto) => object.prototype = Some(proto),
+                        _ => return Err(BytecodeError::InvalidConstant(*idx)),
+                    }
+                }
+                for (key, value) in table.iter() {
+                    object.table.insert(get(*key)?, get(*value)?);
+                }
+            }
+        }
+        Ok(values)
+    }
+
+    pub fn read_module(&mut self) -> ReadResult<Ref<Module>> {
+        let m = self.parse_module()?;
+        validate(&m.borrow())?;
+        verify(&m.borrow()).map_err(BytecodeError::Verify)?;
+        Ok(m)
+    }
+
+    /// Read module without checking that its code is valid.
+    pub fn parse_module(&mut self) -> ReadResult<Ref<Module>> {
+        let flags = self.read_header()?;
         let m = Ref(Module {
             exports: Value::Object(Ref(Object {
                 prototype: None,
@@ -59,137 +344,115 @@ impl<'a> BytecodeReader<'a> {
             globals: vec![],
         });
         let mut strings = Vec::new();
-        let count_strings = self.read_u32();
-        let count_globals = self.read_u32();
-        let code_size = self.read_u32();
-        let has_dbginfo = self.read_u8();
+        let count_strings = self.read_u32()?;
+        let count_constants = self.read_u32()?;
+        let count_globals = self.read_u32()?;
+        let code_size = self.read_u32()?;
         for _ in 0..count_strings {
-            let len = self.read_u32();
+            let len = self.read_u32()?;
             let mut bytes = vec![];
             for _ in 0..len {
-                bytes.push(self.read_u8());
+                bytes.push(self.read_u8()?);
             }
-            strings.push(String::from_utf8(bytes).unwrap());
+            strings.push(String::from_utf8(bytes).map_err(|_| BytecodeError::InvalidUtf8)?);
         }
 
-        if has_dbginfo == 1 {
-            m.borrow_mut().trace_info = self.read_dbginfo(&strings, code_size as _);
+        if flags & FLAG_DBGINFO != 0 {
+            m.borrow_mut().trace_info = self.read_dbginfo(&strings, code_size as _)?;
         }
 
+        let mut constants = Vec::with_capacity(count_constants as usize);
+        for _ in 0..count_constants {
+            constants.push(self.read_constant(&strings)?);
+        }
+        let values = Self::link_constants(&m, constants)?;
         for _ in 0..count_globals {
-            let tag = self.read_u8();
-            match tag {
-                TAG_STRING => {
-                    let idx = self.read_u32() as usize;
-                    m.borrow_mut()
-                        .globals
-                        .push(Value::String(Ref(strings[idx].clone())));
-                }
-                TAG_FLOAT => {
-                    let bits = self.read_u64();
-                    let float = f64::from_bits(bits);
-                    m.borrow_mut().globals.push(Value::Float(float));
-                }
-                TAG_FUN => {
-                    let at = self.read_u32();
-                    let argc = self.read_u16();
-                    let env = Ref(vec![]);
-                    let fun = Function {
-                        address: at as _,
-                        native: false,
-                        env: Value::Array(env),
-                        argc: argc as _,
-                        module: Some(m.clone()),
-                    };
-                    //gc_add_root(env);
-                    m.borrow_mut().globals.push(Value::Function(Ref(fun)));
-                }
-                TAG_DBGINFO => {
-                    m.borrow_mut().trace_info = self.read_dbginfo(&strings, code_size as _);
-                }
-                _ => unreachable!(),
-            }
+            let idx = self.read_u32()?;
+            let value = values
+                .get(idx as usize)
+                .cloned()
+                .ok_or(BytecodeError::InvalidConstant(idx))?;
+            m.borrow_mut().globals.push(value);
         }
         use opcode::Op;
         for _ in 0..code_size {
-            let op = self.read_u8();
+            let op = self.read_u8()?;
             let opcode = match op {
                 0 => Op::LoadNull,
                 1 => Op::LoadTrue,
                 2 => Op::LoadFalse,
                 3 => {
-                    let int = self.read_u64() as i64;
+                    let int = self.read_u64()? as i64;
                     Op::LoadInt(int)
                 }
                 4 => {
-                    let idx = self.read_u32();
+                    let idx = self.read_u32()?;
                     Op::LoadGlobal(idx)
                 }
                 5 => {
-                    let idx = self.read_u16();
+                    let idx = self.read_u16()?;
                     Op::LoadEnv(idx)
                 }
                 6 => {
-                    let idx = self.read_u16();
+                    let idx = self.read_u16()?;
                     Op::LoadLocal(idx)
                 }
                 7 => {
-                    let name = self.read_u32() as usize;
-                    let name = strings[name].clone();
-                    Op::LoadBuiltin(name)
+                    let name = self.read_u32()?;
+                    Op::LoadBuiltin(Self::string(&strings, name)?)
                 }
                 8 => Op::LoadThis,
                 9 => Op::Load,
                 10 => Op::Store,
                 11 => {
-                    let idx = self.read_u16();
+                    let idx = self.read_u16()?;
                     Op::StoreEnv(idx)
                 }
                 12 => {
-                    let idx = self.read_u16();
+                    let idx = self.read_u16()?;
                     Op::StoreLocal(idx)
                 }
                 13 => Op::StoreThis,
                 14 => {
-                    let count = self.read_u16();
+                    let count = self.read_u16()?;
                     Op::Pop(count)
                 }
                 15 => {
-                    let count = self.read_u16();
+                    let count = self.read_u16()?;
                     Op::Call(count)
                 }
                 16 => {
-                    let count = self.read_u16();
+                    let count = self.read_u16()?;
                     Op::ObjCall(count)
                 }
                 17 => {
-                    let count = self.read_u16();
+                    let count = self.read_u16()?;
                     Op::TailCall(count)
                 }
                 18 => {
-                    let to = self.read_u32();
+                    let to = self.read_u32()?;
                     Op::Jump(to)
                 }
                 19 => {
-                    let to = self.read_u32();
+                    let to = self.read_u32()?;
                     Op::JumpIf(to)
                 }
                 20 => {
-                    let to = self.read_u32();
+                    let to = self.read_u32()?;
                     Op::JumpIfNot(to)
                 }
                 21 => {
-                    let addr = self.read_u32();
+                    let addr = self.read_u32()?;
                     Op::CatchPush(addr)
                 }
                 22 => Op::Throw,
                 23 => Op::Ret,
                 24 => {
-                    let count = self.read_u16();
+                    let count = self.read_u16()?;
                     Op::MakeEnv(count)
                 }
                 25 => {
-                    let count = self.read_u16();
+                    let count = self.read_u16()?;
                     Op::MakeArray(count)
                 }
                 26 => Op::IsNull,
@@ -217,11 +480,427 @@ impl<'a> BytecodeReader<'a> {
                 48 => Op::New,
                 49 => Op::Nop,
                 50 => Op::Last,
-                _ => unreachable!(),
+                51 => Op::Dup,
+                52 => Op::BitNot,
+                53 => Op::CatchPop,
+                54 => {
+                    let idx = self.read_u32()?;
+                    Op::StoreGlobal(idx)
+                }
+                55 => Op::Same,
+                56 => Op::NotSame,
+                _ => return Err(BytecodeError::UnknownOpcode(op)),
             };
             m.borrow_mut().code.push(opcode);
         }
 
-        m
+        Ok(m)
+    }
+}
+
+/// Check that every index stored in the code refers to something that exists,
+/// so that malformed bytecode is rejected before `Vm::interp` runs it.
+pub fn validate(m: &Module) -> ReadResult<()> {
+    use opcode::Op;
+    let len = m.code.len();
+    let mut max_local = None;
+    let globals = m
+        .globals
+        .iter()
+        .enumerate()
+        .filter_map(|(i, global)| match global {
+            Value::Function(f) => Some((Some(i), f.clone())),
+            _ => None,
+        });
+    let nested = nested_functions(m).into_iter().map(|(_, f)| (None, f));
+    for (global, f) in globals.chain(nested) {
+        let f = f.borrow();
+        if f.native {
+            continue;
+        }
+        if f.address >= len {
+            let address = f.address as u32;
+            return Err(match global {
+                Some(global) => BytecodeError::InvalidFunction { global, address },
+                None => BytecodeError::InvalidNestedFunction { address },
+            });
+        }
+        if f.argc > 0 {
+            let last = u16::try_from(f.argc - 1).map_err(|_| BytecodeError::InvalidArgc(f.argc))?;
+            max_local = max_local.max(Some(last));
+        } else if f.argc < 0 {
+            // Variadic functions also get the argument array.
+            let last =
+                u16::try_from(-(f.argc + 1)).map_err(|_| BytecodeError::InvalidArgc(f.argc))?;
+            max_local = max_local.max(Some(last));
+        }
+    }
+    for op in m.code.iter() {
+        if let Op::StoreLocal(idx) = op {
+            max_local = max_local.max(Some(*idx));
+        }
+    }
+    for (at, op) in m.code.iter().enumerate() {
+        match op {
+            Op::Jump(to) | Op::JumpIf(to) | Op::JumpIfNot(to) | Op::CatchPush(to) => {
+                if *to as usize > len {
+                    return Err(BytecodeError::InvalidJump { at, to: *to });
+                }
+            }
+            Op::LoadGlobal(index) | Op::StoreGlobal(index) => {
+                if *index as usize >= m.globals.len() {
+                    return Err(BytecodeError::InvalidGlobal { at, index: *index });
+                }
+            }
+            Op::LoadLocal(index) => {
+                if max_local.map(|max| *index > max).unwrap_or(true) {
+                    return Err(BytecodeError::InvalidLocal { at, index: *index });
+                }
+            }
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::opcode::Op;
+    use crate::writer::BytecodeWriter;
+
+    fn encode(code: Vec<Op>) -> Vec<u8> {
+        let m = Ref(Module {
+            exports: Value::Null,
+            code,
+            globals: vec![Value::String(Ref("print".to_owned()))],
+            trace_info: HashMap::new(),
+        });
+        let mut w = BytecodeWriter { bytecode: vec![] };
+        w.write_module(m);
+        w.bytecode
+    }
+
+    #[test]
+    fn read_written_module() {
+        let bytes = encode(vec![Op::LoadGlobal(0), Op::Jump(3), Op::Nop, Op::Ret]);
+        let m = BytecodeReader::new(&bytes).read_module().unwrap();
+        assert_eq!(m.borrow().code.len(), 4);
+        assert_eq!(m.borrow().globals.len(), 1);
+    }
+
+    #[test]
+    fn reject_foreign_and_truncated_files() {
+        let bytes = encode(vec![Op::LoadNull, Op::Ret]);
+        let err = |bytes: &[u8]| BytecodeReader::new(bytes).read_module().err();
+        assert_eq!(err(b"\x7fELF...."), Some(BytecodeError::BadMagic));
+        assert_eq!(err(&bytes[..6]), Some(BytecodeError::UnexpectedEof));
+        assert_eq!(
+            err(&bytes[..bytes.len() - 1]),
+            Some(BytecodeError::ChecksumMismatch)
+        );
+        let mut newer = bytes.clone();
+        newer[4] = 99;
+        assert_eq!(err(&newer), Some(BytecodeError::UnsupportedVersion(99)));
+    }
+
+    fn function(address: usize, argc: i32) -> Value {
+        Value::Function(Ref(Function {
+            native: false,
+            address,
+            argc,
+            env: Value::Array(Ref(vec![])),
+            module: None,
+            calls: Default::default(),
+            bound: None,
+        }))
+    }
+
+    fn encode_globals(globals: Vec<Value>) -> Vec<u8> {
+        let m = Ref(Module {
+            exports: Value::Null,
+            code: vec![Op::LoadNull, Op::Ret],
+            globals,
+            trace_info: HashMap::new(),
+        });
+        let mut w = BytecodeWriter { bytecode: vec![] };
+        w.write_module(m);
+        w.bytecode
+    }
+
+    fn encode_function(argc: i32) -> Vec<u8> {
+        encode_globals(vec![function(0, argc)])
+    }
+
+    #[test]
+    fn reject_out_of_range_argc() {
+        let err = |argc| {
+            BytecodeReader::new(&encode_function(argc))
+                .read_module()
+                .err()
+        };
+        assert_eq!(err(65535), None);
+        assert_eq!(err(65536), Some(BytecodeError::InvalidArgc(65536)));
+        assert_eq!(err(i32::MAX), Some(BytecodeError::InvalidArgc(i32::MAX)));
+        assert_eq!(err(-65536), None);
+        assert_eq!(err(-65537), Some(BytecodeError::InvalidArgc(-65537)));
+        assert_eq!(err(i32::MIN), Some(BytecodeError::InvalidArgc(i32::MIN)));
+    }
+
+    #[test]
+    fn reject_functions_outside_code() {
+        let err = |global| {
+            BytecodeReader::new(&encode_globals(vec![global]))
+                .read_module()
+                .err()
+        };
+        assert_eq!(
+            err(function(2, 0)),
+            Some(BytecodeError::InvalidFunction {
+                global: 0,
+                address: 2
+            })
+        );
+        assert_eq!(
+            err(Value::Array(Ref(vec![function(5, 0)]))),
+            Some(BytecodeError::InvalidNestedFunction { address: 5 })
+        );
+    }
+
+    #[test]
+    fn reject_out_of_range_indices() {
+        let err = |code| BytecodeReader::new(&encode(code)).read_module().err();
+        assert_eq!(
+            err(vec![Op::Jump(10)]),
+            Some(BytecodeError::InvalidJump { at: 0, to: 10 })
+        );
+        assert_eq!(
+            err(vec![Op::LoadNull, Op::LoadGlobal(1)]),
+            Some(BytecodeError::InvalidGlobal { at: 1, index: 1 })
+        );
+        assert_eq!(
+            err(vec![Op::LoadNull, Op::StoreLocal(0), Op::LoadLocal(1)]),
+            Some(BytecodeError::InvalidLocal { at: 2, index: 1 })
+        );
+    }
+
+    pub(crate) mod roundtrip {
+        use super::*;
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+
+        struct Gen {
+            rng: StdRng,
+            /// Heap values created so far, reused to test sharing and cycles.
+            heap: Vec<Value>,
+            code_len: usize,
+        }
+
+        impl Gen {
+            fn string(&mut self) -> String {
+                let len = self.rng.gen_range(0, 8);
+                (0..len).map(|_| self.rng.gen::<char>()).collect()
+            }
+
+            fn value(&mut self, depth: usize) -> Value {
+                if !self.heap.is_empty() && self.rng.gen_ratio(1, 8) {
+                    let i = self.rng.gen_range(0, self.heap.len());
+                    return self.heap[i].clone();
+                }
+                let kind = self.rng.gen_range(0, if depth == 0 { 7 } else { 10 });
+                let value = match kind {
+                    0 => Value::Null,
+                    1 => Value::Bool(self.rng.gen()),
+                    2 => Value::Int(self.rng.gen()),
+                    3 => Value::Float(f64::from_bits(self.rng.gen())),
+                    4 => Value::Char(self.rng.gen()),
+                    5 => Value::String(Ref(self.string())),
+                    6 => {
+                        let limbs = (0..self.rng.gen_range(3, 6))
+                            .map(|_| self.rng.gen())
+                            .collect();
+                        Value::from_bigint(bigint::BigInt::from_parts(self.rng.gen(), limbs))
+                    }
+                    7 => {
+                        let array = Ref(vec![]);
+                        self.heap.push(Value::Array(array.clone()));
+                        let len = self.rng.gen_range(0, 4);
+                        for _ in 0..len {
+                            let item = self.value(depth - 1);
+                            array.borrow_mut().push(item);
+                        }
+                        Value::Array(array)
+                    }
+                    8 => {
+                        let prototype = self.heap.iter().find_map(|x| x.to_object());
+                        let object = Ref(Object {
+                            prototype: prototype.filter(|_| self.rng.gen()),
+                            table: Default::default(),
+                        });
+                        self.heap.push(Value::Object(object.clone()));
+                        for _ in 0..self.rng.gen_range(0, 4) {
+                            let key = match self.rng.gen() {
+                                true => Value::String(Ref(self.string())),
+                                false => Value::Int(self.rng.gen()),
+                            };
+                            let value = self.value(depth - 1);
+                            object.borrow_mut().table.insert(key, value);
+                        }
+                        Value::Object(object)
+                    }
+                    _ => {
+                        let env = (0..self.rng.gen_range(0, 3))
+                            .map(|_| self.value(depth - 1))
+                            .collect();
+                        let function = Value::Function(Ref(Function {
+                            native: false,
+                            address: self.rng.gen_range(0, self.code_len),
+                            argc: self.rng.gen_range(-1, 5),
+                            env: Value::Array(Ref(env)),
+                            module: None,
+                            calls: Default::default(),
+                            bound: None,
+                        }));
+                        self.heap.push(function.clone());
+                        function
+                    }
+                };
+                value
+            }
+
+            fn op(&mut self) -> Op {
+                let n = self.rng.gen::<u16>();
+                let x = self.rng.gen::<u32>();
+                match self.rng.gen_range(0, 12) {
+                    0 => Op::LoadInt(self.rng.gen()),
+                    1 => Op::LoadGlobal(x),
+                    2 => Op::LoadEnv(n),
+                    3 => Op::LoadLocal(n),
+                    4 => Op::LoadBuiltin(self.string()),
+                    5 => Op::StoreLocal(n),
+                    6 => Op::Pop(n),
+                    7 => Op::Call(n),
+                    8 => Op::ObjCall(n),
+                    9 => Op::JumpIfNot(x),
+                    10 => Op::CatchPush(x),
+                    _ => [Op::Add, Op::UShr, Op::Ret, Op::Hash, Op::New, Op::Last]
+                        [self.rng.gen_range(0, 6)]
+                    .clone(),
+                }
+            }
+
+            fn module(&mut self) -> Module {
+                self.code_len = self.rng.gen_range(1, 40);
+                let code = (0..self.code_len).map(|_| self.op()).collect();
+                let globals = (0..self.rng.gen_range(0, 10))
+                    .map(|_| self.value(3))
+                    .collect();
+                let mut trace_info = HashMap::new();
+                if self.rng.gen() {
+                    for pc in 0..self.code_len {
+                        if self.rng.gen_ratio(2, 3) {
+                            let file = format!("{}.jzl", self.rng.gen_range(0, 3));
+                            trace_info.insert(pc as u32, (self.rng.gen_range(1, 100), file));
+                        }
+                    }
+                }
+                Module {
+                    exports: Value::Null,
+                    code,
+                    globals,
+                    trace_info,
+                }
+            }
+        }
+
+        /// Random module whose constants share heap values and contain a cycle
+        /// when there are any arrays.
+        pub(crate) fn random_module(seed: u64) -> Ref<Module> {
+            let mut gen = Gen {
+                rng: StdRng::seed_from_u64(seed),
+                heap: vec![],
+                code_len: 0,
+            };
+            let m = Ref(gen.module());
+            // Cycle through an array that contains itself.
+            if let Some(Value::Array(array)) = gen.heap.first() {
+                array.borrow_mut().push(Value::Array(array.clone()));
+            }
+            m
+        }
+
+        /// Structural equality that also requires both values to share heap
+        /// values in the same places.
+        pub(crate) fn same(a: &Value, b: &Value, seen: &mut HashMap<usize, usize>) -> bool {
+            fn ptr<T: ?Sized>(x: &Ref<T>) -> usize {
+                Rc::as_ptr(x) as *const u8 as usize
+            }
+            let (pa, pb) = match (a, b) {
+                (Value::String(x), Value::String(y)) => (ptr(x), ptr(y)),
+                (Value::Array(x), Value::Array(y)) => (ptr(x), ptr(y)),
+                (Value::Object(x), Value::Object(y)) => (ptr(x), ptr(y)),
+                (Value::Function(x), Value::Function(y)) => (ptr(x), ptr(y)),
+                (Value::Null, Value::Null) => return true,
+                (Value::Bool(x), Value::Bool(y)) => return x == y,
+                (Value::Int(x), Value::Int(y)) => return x == y,
+                (Value::BigInt(x), Value::BigInt(y)) => return x == y,
+                (Value::Float(x), Value::Float(y)) => return x.to_bits() == y.to_bits(),
+                (Value::Char(x), Value::Char(y)) => return x == y,
+                _ => return false,
+            };
+            if let Some(seen_b) = seen.get(&pa) {
+                return *seen_b == pb;
+            }
+            seen.insert(pa, pb);
+            match (a, b) {
+                (Value::String(x), Value::String(y)) => *x.borrow() == *y.borrow(),
+                (Value::Array(x), Value::Array(y)) => {
+                    let (x, y) = (x.borrow(), y.borrow());
+                    x.len() == y.len() && x.iter().zip(y.iter()).all(|(x, y)| same(x, y, seen))
+                }
+                (Value::Object(x), Value::Object(y)) => {
+                    let (x, y) = (x.borrow(), y.borrow());
+                    let protos = match (&x.prototype, &y.prototype) {
+                        (None, None) => true,
+                        (Some(px), Some(py)) => {
+                            same(&Value::Object(px.clone()), &Value::Object(py.clone()), seen)
+                        }
+                        _ => false,
+                    };
+                    protos
+                        && x.table.len() == y.table.len()
+                        && x.table
+                            .iter()
+                            .zip(y.table.iter())
+                            .all(|((kx, vx), (ky, vy))| same(kx, ky, seen) && same(vx, vy, seen))
+                }
+                (Value::Function(x), Value::Function(y)) => {
+                    let (x, y) = (x.borrow(), y.borrow());
+                    x.address == y.address
+                        && x.argc == y.argc
+                        && x.native == y.native
+                        && same(&x.env, &y.env, seen)
+                }
+                _ => unreachable!(),
+            }
+        }
+
+        #[test]
+        fn write_then_read_preserves_module() {
+            for seed in 0..300 {
+                let m = random_module(seed);
+                let mut w = BytecodeWriter { bytecode: vec![] };
+                w.write_module(m.clone());
+                let read = BytecodeReader::new(&w.bytecode).parse_module().unwrap();
+                let (m, read) = (m.borrow(), read.borrow());
+                assert_eq!(m.code, read.code, "seed {}", seed);
+                assert_eq!(m.trace_info, read.trace_info, "seed {}", seed);
+                assert_eq!(m.globals.len(), read.globals.len(), "seed {}", seed);
+                let mut seen = HashMap::new();
+                for (a, b) in m.globals.iter().zip(read.globals.iter()) {
+                    assert!(same(a, b, &mut seen), "seed {}", seed);
+                }
+            }
+        }
     }
 }
@@ -1,5 +1,7 @@
+use crate::bigint::BigInt;
 use crate::*;
 use hashlink::LinkedHashMap;
+use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
 
 #[derive(Clone)]
@@ -7,6 +9,8 @@ pub enum Value {
     Null,
     Bool(bool),
     Int(i64),
+    /// Integer outside the `i64` range, see `bigint`.
+    BigInt(Rc<BigInt>),
     Float(f64),
     String(Ref<String>),
     Array(Ref<Vec<Value>>),
@@ -21,6 +25,7 @@ pub enum ValTag {
     Null,
     Bool,
     Int,
+    BigInt,
     Float,
     Str,
     Array,
@@ -77,6 +82,7 @@ impl Value {
     pub fn to_float(&self) -> Option<f64> {
         match self {
             Value::Int(x) => Some(*x as f64),
+            Value::BigInt(x) => Some(x.to_f64()),
             Value::Float(x) => Some(*x),
             _ => None,
         }
@@ -85,6 +91,7 @@ impl Value {
     pub fn tag(&self) -> ValTag {
         match self {
             Value::Int(_) => ValTag::Int,
+            Value::BigInt(_) => ValTag::BigInt,
             Value::Float(_) => ValTag::Float,
             Value::Null => ValTag::Null,
             Value::Object(_) => ValTag::Object,
@@ -96,43 +103,115 @@ impl Value {
             Value::User(x) => ValTag::User(x.borrow().get_kind()),
         }
     }
+
+    /// Name of the value's type as reported by `$typeof` and in error messages.
+    pub fn type_name(&self) -> &'static str {
+        match self.tag() {
+            ValTag::Array => "array",
+            ValTag::Null => "null",
+            ValTag::Float => "float",
+            ValTag::Int | ValTag::BigInt => "int",
+            ValTag::Str => "string",
+            ValTag::Bool => "bool",
+            ValTag::Object => "object",
+            ValTag::Char => "char",
+            ValTag::Func => "function",
+            ValTag::User(x) => x,
+        }
+    }
+
+    /// `x` as an `Int` when it fits, otherwise as a `BigInt`.
+    pub fn from_bigint(x: BigInt) -> Value {
+        match x.to_i64() {
+            Some(x) => Value::Int(x),
+            None => Value::BigInt(Rc::new(x)),
+        }
+    }
 }
 
+/// How deep `Hash for Value` looks into nested arrays and objects. Deeper
+/// containers only contribute their length, which keeps hashing of cyclic
+/// values finite.
+const HASH_DEPTH: usize = 4;
+
 impl Hash for Value {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        match self {
-            Value::Null => {
-                0.hash(state);
-            }
-            Value::Int(x) => {
-                1.hash(state);
-                x.hash(state);
+        hash_value(self, state, HASH_DEPTH);
+    }
+}
+
+/// Hash agreeing with `PartialEq`: integral floats hash like the integer
+/// they equal, all NaNs hash alike and object entries are combined
+/// independently of their order.
+fn hash_value<H: Hasher>(value: &Value, state: &mut H, depth: usize) {
+    match value {
+        Value::Null => {
+            0.hash(state);
+        }
+        Value::Int(x) => {
+            1.hash(state);
+            x.hash(state);
+        }
+        Value::Float(x) => match integral(*x) {
+            Some(x) => hash_value(&x, state, depth),
+            None if x.is_nan() => {
+                2.hash(state);
+                std::f64::NAN.to_bits().hash(state);
             }
-            Value::Float(x) => {
+            None => {
                 2.hash(state);
                 x.to_bits().hash(state);
             }
-            Value::String(s) => {
-                3.hash(state);
-                s.borrow().hash(state);
-            }
-            Value::Array(array) => {
-                4.hash(state);
-                array.borrow().hash(state);
-            }
-            Value::Object(object) => {
-                5.hash(state);
-                object.borrow().hash(state);
-            }
-            Value::Bool(x) => {
-                6.hash(state);
-                x.hash(state);
+        },
+        Value::String(s) => {
+            3.hash(state);
+            s.borrow().hash(state);
+        }
+        Value::Array(array) => {
+            4.hash(state);
+            let array = array.borrow();
+            array.len().hash(state);
+            if depth > 0 {
+                for item in array.iter() {
+                    hash_value(item, state, depth - 1);
+                }
             }
-            Value::Char(x) => {
-                7.hash(state);
-                x.hash(state);
+        }
+        Value::Object(object) => {
+            5.hash(state);
+            let object = object.borrow();
+            object.table.len().hash(state);
+            object.prototype.as_ref().map(Rc::as_ptr).hash(state);
+            if depth > 0 {
+                let mut entries = 0u64;
+                for (key, val) in object.table.iter() {
+                    let mut entry = DefaultHasher::new();
+                    hash_value(key, &mut entry, depth - 1);
+                    hash_value(val, &mut entry, depth - 1);
+                    entries = entries.wrapping_add(entry.finish());
+                }
+                entries.hash(state);
             }
-            _ => (),
+        }
+        Value::Bool(x) => {
+            6.hash(state);
+            x.hash(state);
+        }
+        Value::Char(x) => {
+            7.hash(state);
+            x.hash(state);
+        }
+        Value::BigInt(x) => {
+            8.hash(state);
+            x.hash(state);
+        }
+        Value::Function(func) => {
+            9.hash(state);
+            Rc::as_ptr(func).hash(state);
+        }
+        Value::User(user) => {
+            10.hash(state);
+            (Rc::as_ptr(user) as *const u8).hash(state);
         }
     }
 }
@@ -140,102 +219,123 @@ impl Hash for Value {
 use std::fmt;
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Value::Int(x) => write!(f, "{}", x),
-            Value::Float(x) => write!(f, "{}", x),
-            Value::Array(array) => {
-                let mut fmt = String::new();
-                fmt.push('[');
-                for (idx, value) in array.borrow().iter().enumerate() {
-                    fmt.push_str(&value.to_string());
-
-                    if idx < array.borrow().len() - 1 {
-                        fmt.push(',');
-                    }
-                }
+        write!(f, "{}", crate::format::plain(self))
+    }
+}
 
-                fmt.push(']');
-                write!(f, "{}", fmt)
+/// Structural equality, what `==` and `!=` compute, see `Value::equals`:
+///
+/// - Numbers are equal when they have the same mathematical value, so
+///   `1 == 1.0` but `1 != 1.9`. `NaN` is not equal to anything.
+/// - Chars are only equal to chars, strings to strings with the same text.
+/// - Arrays are equal when they have the same length and equal elements.
+/// - Objects are equal when they have the same prototype, the same set of
+///   keys and equal values for each key, in any insertion order.
+/// - Functions and user values are only equal to themselves.
+///
+/// Containers that contain themselves are compared by assuming that a pair
+/// already being compared is equal.
+///
+/// This is also how object fields are looked up, except that `NaN` equals
+/// itself there, like in `$compare`, so that `o[n] = 1` with a `NaN` key can
+/// be read back and `Eq` holds.
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        equal(self, other, true, &mut vec![])
+    }
+}
+
+/// `lhs == rhs` given that the container pairs in `assumed` are equal. `NaN`
+/// equals `NaN` if `nan_equal` is set.
+fn equal(lhs: &Value, rhs: &Value, nan_equal: bool, assumed: &mut Vec<(usize, usize)>) -> bool {
+    match (lhs, rhs) {
+        (Value::Null, Value::Null) => true,
+        (Value::Bool(x), Value::Bool(y)) => x == y,
+        (Value::Int(x), Value::Int(y)) => x == y,
+        // Normalized, so never equal to an `Int`.
+        (Value::BigInt(x), Value::BigInt(y)) => x == y,
+        (Value::Float(x), Value::Float(y)) => x == y || nan_equal && x.is_nan() && y.is_nan(),
+        (Value::Float(x), Value::Int(_)) | (Value::Float(x), Value::BigInt(_)) => {
+            integral(*x).map_or(false, |x| x == *rhs)
+        }
+        (Value::Int(_), Value::Float(y)) | (Value::BigInt(_), Value::Float(y)) => {
+            integral(*y).map_or(false, |y| y == *lhs)
+        }
+        (Value::Char(x), Value::Char(y)) => x == y,
+        (Value::String(x), Value::String(y)) => *x.borrow() == *y.borrow(),
+        (Value::Array(x), Value::Array(y)) => {
+            let pair = (Rc::as_ptr(x) as usize, Rc::as_ptr(y) as usize);
+            if assumed.contains(&pair) {
+                return true;
             }
-            Value::Char(x) => write!(f, "{}", x),
-            Value::Object(object) => {
-                let mut fmt = String::new();
-                fmt.push_str("{\n");
-                for (i, (key, val)) in object.borrow().table.iter().enumerate() {
-                    let key = key.to_string();
-                    let value = val.to_string();
-                    fmt.push_str(&format!("  {} => {}", key, value));
-                    if i < object.borrow().table.len() - 1 {
-                        fmt.push(',');
-                    }
-                    fmt.push('\n');
-                }
-                fmt.push('}');
-                write!(f, "{}", fmt)
+            let (x, y) = (x.borrow(), y.borrow());
+            if x.len() != y.len() {
+                return false;
             }
-            Value::Function(func) => {
-                if func.borrow().native {
-                    write!(f, "<function {:x}>", func.borrow().address)
-                } else {
-                    write!(f, "<function at {:x}>", func.borrow().address)
-                }
+            assumed.push(pair);
+            let result = x
+                .iter()
+                .zip(y.iter())
+                .all(|(x, y)| equal(x, y, nan_equal, assumed));
+            assumed.pop();
+            result
+        }
+        (Value::Object(x), Value::Object(y)) => {
+            let pair = (Rc::as_ptr(x) as usize, Rc::as_ptr(y) as usize);
+            if assumed.contains(&pair) {
+                return true;
             }
-            Value::User(x) => write!(f, "{}", x.borrow()),
-            Value::Null => write!(f, "null"),
-            Value::String(s) => write!(f, "{}", *s.borrow()),
-            Value::Bool(x) => write!(f, "{}", x),
+            let (x, y) = (x.borrow(), y.borrow());
+            let same_prototype = match (&x.prototype, &y.prototype) {
+                (Some(p), Some(q)) => Rc::ptr_eq(p, q),
+                (None, None) => true,
+                _ => false,
+            };
+            if !same_prototype || x.table.len() != y.table.len() {
+                return false;
+            }
+            assumed.push(pair);
+            let result = x.table.iter().all(|(key, x)| match y.table.get(key) {
+                Some(y) => equal(x, y, nan_equal, assumed),
+                None => false,
+            });
+            assumed.pop();
+            result
         }
+        (Value::Function(x), Value::Function(y)) => Rc::ptr_eq(x, y),
+        (Value::User(x), Value::User(y)) => Rc::ptr_eq(x, y),
+        _ => false,
     }
 }
 
-impl PartialEq for Value {
-    fn eq(&self, other: &Self) -> bool {
-        match self {
-            Value::Bool(x) => match other {
-                Value::Bool(y) => x == y,
-                _ => false,
-            },
-            Value::Int(x) => match other {
-                Value::Int(y) => x == y,
-                Value::Float(y) => *x == *y as i64,
-                _ => false,
-            },
-            Value::Float(x) => match other {
-                Value::Int(y) => *x == *y as f64,
-                Value::Float(y) => x == y,
-                _ => false,
-            },
-            Value::Char(x) => match other {
-                Value::Int(y) => *x as u32 == *y as u32,
-                Value::Char(y) => *x == *y,
-                _ => false,
-            },
-            Value::String(s) => match other {
-                Value::String(s2) => *s.borrow() == *s2.borrow(),
-                _ => false,
-            },
-            Value::Array(x) => match other {
-                Value::Array(y) => *x.borrow() == *y.borrow(),
-                _ => false,
-            },
-            Value::Null => match other {
-                Value::Null => true,
-                _ => false,
-            },
-            Value::Object(x) => match other {
-                Value::Object(y) => {
-                    for ((key1, val1), (key2, val2)) in
-                        x.borrow().table.iter().zip(y.borrow().table.iter())
-                    {
-                        if (key1 != key2) || (val2 != val1) {
-                            return false;
-                        }
-                    }
-                    true
-                }
-                _ => false,
-            },
-            _ => false,
+/// The integer equal to `x`, if there is one.
+fn integral(x: f64) -> Option<Value> {
+    if !x.is_finite() || x.fract() != 0.0 {
+        None
+    } else if x >= -9223372036854775808.0 && x < 9223372036854775808.0 {
+        Some(Value::Int(x as i64))
+    } else {
+        Some(Value::BigInt(Rc::new(BigInt::from_f64(x))))
+    }
+}
+
+impl Value {
+    /// `self == other` in a script, where `NaN` is not equal to anything.
+    pub fn equals(&self, other: &Value) -> bool {
+        equal(self, other, false, &mut vec![])
+    }
+
+    /// Identity, what `===` and `!==` compute. Strings, arrays, objects,
+    /// functions and user values are identical only to the same instance,
+    /// other values to values of the same type that are `==`.
+    pub fn same(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::String(x), Value::String(y)) => Rc::ptr_eq(x, y),
+            (Value::Array(x), Value::Array(y)) => Rc::ptr_eq(x, y),
+            (Value::Object(x), Value::Object(y)) => Rc::ptr_eq(x, y),
+            (Value::Function(x), Value::Function(y)) => Rc::ptr_eq(x, y),
+            (Value::User(x), Value::User(y)) => Rc::ptr_eq(x, y),
+            _ => self.tag() == other.tag() && self.equals(other),
         }
     }
 }
@@ -263,31 +363,106 @@ impl Object {
     }
 }
 
-impl Hash for Object {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        for (key, val) in self.table.iter() {
-            key.hash(state);
-            val.hash(state);
-        }
-        self.table.len().hash(state);
-        match &self.prototype {
-            Some(value) => value.borrow().hash(state),
-            _ => (),
-        }
-    }
-}
-
 #[derive(Clone)]
 pub struct Function {
     pub native: bool,
     pub address: usize,
     pub env: Value,
     pub module: Option<Ref<Module>>,
+    /// Number of parameters. Natives that take any number of arguments have
+    /// -1, variadic script functions with `n` positional parameters `-(n + 1)`.
     pub argc: i32,
+    /// How many times this function was called, used by the JIT to find hot functions.
+    pub calls: std::cell::Cell<usize>,
+    /// Set for functions made by `$bind`.
+    pub bound: Option<Rc<Bound>>,
+}
+
+/// `this` and leading arguments a bound function passes to its target. A
+/// bound function is a copy of the target with this attached, so `argc`,
+/// `check_args` and `locals` see all arguments of the call.
+pub struct Bound {
+    pub this: Value,
+    pub args: Vec<Value>,
+}
+
+impl Function {
+    /// Copy of this function that calls it with `this` and `args` before the
+    /// arguments it is called with. Binding a bound function again keeps its
+    /// `this` and appends to its arguments.
+    pub fn bind(&self, this: Value, args: &[Value]) -> Function {
+        let bound = match &self.bound {
+            Some(bound) => Bound {
+                this: bound.this.clone(),
+                args: bound.args.iter().chain(args).cloned().collect(),
+            },
+            None => Bound {
+                this,
+                args: args.to_vec(),
+            },
+        };
+        Function {
+            calls: Default::default(),
+            bound: Some(Rc::new(bound)),
+            ..self.clone()
+        }
+    }
+
+    /// Number of arguments still expected, encoded like `argc`. This is what
+    /// `$nargs` reports, so it doesn't count arguments bound by `$bind`.
+    pub fn arity(&self) -> i32 {
+        let bound = self
+            .bound
+            .as_ref()
+            .map_or(0, |bound| bound.args.len() as i32);
+        if self.argc >= 0 {
+            (self.argc - bound).max(0)
+        } else {
+            -((-(self.argc + 1) - bound).max(0) + 1)
+        }
+    }
+
+    /// Throw unless a function with a fixed number of parameters gets that
+    /// many arguments. Variadic functions check their arguments themselves.
+    pub fn check_args(&self, count: usize) -> Result<(), Value> {
+        if self.argc >= 0 && count != self.argc as usize {
+            return Err(Value::String(Ref(format!(
+                "Expected {} arguments,found {}",
+                self.argc, count
+            ))));
+        }
+        Ok(())
+    }
+
+    /// Locals of a call to this script function. A variadic function with
+    /// `argc == -(n + 1)` gets `n` positional arguments, null for missing
+    /// ones, and the array of all arguments in local `n`.
+    pub fn locals(&self, args: Vec<Value>) -> Ref<HashMap<u16, Value>> {
+        let mut locals = HashMap::new();
+        if self.argc >= 0 {
+            for (i, arg) in args.into_iter().enumerate() {
+                locals.insert(i as u16, arg);
+            }
+        } else {
+            let n = (-(self.argc + 1)) as usize;
+            for i in 0..n {
+                locals.insert(i as u16, args.get(i).cloned().unwrap_or(Value::Null));
+            }
+            locals.insert(n as u16, Value::Array(Ref(args)));
+        }
+        Ref(locals)
+    }
 }
 
 pub trait UserKind: mopa::Any + fmt::Debug + fmt::Display {
     fn get_kind(&self) -> &'static str;
+
+    /// Method `name` of values of this kind, such as the operator methods
+    /// described in `meta`. It is called with the user value as `this`, so a
+    /// native method gets it as its first argument.
+    fn method(&self, _name: &str) -> Option<Value> {
+        None
+    }
 }
 /*
 use crate::gc::Trace;
@@ -330,3 +505,142 @@ impl Trace for Object {
 */
 
 mopafy!(UserKind);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn hash(value: &Value) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn object_key(key: &str) -> Value {
+        Value::String(Ref(key.to_owned()))
+    }
+
+    fn object(entries: Vec<(&str, Value)>) -> Value {
+        let mut table = LinkedHashMap::new();
+        for (key, value) in entries {
+            table.insert(object_key(key), value);
+        }
+        Value::Object(Ref(Object {
+            prototype: None,
+            table,
+        }))
+    }
+
+    /// Random values from a small domain, so that equal values turn up often.
+    fn value(rng: &mut StdRng, depth: usize) -> Value {
+        match rng.gen_range(0, if depth == 0 { 7 } else { 9 }) {
+            0 => Value::Null,
+            1 => Value::Int(rng.gen_range(-2, 3)),
+            2 => {
+                let nan = std::f64::NAN;
+                Value::Float([0.0, -0.0, 1.0, 1.5, -2.0, 1e19, nan, -nan][rng.gen_range(0, 8)])
+            }
+            3 => Value::from_bigint(BigInt::from_f64(1e19)),
+            4 => Value::Char(['a', '\u{1}'][rng.gen_range(0, 2)]),
+            5 => Value::String(Ref(["", "a"][rng.gen_range(0, 2)].to_owned())),
+            6 => Value::Bool(rng.gen()),
+            7 => Value::Array(Ref((0..rng.gen_range(0, 3))
+                .map(|_| value(rng, depth - 1))
+                .collect())),
+            _ => {
+                let mut table = LinkedHashMap::new();
+                let nan = Value::Float(std::f64::NAN);
+                let keys = [object_key("x"), object_key("y"), nan.clone(), nan];
+                for key in keys.iter() {
+                    if rng.gen() {
+                        table.insert(key.clone(), value(rng, depth - 1));
+                    }
+                }
+                Value::Object(Ref(Object {
+                    prototype: None,
+                    table,
+                }))
+            }
+        }
+    }
+
+    /// A structurally equal copy: objects get their keys in reverse order
+    /// and integers that floats represent exactly become floats.
+    fn copy(value: &Value) -> Value {
+        match value {
+            Value::Int(x) if x.abs() < 1 << 53 => Value::Float(*x as f64),
+            Value::Float(x) if x.fract() == 0.0 && x.abs() < 2f64.powi(53) => Value::Int(*x as i64),
+            Value::String(s) => Value::String(Ref(s.borrow().clone())),
+            Value::Array(array) => Value::Array(Ref(array.borrow().iter().map(copy).collect())),
+            Value::Object(object) => {
+                let mut table = LinkedHashMap::new();
+                for (key, value) in object.borrow().table.iter().rev() {
+                    table.insert(key.clone(), copy(value));
+                }
+                Value::Object(Ref(Object {
+                    prototype: None,
+                    table,
+                }))
+            }
+            value => value.clone(),
+        }
+    }
+
+    #[test]
+    fn equality_is_structural() {
+        assert!(
+            object(vec![("a", Value::Int(1))])
+                != object(vec![("a", Value::Int(1)), ("b", Value::Int(2))])
+        );
+        assert!(Value::Array(Ref(vec![Value::Int(1)])) != Value::Array(Ref(vec![])));
+        assert!(Value::Int(1) != Value::Float(1.9));
+        assert!(Value::Int(1) == Value::Float(1.0));
+        assert!(Value::Int(97) != Value::Char('a'));
+        assert!(!Value::Float(std::f64::NAN).equals(&Value::Float(std::f64::NAN)));
+        assert!(Value::from_bigint(BigInt::from_f64(1e19)) == Value::Float(1e19));
+        assert!(Value::Int(i64::MAX) != Value::Float(9223372036854775807.0));
+    }
+
+    #[test]
+    fn cyclic_values_compare_and_hash() {
+        let (a, b) = (object(vec![]), object(vec![]));
+        for x in &[&a, &b] {
+            let object = x.to_object().unwrap();
+            object
+                .borrow_mut()
+                .set(Value::String(Ref("self".to_owned())), (*x).clone());
+        }
+        assert!(a == b);
+        assert_eq!(hash(&a), hash(&b));
+    }
+
+    #[test]
+    fn identity_is_by_reference() {
+        let a = Value::Array(Ref(vec![]));
+        assert!(a.same(&a.clone()));
+        assert!(!a.same(&Value::Array(Ref(vec![]))));
+        assert!(Value::Int(1).same(&Value::Int(1)));
+        assert!(!Value::Int(1).same(&Value::Float(1.0)));
+    }
+
+    #[test]
+    fn equal_values_hash_equally() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..5000 {
+            let x = value(&mut rng, 3);
+            let y = if rng.gen() {
+                copy(&x)
+            } else {
+                value(&mut rng, 3)
+            };
+            assert!(x == x, "{}", x);
+            assert_eq!(x == y, y == x, "{} {}", x, y);
+            assert!(!x.equals(&y) || x == y, "{} {}", x, y);
+            if x == y {
+                assert_eq!(hash(&x), hash(&y), "{} {}", x, y);
+            }
+        }
+    }
+}
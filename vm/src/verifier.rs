@@ -0,0 +1,601 @@
+//! Bytecode verifier.
+//!
+//! Abstractly interprets the code of a module, tracking only the depth of the
+//! operand stack. Every instruction must be reached with the same depth on
+//! every path, no instruction may pop more values than there are, `Ret` must
+//! leave nothing but the return value, every index stored in an instruction
+//! must refer to something that exists and every instruction must be one the
+//! interpreter can execute.
+
+use crate::opcode::Op;
+use crate::value::Value;
+use crate::*;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::rc::Rc;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerifyError {
+    StackUnderflow {
+        at: usize,
+        depth: usize,
+        needed: usize,
+    },
+    DepthMismatch {
+        at: usize,
+        expected: usize,
+        found: usize,
+    },
+    InvalidJump {
+        at: usize,
+        to: u32,
+    },
+    InvalidGlobal {
+        at: usize,
+        index: u32,
+    },
+    InvalidEnv {
+        at: usize,
+        index: u16,
+        size: usize,
+    },
+    InvalidFunction {
+        global: usize,
+        address: usize,
+    },
+    InvalidFunctionEnv {
+        global: usize,
+    },
+    InvalidNestedFunction {
+        address: usize,
+    },
+    InvalidNestedFunctionEnv {
+        address: usize,
+    },
+    InvalidMakeEnv {
+        at: usize,
+    },
+    ReturnDepth {
+        at: usize,
+        depth: usize,
+    },
+    UnsupportedOp {
+        at: usize,
+    },
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VerifyError::StackUnderflow { at, depth, needed } => write!(
+                f,
+                "{:04}: stack underflow, {} values needed but stack depth is {}",
+                at, needed, depth
+            ),
+            VerifyError::DepthMismatch {
+                at,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{:04}: reached with stack depth {} but {} on another path",
+                at, found, expected
+            ),
+            VerifyError::InvalidJump { at, to } => {
+                write!(f, "{:04}: jump target {} out of range", at, to)
+            }
+            VerifyError::InvalidGlobal { at, index } => {
+                write!(f, "{:04}: global index {} out of range", at, index)
+            }
+            VerifyError::InvalidEnv { at, index, size } => write!(
+                f,
+                "{:04}: env index {} out of range, function env has {} values",
+                at, index, size
+            ),
+            VerifyError::InvalidFunction { global, address } => write!(
+                f,
+                "function in global {} starts outside of code at {}",
+                global, address
+            ),
+            VerifyError::InvalidFunctionEnv { global } => {
+                write!(
+                    f,
+                    "function in global {} has an env that is not an array",
+                    global
+                )
+            }
+            VerifyError::InvalidNestedFunction { address } => write!(
+                f,
+                "function in a constant starts outside of code at {}",
+                address
+            ),
+            VerifyError::InvalidNestedFunctionEnv { address } => write!(
+                f,
+                "function at {} in a constant has an env that is not an array",
+                address
+            ),
+            VerifyError::InvalidMakeEnv { at } => write!(
+                f,
+                "{:04}: MakeEnv must directly follow LoadGlobal of a script function",
+                at
+            ),
+            VerifyError::ReturnDepth { at, depth } => write!(
+                f,
+                "{:04}: return with stack depth {}, expected only the return value",
+                at, depth
+            ),
+            VerifyError::UnsupportedOp { at } => {
+                write!(f, "{:04}: opcode the interpreter can't execute", at)
+            }
+        }
+    }
+}
+
+/// Number of values `op` pops from and pushes to the stack.
+pub fn stack_effect(op: &Op) -> (usize, usize) {
+    match op {
+        Op::LoadNull
+        | Op::LoadTrue
+        | Op::LoadFalse
+        | Op::LoadInt(_)
+        | Op::LoadGlobal(_)
+        | Op::LoadEnv(_)
+        | Op::LoadLocal(_)
+        | Op::LoadBuiltin(_)
+        | Op::LoadThis => (0, 1),
+        Op::Load => (2, 1),
+        Op::Store => (3, 0),
+        Op::StoreEnv(_) | Op::StoreLocal(_) | Op::StoreGlobal(_) | Op::StoreThis => (1, 0),
+        Op::Pop(n) => (*n as usize, 0),
+        Op::Call(n) | Op::TailCall(n) => (*n as usize + 1, 1),
+        Op::ObjCall(n) => (*n as usize + 2, 1),
+        Op::MakeEnv(n) => (*n as usize + 1, 1),
+        Op::MakeArray(n) => (*n as usize, 1),
+        Op::JumpIf(_) | Op::JumpIfNot(_) | Op::Throw | Op::Ret => (1, 0),
+        Op::Jump(_) | Op::CatchPush(_) | Op::CatchPop | Op::Nop | Op::Last => (0, 0),
+        Op::IsNull | Op::IsNotNull | Op::Not | Op::Neg | Op::BitNot | Op::Hash | Op::New => (1, 1),
+        Op::Dup => (1, 2),
+        Op::Add
+        | Op::Sub
+        | Op::Div
+        | Op::Mul
+        | Op::Mod
+        | Op::Shl
+        | Op::Shr
+        | Op::UShr
+        | Op::Or
+        | Op::And
+        | Op::Xor
+        | Op::Eq
+        | Op::Neq
+        | Op::Same
+        | Op::NotSame
+        | Op::Gt
+        | Op::Gte
+        | Op::Lt
+        | Op::Lte => (2, 1),
+    }
+}
+
+fn address<T>(value: &Ref<T>) -> usize {
+    Rc::as_ptr(value) as *const u8 as usize
+}
+
+/// Functions stored in array, object and env constants by address, in the
+/// order they were found. Code can reach and call them without going through
+/// `MakeEnv`.
+pub(crate) fn nested_functions(m: &Module) -> Vec<(usize, Ref<value::Function>)> {
+    fn walk(
+        value: &Value,
+        seen: &mut HashSet<usize>,
+        found: &mut Vec<(usize, Ref<value::Function>)>,
+    ) {
+        match value {
+            Value::Array(array) if seen.insert(address(array)) => {
+                for item in array.borrow().iter() {
+                    walk(item, seen, found);
+                }
+            }
+            Value::Object(object) if seen.insert(address(object)) => {
+                let object = object.borrow();
+                if let Some(prototype) = &object.prototype {
+                    walk(&Value::Object(prototype.clone()), seen, found);
+                }
+                for (key, value) in object.table.iter() {
+                    walk(key, seen, found);
+                    walk(value, seen, found);
+                }
+            }
+            Value::Function(f) if seen.insert(address(f)) => {
+                found.push((address(f), f.clone()));
+                walk(&f.borrow().env, seen, found)
+            }
+            _ => (),
+        }
+    }
+    let (mut seen, mut found) = (HashSet::new(), vec![]);
+    for global in m.globals.iter() {
+        match global {
+            Value::Function(f) => walk(&f.borrow().env, &mut seen, &mut found),
+            global => walk(global, &mut seen, &mut found),
+        }
+    }
+    found
+}
+
+/// Smallest env each function global can run with. Closures are created by
+/// `LoadGlobal(function); MakeEnv(count)`, which appends `count` values to the
+/// function's env constant. A function that is loaded in any other way may be
+/// called with only its env constant.
+fn env_sizes(m: &Module) -> HashMap<usize, usize> {
+    // Fewest values added by `MakeEnv`, `None` when loaded without it.
+    let mut added: HashMap<usize, Option<usize>> = HashMap::new();
+    for (at, op) in m.code.iter().enumerate() {
+        if let Op::LoadGlobal(g) = op {
+            let count = match m.code.get(at + 1) {
+                Some(Op::MakeEnv(count)) => Some(*count as usize),
+                _ => None,
+            };
+            let entry = added.entry(*g as usize).or_insert(count);
+            *entry = entry.and_then(|x| Some(x.min(count?)));
+        }
+    }
+    let nested = nested_functions(m)
+        .into_iter()
+        .map(|(address, _)| address)
+        .collect::<HashSet<_>>();
+    let mut sizes = HashMap::new();
+    for (i, global) in m.globals.iter().enumerate() {
+        if let Value::Function(f) = global {
+            let declared = match &f.borrow().env {
+                Value::Array(env) => env.borrow().len(),
+                _ => continue,
+            };
+            let added = match nested.contains(&address(f)) {
+                true => 0,
+                false => added.get(&i).cloned().flatten().unwrap_or(0),
+            };
+            sizes.insert(i, declared + added);
+        }
+    }
+    sizes
+}
+
+/// Check that every `MakeEnv` gets a script function from a global that code
+/// never overwrites, and that no jump lands between the two instructions.
+fn check_make_env(m: &Module) -> Result<(), VerifyError> {
+    let mut targets = HashSet::new();
+    let mut stored = HashSet::new();
+    for op in m.code.iter() {
+        match op {
+            Op::Jump(to) | Op::JumpIf(to) | Op::JumpIfNot(to) | Op::CatchPush(to) => {
+                targets.insert(*to as usize);
+            }
+            Op::StoreGlobal(g) => {
+                stored.insert(*g as usize);
+            }
+            _ => (),
+        }
+    }
+    for (at, op) in m.code.iter().enumerate() {
+        if let Op::MakeEnv(_) = op {
+            let global = match at.checked_sub(1).map(|at| &m.code[at]) {
+                Some(Op::LoadGlobal(g))
+                    if !targets.contains(&at) && !stored.contains(&(*g as usize)) =>
+                {
+                    m.globals.get(*g as usize)
+                }
+                _ => None,
+            };
+            match global {
+                Some(Value::Function(f)) if !f.borrow().native => (),
+                _ => return Err(VerifyError::InvalidMakeEnv { at }),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Verify module code. The module entry point at address 0 and every function
+/// in a global or nested in a constant start with an empty stack.
+pub fn verify(m: &Module) -> Result<(), VerifyError> {
+    let len = m.code.len();
+    check_make_env(m)?;
+    let sizes = env_sizes(m);
+    let mut entries = vec![(0, 0)];
+    for (i, global) in m.globals.iter().enumerate() {
+        if let Value::Function(f) = global {
+            let f = f.borrow();
+            if f.native {
+                continue;
+            }
+            if f.address >= len {
+                return Err(VerifyError::InvalidFunction {
+                    global: i,
+                    address: f.address,
+                });
+            }
+            if !matches!(f.env, Value::Array(_)) {
+                return Err(VerifyError::InvalidFunctionEnv { global: i });
+            }
+            entries.push((f.address, sizes.get(&i).cloned().unwrap_or(0)));
+        }
+    }
+    // Functions in constants run with just their env constant.
+    for (_, f) in nested_functions(m) {
+        let f = f.borrow();
+        if f.native {
+            continue;
+        }
+        if f.address >= len {
+            return Err(VerifyError::InvalidNestedFunction { address: f.address });
+        }
+        match &f.env {
+            Value::Array(env) => entries.push((f.address, env.borrow().len())),
+            _ => return Err(VerifyError::InvalidNestedFunctionEnv { address: f.address }),
+        }
+    }
+    // Code shared by several functions is only walked once, with the smallest env.
+    entries.sort_by_key(|(_, env)| *env);
+
+    let mut depths: Vec<Option<usize>> = vec![None; len];
+    for (start, env) in entries {
+        let mut worklist = vec![(start, 0)];
+        while let Some((at, depth)) = worklist.pop() {
+            // Running off the end of code stops the interpreter.
+            if at >= len {
+                continue;
+            }
+            match depths[at] {
+                Some(expected) if expected == depth => continue,
+                Some(expected) => {
+                    return Err(VerifyError::DepthMismatch {
+                        at,
+                        expected,
+                        found: depth,
+                    })
+                }
+                None => depths[at] = Some(depth),
+            }
+            let op = &m.code[at];
+            let (pops, pushes) = stack_effect(op);
+            if depth < pops {
+                return Err(VerifyError::StackUnderflow {
+                    at,
+                    depth,
+                    needed: pops,
+                });
+            }
+            let next = depth - pops + pushes;
+            match op {
+                Op::Jump(to) | Op::JumpIf(to) | Op::JumpIfNot(to) | Op::CatchPush(to) => {
+                    if *to as usize > len {
+                        return Err(VerifyError::InvalidJump { at, to: *to });
+                    }
+                }
+                Op::LoadGlobal(index) | Op::StoreGlobal(index)
+                    if *index as usize >= m.globals.len() =>
+                {
+                    return Err(VerifyError::InvalidGlobal { at, index: *index });
+                }
+                Op::LoadEnv(index) | Op::StoreEnv(index) if *index as usize >= env => {
+                    return Err(VerifyError::InvalidEnv {
+                        at,
+                        index: *index,
+                        size: env,
+                    });
+                }
+                Op::Ret if depth != 1 => return Err(VerifyError::ReturnDepth { at, depth }),
+                Op::Hash => return Err(VerifyError::UnsupportedOp { at }),
+                _ => (),
+            }
+            match op {
+                Op::Jump(to) => worklist.push((*to as usize, next)),
+                Op::JumpIf(to) | Op::JumpIfNot(to) => {
+                    worklist.push((*to as usize, next));
+                    worklist.push((at + 1, next));
+                }
+                // The handler starts with the exception pushed.
+                Op::CatchPush(to) => {
+                    worklist.push((*to as usize, next + 1));
+                    worklist.push((at + 1, next));
+                }
+                Op::Ret | Op::Throw | Op::Last => (),
+                _ => worklist.push((at + 1, next)),
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn module(code: Vec<Op>, globals: Vec<Value>) -> Module {
+        Module {
+            exports: Value::Null,
+            code,
+            globals,
+            trace_info: HashMap::new(),
+        }
+    }
+
+    fn function(address: usize) -> Value {
+        Value::Function(Ref(value::Function {
+            native: false,
+            address,
+            env: Value::Array(Ref(vec![])),
+            module: None,
+            argc: 0,
+            calls: Default::default(),
+            bound: None,
+        }))
+    }
+
+    #[test]
+    fn accept_balanced_code() {
+        // if true { 1 } else { 2 }
+        let code = vec![
+            Op::LoadTrue,
+            Op::JumpIfNot(4),
+            Op::LoadInt(1),
+            Op::Jump(5),
+            Op::LoadInt(2),
+            Op::Ret,
+        ];
+        assert_eq!(verify(&module(code, vec![])), Ok(()));
+    }
+
+    #[test]
+    fn reject_underflow_and_unbalanced_merge() {
+        assert_eq!(
+            verify(&module(vec![Op::LoadInt(1), Op::Add, Op::Ret], vec![])),
+            Err(VerifyError::StackUnderflow {
+                at: 1,
+                depth: 1,
+                needed: 2
+            })
+        );
+        // `if` without else leaves a value on only one path.
+        let code = vec![Op::LoadTrue, Op::JumpIfNot(3), Op::LoadInt(1), Op::Ret];
+        assert_eq!(
+            verify(&module(code, vec![])),
+            Err(VerifyError::DepthMismatch {
+                at: 3,
+                expected: 1,
+                found: 0
+            })
+        );
+    }
+
+    #[test]
+    fn check_catch_handler_and_env() {
+        let code = vec![
+            Op::CatchPush(3),
+            Op::LoadNull,
+            Op::Throw,
+            Op::Ret,
+            Op::LoadEnv(0),
+            Op::Ret,
+        ];
+        assert_eq!(verify(&module(code.clone(), vec![])), Ok(()));
+        assert_eq!(
+            verify(&module(code, vec![function(4)])),
+            Err(VerifyError::InvalidEnv {
+                at: 4,
+                index: 0,
+                size: 0
+            })
+        );
+        let code = vec![
+            Op::LoadNull,
+            Op::LoadGlobal(0),
+            Op::MakeEnv(1),
+            Op::Ret,
+            Op::LoadEnv(0),
+            Op::Ret,
+        ];
+        assert_eq!(verify(&module(code, vec![function(4)])), Ok(()));
+    }
+
+    #[test]
+    fn use_the_smallest_env_a_function_runs_with() {
+        // The function is also called without `MakeEnv`.
+        let code = vec![
+            Op::LoadNull,
+            Op::LoadGlobal(0),
+            Op::MakeEnv(1),
+            Op::Pop(1),
+            Op::LoadGlobal(0),
+            Op::Call(0),
+            Op::Ret,
+            Op::LoadEnv(0),
+            Op::Ret,
+        ];
+        assert_eq!(
+            verify(&module(code, vec![function(7)])),
+            Err(VerifyError::InvalidEnv {
+                at: 7,
+                index: 0,
+                size: 0
+            })
+        );
+        // Stored in an array constant, so reachable without `MakeEnv`.
+        let code = vec![
+            Op::LoadNull,
+            Op::LoadGlobal(0),
+            Op::MakeEnv(1),
+            Op::Ret,
+            Op::LoadEnv(0),
+            Op::Ret,
+        ];
+        let f = function(4);
+        let array = Value::Array(Ref(vec![f.clone()]));
+        assert!(verify(&module(code.clone(), vec![f, array])).is_err());
+        // The env constant counts.
+        let f = function(2);
+        if let Value::Function(f) = &f {
+            f.borrow_mut().env = Value::Array(Ref(vec![Value::Null]));
+        }
+        let code = vec![Op::LoadNull, Op::Ret, Op::LoadEnv(0), Op::Ret];
+        assert_eq!(verify(&module(code, vec![f])), Ok(()));
+    }
+
+    #[test]
+    fn reject_bad_make_env_and_function_env() {
+        let code = vec![Op::LoadNull, Op::LoadInt(1), Op::MakeEnv(1), Op::Ret];
+        assert_eq!(
+            verify(&module(code, vec![])),
+            Err(VerifyError::InvalidMakeEnv { at: 2 })
+        );
+        let code = vec![Op::LoadNull, Op::LoadGlobal(0), Op::MakeEnv(1), Op::Ret];
+        assert_eq!(
+            verify(&module(code, vec![Value::Int(1)])),
+            Err(VerifyError::InvalidMakeEnv { at: 2 })
+        );
+        let f = function(0);
+        if let Value::Function(f) = &f {
+            f.borrow_mut().env = Value::Null;
+        }
+        assert_eq!(
+            verify(&module(vec![Op::LoadNull, Op::Ret], vec![f])),
+            Err(VerifyError::InvalidFunctionEnv { global: 0 })
+        );
+    }
+
+    #[test]
+    fn reject_extra_values_at_return() {
+        assert_eq!(
+            verify(&module(vec![Op::LoadNull, Op::LoadNull, Op::Ret], vec![])),
+            Err(VerifyError::ReturnDepth { at: 2, depth: 2 })
+        );
+    }
+
+    #[test]
+    fn reject_opcodes_the_interpreter_cannot_execute() {
+        assert_eq!(
+            verify(&module(vec![Op::LoadNull, Op::Hash, Op::Ret], vec![])),
+            Err(VerifyError::UnsupportedOp { at: 1 })
+        );
+    }
+
+    #[test]
+    fn check_functions_nested_in_constants() {
+        // The function is only reachable through the array constant.
+        let code = vec![Op::LoadGlobal(0), Op::Ret, Op::Add, Op::Ret];
+        let nested = |f| vec![Value::Array(Ref(vec![f]))];
+        assert_eq!(
+            verify(&module(code.clone(), nested(function(2)))),
+            Err(VerifyError::StackUnderflow {
+                at: 2,
+                depth: 0,
+                needed: 2
+            })
+        );
+        assert_eq!(
+            verify(&module(code, nested(function(7)))),
+            Err(VerifyError::InvalidNestedFunction { address: 7 })
+        );
+    }
+}
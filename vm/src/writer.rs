@@ -3,9 +3,157 @@ use byteorder::{LittleEndian, WriteBytesExt};
 use value::*;
 
 use crate::opcode::Op;
-use crate::reader::{TAG_FLOAT, TAG_FUN, TAG_STRING};
-use crate::value::{Function, ValTag};
+use crate::reader::{
+    checksum, FLAG_DBGINFO, HEADER_SIZE, MAGIC, NO_PROTOTYPE, TAG_ARRAY, TAG_BIGINT, TAG_BOOL,
+    TAG_CHAR, TAG_FLOAT, TAG_FUN, TAG_INT, TAG_NULL, TAG_OBJECT, TAG_STRING, VERSION,
+};
+use crate::value::Function;
 use hashlink::LinkedHashMap;
+use std::collections::HashMap;
+
+/// Entry of the constant pool, composite constants refer to other entries by index.
+pub enum Constant {
+    Null,
+    Bool(bool),
+    Int(i64),
+    BigInt(Rc<bigint::BigInt>),
+    Float(f64),
+    Char(char),
+    String(String),
+    Array(Vec<u32>),
+    Object(Option<u32>, Vec<(u32, u32)>),
+    Function(Ref<Function>, u32),
+}
+
+/// Every value reachable from module globals, each heap value stored once so
+/// that sharing and cycles survive serialization.
+#[derive(Default)]
+pub struct ConstantPool {
+    pub constants: Vec<Constant>,
+    /// Indices of globals in `constants`.
+    pub globals: Vec<u32>,
+    seen: HashMap<usize, u32>,
+}
+
+impl ConstantPool {
+    pub fn new(m: &Module) -> Self {
+        let mut pool = Self::default();
+        for value in m.globals.iter() {
+            let idx = pool.add(value);
+            pool.globals.push(idx);
+        }
+        pool
+    }
+
+    fn reserve(&mut self, ptr: usize) -> Result<u32, u32> {
+        if let Some(idx) = self.seen.get(&ptr) {
+            return Err(*idx);
+        }
+        let idx = self.constants.len() as u32;
+        self.seen.insert(ptr, idx);
+        self.constants.push(Constant::Null);
+        Ok(idx)
+    }
+
+    pub fn add(&mut self, value: &Value) -> u32 {
+        let constant = match value {
+            Value::Null => Constant::Null,
+            Value::Bool(x) => Constant::Bool(*x),
+            Value::Int(x) => Constant::Int(*x),
+            Value::BigInt(x) => Constant::BigInt(x.clone()),
+            Value::Float(x) => Constant::Float(*x),
+            Value::Char(x) => Constant::Char(*x),
+            Value::String(s) => {
+                let idx = match self.reserve(Rc::as_ptr(s) as *const u8 as usize) {
+                    Ok(idx) => idx,
+                    Err(idx) => return idx,
+                };
+                self.constants[idx as usize] = Constant::String(s.borrow().clone());
+                return idx;
+            }
+            Value::Array(array) => {
+                let idx = match self.reserve(Rc::as_ptr(array) as *const u8 as usize) {
+                    Ok(idx) => idx,
+                    Err(idx) => return idx,
+                };
+                let items = array.borrow().iter().map(|x| self.add(x)).collect();
+                self.constants[idx as usize] = Constant::Array(items);
+                return idx;
+            }
+            Value::Object(object) => return self.add_object(object),
+            Value::Function(f) => {
+                assert!(
+                    !f.borrow().native,
+                    "native functions can't be stored in bytecode"
+                );
+                let idx = match self.reserve(Rc::as_ptr(f) as *const u8 as usize) {
+                    Ok(idx) => idx,
+                    Err(idx) => return idx,
+                };
+                let env = self.add(&f.borrow().env);
+                self.constants[idx as usize] = Constant::Function(f.clone(), env);
+                return idx;
+            }
+            Value::User(x) => panic!(
+                "value of type {} can't be stored in bytecode",
+                x.borrow().get_kind()
+            ),
+        };
+        self.constants.push(constant);
+        self.constants.len() as u32 - 1
+    }
+
+    fn add_object(&mut self, object: &Ref<Object>) -> u32 {
+        let idx = match self.reserve(Rc::as_ptr(object) as *const u8 as usize) {
+            Ok(idx) => idx,
+            Err(idx) => return idx,
+        };
+        let object = object.borrow();
+        let prototype = object.prototype.as_ref().map(|p| self.add_object(p));
+        let table = object
+            .table
+            .iter()
+            .map(|(key, value)| (self.add(key), self.add(value)))
+            .collect();
+        self.constants[idx as usize] = Constant::Object(prototype, table);
+        idx
+    }
+}
+
+/// Strings stored in the string table of a `.j` file: string constants,
+/// builtin names and file names from debug information, each with its index.
+pub fn string_table(m: &Module) -> LinkedHashMap<String, u32> {
+    strings_of(m, &ConstantPool::new(m))
+}
+
+fn strings_of(m: &Module, pool: &ConstantPool) -> LinkedHashMap<String, u32> {
+    let mut strings = LinkedHashMap::new();
+    let mut add = |s: &str| {
+        if !strings.contains_key(s) {
+            let idx = strings.len() as u32;
+            strings.insert(s.to_owned(), idx);
+        }
+    };
+    for constant in pool.constants.iter() {
+        if let Constant::String(s) = constant {
+            add(s);
+        }
+    }
+    for op in m.code.iter() {
+        if let Op::LoadBuiltin(name) = op {
+            add(name);
+        }
+    }
+    if !m.trace_info.is_empty() {
+        for pc in 0..m.code.len() {
+            match m.trace_info.get(&(pc as u32)) {
+                Some((_, file)) => add(file),
+                None => add(""),
+            }
+        }
+    }
+    strings
+}
 
 pub struct BytecodeWriter {
     pub bytecode: Vec<u8>,
@@ -26,55 +174,98 @@ impl BytecodeWriter {
     }
 
     pub fn write_module(&mut self, m: Ref<Module>) {
-        let mut strings = LinkedHashMap::new();
-        let mut i = 0;
-        for value in m.borrow().globals.iter() {
-            if let Value::String(s) = value {
-                strings.insert(s.borrow().clone(), i);
-                i += 1;
-            }
-        }
-        let mut globals = vec![];
-        for value in m.borrow().globals.iter() {
-            match value.tag() {
-                ValTag::Func | ValTag::Str | ValTag::Float => globals.push(value.clone()),
+        let pool = ConstantPool::new(&m.borrow());
+        let strings = strings_of(&m.borrow(), &pool);
+        let has_dbginfo = !m.borrow().trace_info.is_empty();
 
-                _ => (), // TODO: Add more values to globals
-            }
-        }
+        let start = self.bytecode.len();
+        self.bytecode.extend_from_slice(&MAGIC);
+        self.write_u16(VERSION);
+        self.write_u16(if has_dbginfo { FLAG_DBGINFO } else { 0 });
+        self.write_u32(0);
 
         self.write_u32(strings.len() as _);
-        self.write_u32(globals.len() as _);
+        self.write_u32(pool.constants.len() as _);
+        self.write_u32(pool.globals.len() as _);
         self.write_u32(m.borrow().code.len() as _);
-        self.write_u8(0);
         for (string, _) in strings.iter() {
             self.write_u32(string.len() as _);
             for byte in string.as_bytes() {
                 self.write_u8(*byte);
             }
         }
+        if has_dbginfo {
+            let m = m.borrow();
+            for pc in 0..m.code.len() {
+                let (line, file) = m
+                    .trace_info
+                    .get(&(pc as u32))
+                    .map(|(line, file)| (*line, file.as_str()))
+                    .unwrap_or((0, ""));
+                self.write_u32(line as _);
+                self.write_u32(strings[file]);
+            }
+        }
 
-        for i in 0..globals.len() {
-            let global = globals[i].clone();
-            match global {
-                Value::String(s) => {
-                    self.write_u8(TAG_STRING);
-                    let idx = strings.get(&*s.borrow()).unwrap();
-                    self.write_u32(*idx as _);
+        for constant in pool.constants.iter() {
+            match constant {
+                Constant::Null => self.write_u8(TAG_NULL),
+                Constant::Bool(x) => {
+                    self.write_u8(TAG_BOOL);
+                    self.write_u8(*x as u8);
+                }
+                Constant::Int(x) => {
+                    self.write_u8(TAG_INT);
+                    self.write_u64(*x as u64);
+                }
+                Constant::BigInt(x) => {
+                    self.write_u8(TAG_BIGINT);
+                    self.write_u8(x.negative() as u8);
+                    self.write_u32(x.limbs().len() as _);
+                    for limb in x.limbs() {
+                        self.write_u32(*limb);
+                    }
                 }
-                Value::Float(x) => {
+                Constant::Float(x) => {
                     self.write_u8(TAG_FLOAT);
                     self.write_u64(x.to_bits());
                 }
-                Value::Function(f) => {
+                Constant::Char(x) => {
+                    self.write_u8(TAG_CHAR);
+                    self.write_u32(*x as u32);
+                }
+                Constant::String(s) => {
+                    self.write_u8(TAG_STRING);
+                    self.write_u32(strings[s]);
+                }
+                Constant::Array(items) => {
+                    self.write_u8(TAG_ARRAY);
+                    self.write_u32(items.len() as _);
+                    for item in items.iter() {
+                        self.write_u32(*item);
+                    }
+                }
+                Constant::Object(prototype, table) => {
+                    self.write_u8(TAG_OBJECT);
+                    self.write_u32(prototype.unwrap_or(NO_PROTOTYPE));
+                    self.write_u32(table.len() as _);
+                    for (key, value) in table.iter() {
+                        self.write_u32(*key);
+                        self.write_u32(*value);
+                    }
+                }
+                Constant::Function(f, env) => {
                     let f: &Function = &f.borrow();
                     self.write_u8(TAG_FUN);
                     self.write_u32(f.address as u32);
-                    self.write_u16(f.argc as _);
+                    self.write_u32(f.argc as u32);
+                    self.write_u32(*env);
                 }
-                _ => (),
             }
         }
+        for idx in pool.globals.iter() {
+            self.write_u32(*idx);
+        }
 
         for i in 0..m.borrow().code.len() {
             let op = m.borrow().code[i].clone();
@@ -100,8 +291,7 @@ impl BytecodeWriter {
                 }
                 Op::LoadBuiltin(name) => {
                     self.write_u8(7);
-                    let idx = strings.get(&name).unwrap();
-                    self.write_u32(*idx as _);
+                    self.write_u32(strings[&name]);
                 }
                 Op::LoadThis => self.write_u8(8),
                 Op::Load => self.write_u8(9),
@@ -182,7 +372,19 @@ impl BytecodeWriter {
                 Op::New => self.write_u8(48),
                 Op::Nop => self.write_u8(49),
                 Op::Last => self.write_u8(50),
+                Op::Dup => self.write_u8(51),
+                Op::BitNot => self.write_u8(52),
+                Op::CatchPop => self.write_u8(53),
+                Op::StoreGlobal(idx) => {
+                    self.write_u8(54);
+                    self.write_u32(idx);
+                }
+                Op::Same => self.write_u8(55),
+                Op::NotSame => self.write_u8(56),
             }
         }
+
+        let sum = checksum(&self.bytecode[start + HEADER_SIZE..]);
+        self.bytecode[start + 8..start + HEADER_SIZE].copy_from_slice(&sum.to_le_bytes());
     }
 }